@@ -63,7 +63,7 @@ fi
 %{_bindir}/lwl-x6-keyboard
 %{_datadir}/applications/rusty-kb.desktop
 %{_datadir}/icons/hicolor/256x256/apps/rusty-kb.png
-/usr/lib/rusty-kb/setcolor.sh
 /usr/lib/systemd/user/rusty-kb.service
 /etc/udev/rules.d/99-rusty-kb.rules
-/usr/lib/rusty-kb/colors.txt
+/usr/lib/rusty-kb/config.toml
+%{_datadir}/dbus-1/services/com.example.RustyKb.service
@@ -0,0 +1,362 @@
+// Auto brightness from an ambient light sensor. The illuminance an IIO sensor
+// reports is mapped through the `[auto_brightness]` curve to a percentage of
+// the keyboard's and lightbar's maximum brightness, which the daemon's
+// brightness workers write and the config keeps like any other brightness.
+// Readings are smoothed, and the LEDs only follow once the target moved by the
+// hysteresis, so a passing shadow or a flickering lamp doesn't make them pump.
+
+use crate::backend::LedBackend;
+use crate::config::AutoBrightnessConfig;
+use crate::idle::unless_dimmed;
+use crate::leds::{pick_primary, read_max_brightness};
+use crate::persist::{runtime_path, write_atomically, Brightness, SharedConfig};
+use crate::workers::{notify_coalescer, Targets};
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// `RUSTY_KB_IIO_ROOT=/tmp/iio` looks for the sensor in a fake tree laid out
+// like /sys/bus/iio/devices (a folder per device with in_illuminance_input or
+// in_illuminance_raw and in_illuminance_scale).
+const IIO_ROOT_ENV: &str = "RUSTY_KB_IIO_ROOT";
+const DEFAULT_IIO_ROOT: &str = "/sys/bus/iio/devices";
+pub const AMBIENT_POLL: Duration = Duration::from_secs(1);
+const AUTO_SAVE_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+pub struct LightSensor {
+    pub device: PathBuf,
+    value: PathBuf,
+    scale: f64,
+    offset: f64,
+}
+
+fn read_number(path: &Path) -> io::Result<f64> {
+    fs::read_to_string(path)?
+        .trim()
+        .parse()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+impl LightSensor {
+    // Channels are `in_illuminance_*` or numbered like `in_illuminance0_*`. A
+    // processed `_input` is in lux already, `_raw` needs its scale and offset.
+    fn open(device: &Path) -> Option<Self> {
+        let mut names: Vec<String> = fs::read_dir(device)
+            .ok()?
+            .filter_map(|e| e.ok()?.file_name().into_string().ok())
+            .filter(|n| n.starts_with("in_illuminance"))
+            .collect();
+        names.sort();
+        let channel = |suffix: &str| names.iter().find(|n| n.ends_with(suffix)).cloned();
+        if let Some(input) = channel("_input") {
+            return Some(LightSensor { device: device.to_path_buf(), value: device.join(input), scale: 1.0, offset: 0.0 });
+        }
+        let raw = channel("_raw")?;
+        let prefix = raw.strip_suffix("_raw").unwrap_or(&raw);
+        let attr = |name: &str| {
+            read_number(&device.join(format!("{}_{}", prefix, name)))
+                .or_else(|_| read_number(&device.join(format!("in_illuminance_{}", name))))
+                .ok()
+        };
+        Some(LightSensor {
+            device: device.to_path_buf(),
+            value: device.join(&raw),
+            scale: attr("scale").unwrap_or(1.0),
+            offset: attr("offset").unwrap_or(0.0),
+        })
+    }
+
+    pub fn lux(&self) -> io::Result<f64> {
+        Ok(((read_number(&self.value)? + self.offset) * self.scale).max(0.0))
+    }
+
+    /// The driver's name for the sensor, e.g. "als" or "acpi-als".
+    pub fn name(&self) -> String {
+        fs::read_to_string(self.device.join("name")).map_or_else(|_| "unknown".to_string(), |n| n.trim().to_string())
+    }
+}
+
+/// The first IIO device that measures illuminance.
+pub fn find_light_sensor() -> Result<LightSensor, String> {
+    let root = PathBuf::from(env::var(IIO_ROOT_ENV).unwrap_or_else(|_| DEFAULT_IIO_ROOT.to_string()));
+    let mut devices: Vec<PathBuf> = fs::read_dir(&root)
+        .map_err(|e| format!("{}: {}", root.display(), e))?
+        .filter_map(|e| Some(e.ok()?.path()))
+        .collect();
+    devices.sort();
+    devices
+        .iter()
+        .find_map(|device| LightSensor::open(device))
+        .ok_or_else(|| format!("no ambient light sensor under {}", root.display()))
+}
+
+/// The brightness in percent for `lux`, interpolated linearly between the
+/// points of `curve` and held at its ends.
+pub fn curve_percent(curve: &[(f64, u8)], lux: f64) -> Option<f64> {
+    let (first, last) = (curve.first()?, curve.last()?);
+    if lux <= first.0 {
+        return Some(f64::from(first.1));
+    }
+    if lux >= last.0 {
+        return Some(f64::from(last.1));
+    }
+    curve.windows(2).find(|pair| lux <= pair[1].0).map(|pair| {
+        let ((x0, y0), (x1, y1)) = (pair[0], pair[1]);
+        f64::from(y0) + (f64::from(y1) - f64::from(y0)) * (lux - x0) / (x1 - x0)
+    })
+}
+
+/// A brightness worker of the daemon and the LEDs it writes to.
+pub struct BrightnessOutput {
+    pub latest: Arc<AtomicI32>,
+    pub tx: mpsc::SyncSender<()>,
+    pub targets: Targets,
+    pub default_max: i32,
+}
+
+impl BrightnessOutput {
+    // Read on every adjustment, the LEDs come and go with hotplug. None while
+    // there are none.
+    fn max(&self, backend: &dyn LedBackend) -> Option<i32> {
+        let primary = pick_primary(&self.targets.lock().unwrap())?;
+        Some(read_max_brightness(backend, &primary).unwrap_or(self.default_max))
+    }
+
+    fn set(&self, brightness: Brightness) {
+        self.latest.store(brightness.value, Ordering::Relaxed);
+        notify_coalescer(&self.tx);
+    }
+}
+
+// The levels last written, one `<brightness> <led>` line per LED. The level is
+// only saved now and then, and until it is the Fn-key watchers of the window
+// (and of the daemon for the lightbar) would take it for a key press.
+fn auto_levels_path() -> Option<PathBuf> {
+    runtime_path("auto-levels")
+}
+
+fn record_auto_levels(levels: &[(PathBuf, i32)]) {
+    let Some(path) = auto_levels_path() else { return };
+    let lines: String = levels.iter().map(|(led, value)| format!("{} {}\n", value, led.display())).collect();
+    if let Err(e) = write_atomically(&path, &lines) {
+        eprintln!("Error: {}: {}", path.display(), e);
+    }
+}
+
+fn clear_auto_levels() {
+    if let Some(path) = auto_levels_path() {
+        let _ = fs::remove_file(path);
+    }
+}
+
+/// The brightness auto brightness last wrote to `led`, by any process.
+pub fn auto_level(led: &Path) -> Option<i32> {
+    let content = fs::read_to_string(auto_levels_path()?).ok()?;
+    content.lines().find_map(|line| {
+        let (value, path) = line.split_once(' ')?;
+        (Path::new(path) == led).then(|| value.parse().ok()).flatten()
+    })
+}
+
+// Writes `percent` to the LEDs and the config, keyboard first. The config is
+// only changed in memory; the caller decides when it is saved.
+fn apply(
+    backend: &dyn LedBackend,
+    settings: &AutoBrightnessConfig,
+    keyboard: Option<&BrightnessOutput>,
+    lightbar: Option<&BrightnessOutput>,
+    config: &SharedConfig,
+    percent: f64,
+) {
+    let level = |out| Some((out, Brightness::from_percent(percent, BrightnessOutput::max(out, backend)?)));
+    let kb = keyboard.and_then(level);
+    let lb = lightbar.filter(|_| settings.lightbar).and_then(level);
+    // The config first, so the Fn-key watcher doesn't take the new LED values
+    // for a change of its own.
+    {
+        let mut c = config.lock().unwrap();
+        if let Some((_, b)) = kb {
+            c.keyboard.brightness = b.value;
+            c.keyboard.max_brightness = b.max;
+        }
+        if let (Some((_, b)), Some(config)) = (lb, c.lightbar.as_mut()) {
+            config.brightness = b.value;
+            config.max_brightness = b.max;
+        }
+    }
+    let mut levels = Vec::new();
+    for (out, b) in kb.into_iter().chain(lb) {
+        levels.extend(out.targets.lock().unwrap().iter().map(|led| (led.clone(), b.value)));
+        out.set(b);
+    }
+    record_auto_levels(&levels);
+}
+
+/// Follows the ambient light sensor with the `[auto_brightness]` settings in
+/// `config` while there is a curve, and calls `on_change` after each adjustment.
+/// The settings are read on every poll, so profiles and edits take effect.
+pub fn spawn_auto_brightness(
+    backend: Arc<dyn LedBackend>,
+    config: SharedConfig,
+    keyboard: Option<BrightnessOutput>,
+    lightbar: Option<BrightnessOutput>,
+    tx_persist: mpsc::SyncSender<()>,
+    on_change: impl Fn() + Send + 'static,
+) {
+    thread::spawn(move || {
+        let mut sensor: Option<LightSensor> = None;
+        let mut announced: Option<PathBuf> = None;
+        let mut smoothed: Option<f64> = None;
+        let mut applied: Option<f64> = None;
+        let mut last_save = Instant::now();
+        let mut unsaved = false;
+        let mut failing = false;
+        loop {
+            let settings = config.lock().unwrap().auto_brightness.clone();
+            if settings.curve.is_empty() {
+                // Turned off: start over from fresh readings when it comes back.
+                if applied.is_some() {
+                    clear_auto_levels();
+                }
+                (smoothed, applied) = (None, None);
+                thread::sleep(AMBIENT_POLL);
+                continue;
+            }
+            let Some(current) = &sensor else {
+                match find_light_sensor() {
+                    Ok(found) => {
+                        // Found again after a read error: reported once is enough.
+                        if announced.as_ref() != Some(&found.device) {
+                            println!("Auto brightness from {} ({})", found.device.display(), found.name());
+                            announced = Some(found.device.clone());
+                        }
+                        sensor = Some(found);
+                        continue;
+                    }
+                    Err(e) if !failing => eprintln!("Error: auto brightness: {}", e),
+                    Err(_) => {}
+                }
+                failing = true;
+                thread::sleep(AMBIENT_POLL);
+                continue;
+            };
+            // Exponential smoothing with `smoothing_seconds` as its time constant.
+            let poll = AMBIENT_POLL.as_secs_f64();
+            let weight = poll / (f64::from(settings.smoothing_seconds) + poll);
+            let ends = settings.curve.iter().map(|(_, percent)| f64::from(*percent));
+            let (lowest, highest) = ends.fold((100.0_f64, 0.0_f64), |(lo, hi), p| (lo.min(p), hi.max(p)));
+            match current.lux() {
+                Ok(lux) => {
+                    failing = false;
+                    let lux = smoothed.map_or(lux, |s| s + (lux - s) * weight);
+                    smoothed = Some(lux);
+                    let target = curve_percent(&settings.curve, lux).unwrap_or(highest);
+                    // The ends of the curve are always reached, however close.
+                    let moved = applied.is_none_or(|a| {
+                        (target - a).abs() >= f64::from(settings.hysteresis)
+                            || ((target == lowest || target == highest) && target != a)
+                    });
+                    let adjust = || apply(backend.as_ref(), &settings, keyboard.as_ref(), lightbar.as_ref(), &config, target);
+                    if moved && unless_dimmed(adjust).is_some() {
+                        applied = Some(target);
+                        unsaved = true;
+                        on_change();
+                    }
+                }
+                // The sensor may be gone for good (e.g. a driver reload gave it
+                // another device number), so it is looked for again.
+                Err(e) => {
+                    if !failing {
+                        eprintln!("Error: reading {}: {}", current.value.display(), e);
+                    }
+                    failing = true;
+                    sensor = None;
+                }
+            }
+            // The level changes with every passing cloud; it is saved now and
+            // then, and by the shutdown flush, rather than on every change.
+            if unsaved && last_save.elapsed() >= AUTO_SAVE_INTERVAL {
+                notify_coalescer(&tx_persist);
+                (unsaved, last_save) = (false, Instant::now());
+            }
+            thread::sleep(AMBIENT_POLL);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp_dir::{runtime_dir, TempDir};
+
+    fn device(attrs: &[(&str, &str)]) -> TempDir {
+        let device = TempDir::new("iio");
+        for (attr, value) in attrs {
+            fs::write(device.join(attr), format!("{}\n", value)).unwrap();
+        }
+        device
+    }
+
+    #[test]
+    fn follows_the_curve() {
+        let curve = [(0.0, 100), (50.0, 40), (400.0, 0)];
+        assert_eq!(curve_percent(&curve, -1.0), Some(100.0));
+        assert_eq!(curve_percent(&curve, 0.0), Some(100.0));
+        assert_eq!(curve_percent(&curve, 25.0), Some(70.0));
+        assert_eq!(curve_percent(&curve, 50.0), Some(40.0));
+        assert_eq!(curve_percent(&curve, 225.0), Some(20.0));
+        assert_eq!(curve_percent(&curve, 10_000.0), Some(0.0));
+        assert_eq!(curve_percent(&[(10.0, 30)], 0.0), Some(30.0));
+        assert_eq!(curve_percent(&[], 10.0), None);
+    }
+
+    #[test]
+    fn reads_processed_illuminance() {
+        let dir = device(&[("in_illuminance_input", "123.5"), ("in_illuminance_raw", "7")]);
+        let sensor = LightSensor::open(dir.path()).unwrap();
+        assert_eq!(sensor.lux().unwrap(), 123.5);
+    }
+
+    #[test]
+    fn scales_raw_illuminance() {
+        let dir = device(&[
+            ("in_illuminance0_raw", "100"),
+            ("in_illuminance0_offset", "-20"),
+            ("in_illuminance_scale", "0.5"),
+            ("name", "als"),
+        ]);
+        let sensor = LightSensor::open(dir.path()).unwrap();
+        assert_eq!(sensor.lux().unwrap(), 40.0);
+        assert_eq!(sensor.name(), "als");
+        // Never below 0 lux, whatever the offset.
+        fs::write(dir.join("in_illuminance0_raw"), "5\n").unwrap();
+        assert_eq!(sensor.lux().unwrap(), 0.0);
+        fs::write(dir.join("in_illuminance0_raw"), "dark\n").unwrap();
+        assert!(sensor.lux().is_err());
+    }
+
+    #[test]
+    fn skips_devices_without_illuminance() {
+        let dir = device(&[("in_accel_x_raw", "1"), ("name", "accel")]);
+        assert!(LightSensor::open(dir.path()).is_none());
+        assert!(LightSensor::open(&dir.join("missing")).is_none());
+    }
+
+    #[test]
+    fn records_the_levels_it_wrote() {
+        let _runtime = runtime_dir();
+        let (keyboard, lightbar) = (PathBuf::from("/leds/rgb:kbd_backlight"), PathBuf::from("/leds/rgb:lightbar"));
+        assert_eq!(auto_level(&keyboard), None);
+        record_auto_levels(&[(keyboard.clone(), 20), (lightbar.clone(), 45)]);
+        assert_eq!(auto_level(&keyboard), Some(20));
+        assert_eq!(auto_level(&lightbar), Some(45));
+        assert_eq!(auto_level(Path::new("/leds/rgb:kbd_backlight_1")), None);
+        clear_auto_levels();
+        assert_eq!(auto_level(&keyboard), None);
+    }
+}
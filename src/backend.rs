@@ -0,0 +1,255 @@
+use crate::layout::{led_name, X6_KEYS};
+use glob::{glob, Pattern};
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+pub const DEFAULT_SYSFS_ROOT: &str = "/sys/class/leds";
+
+// `RUSTY_KB_SYSFS_ROOT=/tmp/leds` points every access at a fake sysfs tree
+// (one directory per LED holding `brightness`, `multi_intensity`, ... files).
+// `RUSTY_KB_BACKEND=memory` runs against a simulated keyboard and lightbar.
+const SYSFS_ROOT_ENV: &str = "RUSTY_KB_SYSFS_ROOT";
+const BACKEND_ENV: &str = "RUSTY_KB_BACKEND";
+
+/// Everything the app needs from the LED class: finding devices and reading or
+/// writing their attribute files. LEDs are identified by their path below the
+/// backend root so callers can keep passing `PathBuf`s around.
+pub trait LedBackend: Send + Sync {
+    fn root(&self) -> &Path;
+
+    /// LED directories below the root whose name matches `pattern` (a glob), sorted.
+    fn find(&self, pattern: &str) -> Vec<PathBuf>;
+
+    fn exists(&self, led: &Path) -> bool;
+
+    fn read_attr(&self, led: &Path, attr: &str) -> io::Result<String>;
+
+    fn write_attr(&self, led: &Path, attr: &str, value: &str) -> io::Result<()>;
+
+    /// Whether `attr` could be written, without writing it.
+    fn check_writable(&self, led: &Path, attr: &str) -> io::Result<()>;
+
+    fn led(&self, name: &str) -> PathBuf {
+        self.root().join(name)
+    }
+}
+
+/// The real LED class (or a directory laid out like it).
+pub struct SysfsBackend {
+    root: PathBuf,
+}
+
+impl SysfsBackend {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        SysfsBackend { root: root.into() }
+    }
+}
+
+impl LedBackend for SysfsBackend {
+    fn root(&self) -> &Path {
+        &self.root
+    }
+
+    fn find(&self, pattern: &str) -> Vec<PathBuf> {
+        let full = self.root.join(pattern);
+        let mut out: Vec<PathBuf> = match glob(&full.to_string_lossy()) {
+            Ok(entries) => entries.flatten().collect(),
+            Err(_) => Vec::new(),
+        };
+        out.sort();
+        out
+    }
+
+    fn exists(&self, led: &Path) -> bool {
+        led.exists()
+    }
+
+    fn read_attr(&self, led: &Path, attr: &str) -> io::Result<String> {
+        fs::read_to_string(led.join(attr))
+    }
+
+    fn write_attr(&self, led: &Path, attr: &str, value: &str) -> io::Result<()> {
+        fs::write(led.join(attr), value)
+    }
+
+    fn check_writable(&self, led: &Path, attr: &str) -> io::Result<()> {
+        fs::OpenOptions::new().write(true).open(led.join(attr)).map(drop)
+    }
+}
+
+/// In-memory stand-in for the LED class. Attribute files only exist if they
+/// were created with [`MemoryBackend::add_led`]; writing a missing attribute
+/// fails with `NotFound` like sysfs does.
+pub struct MemoryBackend {
+    root: PathBuf,
+    leds: Mutex<BTreeMap<PathBuf, BTreeMap<String, String>>>,
+}
+
+impl MemoryBackend {
+    pub fn new() -> Self {
+        MemoryBackend {
+            root: PathBuf::from("/memory/leds"),
+            leds: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// A simulated X6: one keyboard LED per key of the layout plus the lightbar.
+    pub fn simulated_x6() -> Self {
+        let backend = MemoryBackend::new();
+        for key in X6_KEYS {
+            backend.add_rgb_led(&led_name(key.led), 50);
+        }
+        backend.add_rgb_led("rgb:lightbar", 100);
+        backend
+    }
+
+    pub fn add_led(&self, name: &str, attrs: &[(&str, &str)]) {
+        let attrs = attrs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        self.leds.lock().unwrap().insert(self.root.join(name), attrs);
+    }
+
+    pub fn add_rgb_led(&self, name: &str, max_brightness: i32) {
+        let max = format!("{}\n", max_brightness);
+        self.add_led(
+            name,
+            &[
+                ("brightness", "0\n"),
+                ("max_brightness", &max),
+                ("multi_index", "red green blue\n"),
+                ("multi_intensity", "255 255 255\n"),
+            ],
+        );
+    }
+}
+
+impl Default for MemoryBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LedBackend for MemoryBackend {
+    fn root(&self) -> &Path {
+        &self.root
+    }
+
+    fn find(&self, pattern: &str) -> Vec<PathBuf> {
+        let Ok(pattern) = Pattern::new(pattern) else {
+            return Vec::new();
+        };
+        self.leds
+            .lock()
+            .unwrap()
+            .keys()
+            .filter(|p| {
+                p.file_name()
+                    .and_then(|s| s.to_str())
+                    .is_some_and(|name| pattern.matches(name))
+            })
+            .cloned()
+            .collect()
+    }
+
+    fn exists(&self, led: &Path) -> bool {
+        self.leds.lock().unwrap().contains_key(led)
+    }
+
+    fn read_attr(&self, led: &Path, attr: &str) -> io::Result<String> {
+        self.leds
+            .lock()
+            .unwrap()
+            .get(led)
+            .and_then(|attrs| attrs.get(attr))
+            .cloned()
+            .ok_or_else(|| not_found(led, attr))
+    }
+
+    fn write_attr(&self, led: &Path, attr: &str, value: &str) -> io::Result<()> {
+        let mut leds = self.leds.lock().unwrap();
+        match leds.get_mut(led).and_then(|attrs| attrs.get_mut(attr)) {
+            Some(slot) => {
+                *slot = value.to_string();
+                Ok(())
+            }
+            None => Err(not_found(led, attr)),
+        }
+    }
+
+    fn check_writable(&self, led: &Path, attr: &str) -> io::Result<()> {
+        self.read_attr(led, attr).map(drop)
+    }
+}
+
+fn not_found(led: &Path, attr: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("{} not found", led.join(attr).display()),
+    )
+}
+
+/// Picks the backend from the environment, defaulting to `/sys/class/leds`.
+pub fn from_env() -> Arc<dyn LedBackend> {
+    if env::var(BACKEND_ENV).is_ok_and(|v| v == "memory") {
+        eprintln!("Using simulated in-memory LED backend.");
+        return Arc::new(MemoryBackend::simulated_x6());
+    }
+    let root = env::var(SYSFS_ROOT_ENV).unwrap_or_else(|_| DEFAULT_SYSFS_ROOT.to_string());
+    if root != DEFAULT_SYSFS_ROOT {
+        eprintln!("Using LED sysfs root {}", root);
+    }
+    Arc::new(SysfsBackend::new(root))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp_dir::TempDir;
+
+    #[test]
+    fn memory_backend_finds_and_rewrites_leds() {
+        let backend = MemoryBackend::simulated_x6();
+        assert_eq!(backend.find("rgb:kbd_backlight*").len(), X6_KEYS.len());
+        assert_eq!(backend.find("rgb:lightbar"), vec![backend.led("rgb:lightbar")]);
+        assert!(backend.find("[").is_empty());
+
+        let led = backend.led("rgb:kbd_backlight_3");
+        backend.write_attr(&led, "brightness", "7\n").unwrap();
+        assert_eq!(backend.read_attr(&led, "brightness").unwrap(), "7\n");
+    }
+
+    #[test]
+    fn memory_backend_rejects_missing_attributes() {
+        let backend = MemoryBackend::new();
+        backend.add_led("rgb:lightbar", &[("brightness", "0\n")]);
+        let led = backend.led("rgb:lightbar");
+        let err = backend.write_attr(&led, "multi_intensity", "1 2 3\n").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert!(!backend.exists(&backend.led("rgb:kbd_backlight")));
+    }
+
+    #[test]
+    fn sysfs_backend_reads_a_directory_tree() {
+        let root = TempDir::new("backend");
+        for name in ["rgb:kbd_backlight_2", "rgb:kbd_backlight_1", "rgb:lightbar"] {
+            fs::create_dir(root.join(name)).unwrap();
+            fs::write(root.join(name).join("brightness"), "0\n").unwrap();
+        }
+        let backend = SysfsBackend::new(root.path());
+        assert_eq!(
+            backend.find("rgb:kbd_backlight*"),
+            vec![root.join("rgb:kbd_backlight_1"), root.join("rgb:kbd_backlight_2")]
+        );
+
+        let led = backend.led("rgb:lightbar");
+        backend.write_attr(&led, "brightness", "42\n").unwrap();
+        assert_eq!(backend.read_attr(&led, "brightness").unwrap(), "42\n");
+        assert!(backend.read_attr(&led, "max_brightness").is_err());
+    }
+}
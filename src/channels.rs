@@ -0,0 +1,258 @@
+// How an LED's `multi_intensity` is laid out. Multicolor LEDs list their
+// channels in `multi_index` ("red green blue", "green red blue", "red green blue
+// white", ...). LEDs without one (like the X6 keyboard) are taken to be plain
+// "red green blue" when `multi_intensity` holds three values, or a single
+// channel when it holds one. LEDs without `multi_intensity` have no color.
+
+use crate::backend::LedBackend;
+use crate::leds::read_max_brightness;
+use std::collections::HashMap;
+use std::env;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+
+// `RUSTY_KB_INTENSITY=max_brightness` scales intensities to each LED's
+// `max_brightness`, as the LED class documents. The X6 drivers take 0-255
+// whatever their `max_brightness`, so that is the default.
+const INTENSITY_ENV: &str = "RUSTY_KB_INTENSITY";
+const BYTE_MAX: u32 = 255;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Channel {
+    Red,
+    Green,
+    Blue,
+    White,
+}
+
+impl Channel {
+    fn from_name(name: &str) -> Option<Channel> {
+        match name {
+            "red" => Some(Channel::Red),
+            "green" => Some(Channel::Green),
+            "blue" => Some(Channel::Blue),
+            "white" => Some(Channel::White),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChannelLayout {
+    pub channels: Vec<Channel>,
+    pub max_intensity: u32,
+}
+
+impl ChannelLayout {
+    /// A single channel of any color shows the brightest component of the
+    /// color; otherwise red, green and blue must all be there, white is optional.
+    /// `values` is how many values `multi_intensity` holds now, which is all
+    /// there is to go by without a `multi_index`.
+    pub fn parse(multi_index: Option<&str>, values: usize, max_intensity: u32) -> Result<Self, String> {
+        let channels = match multi_index {
+            None => match values {
+                1 => vec![Channel::White],
+                3 => vec![Channel::Red, Channel::Green, Channel::Blue],
+                n => return Err(format!("no multi_index to tell the {} values of multi_intensity apart", n)),
+            },
+            Some(index) => index
+                .split_whitespace()
+                .map(|name| {
+                    Channel::from_name(&name.to_ascii_lowercase())
+                        .ok_or_else(|| format!("unsupported color channel '{}'", name))
+                })
+                .collect::<Result<Vec<_>, _>>()?,
+        };
+        if max_intensity == 0 {
+            return Err("intensity range is empty".to_string());
+        }
+        let count = |c: Channel| channels.iter().filter(|&&x| x == c).count();
+        let complete = [Channel::Red, Channel::Green, Channel::Blue]
+            .into_iter()
+            .all(|c| count(c) == 1)
+            && count(Channel::White) <= 1;
+        if channels.len() != 1 && !complete {
+            return Err(format!(
+                "unsupported channel layout '{}'",
+                multi_index.unwrap_or_default().trim()
+            ));
+        }
+        Ok(ChannelLayout { channels, max_intensity })
+    }
+
+    fn scale_out(&self, v: u8) -> u32 {
+        (v as u32 * self.max_intensity + BYTE_MAX / 2) / BYTE_MAX
+    }
+
+    fn scale_in(&self, v: u32) -> u8 {
+        ((v.min(self.max_intensity) * BYTE_MAX + self.max_intensity / 2) / self.max_intensity) as u8
+    }
+
+    /// The `multi_intensity` line for `rgb`. A white channel takes over the
+    /// part all three colors share.
+    pub fn encode(&self, (r, g, b): (u8, u8, u8)) -> String {
+        let values: Vec<String> = if let [_] = self.channels[..] {
+            vec![self.scale_out(r.max(g).max(b)).to_string()]
+        } else {
+            let w = if self.channels.contains(&Channel::White) { r.min(g).min(b) } else { 0 };
+            self.channels
+                .iter()
+                .map(|c| match c {
+                    Channel::Red => r - w,
+                    Channel::Green => g - w,
+                    Channel::Blue => b - w,
+                    Channel::White => w,
+                })
+                .map(|v| self.scale_out(v).to_string())
+                .collect()
+        };
+        format!("{}\n", values.join(" "))
+    }
+
+    pub fn decode(&self, content: &str) -> Option<(u8, u8, u8)> {
+        let values: Vec<u8> = content
+            .split_whitespace()
+            .map(|s| s.parse::<u32>().ok().map(|v| self.scale_in(v)))
+            .collect::<Option<_>>()?;
+        if values.len() != self.channels.len() {
+            return None;
+        }
+        if let [v] = values[..] {
+            return Some((v, v, v));
+        }
+        let (mut r, mut g, mut b, mut w) = (0u8, 0u8, 0u8, 0u8);
+        for (channel, v) in self.channels.iter().zip(values) {
+            match channel {
+                Channel::Red => r = v,
+                Channel::Green => g = v,
+                Channel::Blue => b = v,
+                Channel::White => w = v,
+            }
+        }
+        Some((r.saturating_add(w), g.saturating_add(w), b.saturating_add(w)))
+    }
+}
+
+fn intensity_follows_max_brightness() -> bool {
+    env::var(INTENSITY_ENV).is_ok_and(|v| v == "max_brightness")
+}
+
+type LayoutCache = Mutex<HashMap<PathBuf, Result<ChannelLayout, String>>>;
+
+fn layouts() -> &'static LayoutCache {
+    static LAYOUTS: OnceLock<LayoutCache> = OnceLock::new();
+    LAYOUTS.get_or_init(Default::default)
+}
+
+/// Drops what is known about `paths`, for LEDs that went away or came back.
+pub fn forget_channel_layouts(paths: &[PathBuf]) {
+    let mut layouts = layouts().lock().unwrap();
+    for path in paths {
+        layouts.remove(path);
+    }
+}
+
+/// The layout of the LED at `path`, read once per run. An LED whose layout
+/// can't be handled is reported the first time and left alone after that.
+pub fn channel_layout(backend: &dyn LedBackend, path: &Path) -> Result<ChannelLayout, String> {
+    let mut layouts = layouts().lock().unwrap();
+    if let Some(layout) = layouts.get(path) {
+        return layout.clone();
+    }
+    let intensity = match backend.read_attr(path, "multi_intensity") {
+        Ok(content) => Ok(content),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Err("no multi_intensity, the LED only has a brightness".to_string()),
+        // Not remembered, it may be readable next time.
+        Err(e) => {
+            let e = format!("{}: reading multi_intensity: {}", path.display(), e);
+            eprintln!("Error: {}", e);
+            return Err(e);
+        }
+    };
+    let index = backend.read_attr(path, "multi_index").ok();
+    let max_intensity = if intensity_follows_max_brightness() {
+        read_max_brightness(backend, path).map_or(BYTE_MAX, |m| m.max(0) as u32)
+    } else {
+        BYTE_MAX
+    };
+    let layout = intensity
+        .and_then(|content| ChannelLayout::parse(index.as_deref(), content.split_whitespace().count(), max_intensity))
+        .map_err(|e| format!("{}: {}", path.display(), e));
+    if let Err(e) = &layout {
+        eprintln!("Error: {}, leaving its color alone", e);
+    }
+    layouts.insert(path.to_path_buf(), layout.clone());
+    layout
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::MemoryBackend;
+
+    fn layout(multi_index: Option<&str>, values: usize) -> Result<ChannelLayout, String> {
+        ChannelLayout::parse(multi_index, values, BYTE_MAX)
+    }
+
+    #[test]
+    fn parses_channel_layouts() {
+        use Channel::*;
+        assert_eq!(layout(Some("red green blue"), 3).unwrap().channels, [Red, Green, Blue]);
+        assert_eq!(layout(Some("Blue Green Red"), 3).unwrap().channels, [Blue, Green, Red]);
+        assert_eq!(layout(Some("red green blue white"), 4).unwrap().channels, [Red, Green, Blue, White]);
+        assert_eq!(layout(Some("green"), 1).unwrap().channels, [Green]);
+        // Without multi_index only the number of values tells them apart.
+        assert_eq!(layout(None, 3).unwrap().channels, [Red, Green, Blue]);
+        assert_eq!(layout(None, 1).unwrap().channels, [White]);
+    }
+
+    #[test]
+    fn rejects_unusable_layouts() {
+        assert!(layout(None, 4).is_err());
+        assert!(layout(Some("red green"), 2).is_err());
+        assert!(layout(Some("red red blue"), 3).is_err());
+        assert!(layout(Some("red green blue amber"), 4).is_err());
+        assert!(ChannelLayout::parse(Some("red green blue"), 3, 0).is_err());
+    }
+
+    #[test]
+    fn encodes_and_decodes_colors() {
+        let rgb = layout(Some("red green blue"), 3).unwrap();
+        assert_eq!(rgb.encode((255, 128, 0)), "255 128 0\n");
+        assert_eq!(rgb.decode("255 128 0\n"), Some((255, 128, 0)));
+        assert_eq!(rgb.decode("1 2"), None);
+
+        let bgr = layout(Some("blue green red"), 3).unwrap();
+        assert_eq!(bgr.encode((255, 128, 0)), "0 128 255\n");
+        assert_eq!(bgr.decode("0 128 255"), Some((255, 128, 0)));
+
+        // White takes the part the three colors share.
+        let rgbw = layout(Some("red green blue white"), 4).unwrap();
+        assert_eq!(rgbw.encode((255, 200, 100)), "155 100 0 100\n");
+        assert_eq!(rgbw.decode("155 100 0 100"), Some((255, 200, 100)));
+
+        let single = layout(Some("white"), 1).unwrap();
+        assert_eq!(single.encode((10, 200, 30)), "200\n");
+        assert_eq!(single.decode("200"), Some((200, 200, 200)));
+    }
+
+    #[test]
+    fn scales_to_the_intensity_range() {
+        let layout = ChannelLayout::parse(Some("red green blue"), 3, 100).unwrap();
+        assert_eq!(layout.encode((255, 128, 0)), "100 50 0\n");
+        assert_eq!(layout.decode("100 50 0"), Some((255, 128, 0)));
+        assert_eq!(layout.decode("500 0 0"), Some((255, 0, 0)));
+    }
+
+    #[test]
+    fn reads_the_layout_of_each_led() {
+        let backend = MemoryBackend::new();
+        backend.add_led("rgb:kbd_backlight_bgr", &[("multi_index", "blue green red\n"), ("multi_intensity", "0 0 0\n")]);
+        backend.add_led("white:kbd_backlight_mono", &[("brightness", "0\n")]);
+        let bgr = channel_layout(&backend, &backend.led("rgb:kbd_backlight_bgr")).unwrap();
+        assert_eq!(bgr.channels, [Channel::Blue, Channel::Green, Channel::Red]);
+        // Without a multi_intensity there is no color to set.
+        assert!(channel_layout(&backend, &backend.led("white:kbd_backlight_mono")).is_err());
+    }
+}
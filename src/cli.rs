@@ -0,0 +1,697 @@
+// Headless commands: drive the backlight from scripts and keybindings without GTK.
+
+use crate::ambient::{curve_percent, find_light_sensor};
+use crate::backend::LedBackend;
+use crate::config::{load_config, read_config_or_legacy, save_merged, Config, Rgb};
+use crate::dbus::run_daemon;
+use crate::doctor::{format_json, format_report, run_checks, Status};
+use crate::effects::{
+    Direction, Effect, EffectParams, KeyboardFrame, Renderer, FRAME_INTERVAL,
+};
+use crate::input::{input_source, spawn_key_reader};
+use crate::layout::{parse_selection, KeyMap};
+use crate::leds::{
+    apply_lighting, find_lightbar, format_hex, keyboard_write_paths, pack_rgb, parse_color, pick_primary,
+    read_brightness, read_brightness_parallel, read_color, read_color_parallel,
+    read_max_brightness, write_brightness, write_brightness_all, write_color,
+    write_color_all, write_failure_count, KB_BACKLIGHT_PATTERN,
+};
+use crate::hw_brightness::spawn_brightness_watcher;
+use crate::idle::wake;
+use crate::palette::import_palette;
+use crate::schedule::{
+    due_and_next, format_local, format_local_time, local_date, sun_times, unix_now, ScheduleTime,
+};
+use crate::persist::{
+    spawn_persistence_worker, Brightness, DEFAULT_KB_MAX_BRIGHTNESS, DEFAULT_LB_MAX_BRIGHTNESS,
+};
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const PRESS_FADE: Duration = Duration::from_secs(2);
+
+const USAGE: &str = "\
+Usage: lwl-x6-keyboard [COMMAND]
+
+Without a command the settings window opens.
+
+Commands:
+  set [--color COLOR] [--brightness N] [--keys KEYS]
+                          Set the keyboard color and/or brightness. KEYS is a
+                          comma separated list of keys and groups (e.g. wasd,space).
+  get                     Print keyboard and lightbar state.
+  lightbar set [--color COLOR] [--brightness N]
+  lightbar get
+  off                     Turn keyboard and lightbar off.
+  on [--brightness N]     Turn them back on (default: full brightness).
+  profile list            List the saved profiles.
+  profile save NAME       Save the current lighting as profile NAME.
+  profile switch NAME     Apply profile NAME.
+  profile delete NAME
+  preset list             List the palette of the color dropdowns.
+  preset add NAME COLOR   Add a swatch, or recolor the one called NAME.
+  preset rename NAME NEW
+  preset move NAME POS    Move a swatch to position POS (1 is the top).
+  preset delete NAME
+  preset import FILE      Add the colors of a GIMP .gpl palette or a list of
+                          colors, one per line.
+  effect NAME [--speed X] [--direction left|right] [--color COLOR] [--seconds N]
+         [--input DEVICE]
+                          Run an animated effect (breathing, spectrum, wave,
+                          rainbow, starlight, reactive, ripple) until
+                          interrupted, or for N seconds after which the saved
+                          colors come back. reactive and ripple follow key
+                          presses on DEVICE, a /dev/input/event* device or a
+                          recording of one (default: the laptop keyboard).
+  watch                   Print brightness changes made with the Fn keys, and
+                          remember them for the next login, until interrupted.
+  daemon                  Serve the com.example.RustyKb D-Bus interface on the
+                          session bus.
+  restore [FILE]          Apply the saved config (default ~/.rusty-kb/config.toml).
+                          FILE may also be a colors.txt from older versions.
+  doctor [--json]         Check the LEDs, their permissions, the udev rule, the
+                          login service and the saved config, and say how to
+                          fix what is wrong. Exits with 1 if a check failed.
+  schedule                Print today's times of the [[schedule]] in the config,
+                          the profile in effect and the next switch. The
+                          daemon switches the profiles.
+  ambient                 Print the ambient light sensor's reading and the
+                          brightness the [auto_brightness] curve gives for it.
+                          The daemon follows the sensor.
+  help                    Show this help.
+
+COLOR is #rrggbb, rrggbb, r,g,b or hsv(h,s%,v%). N is a percentage like 40%, or
+without the % the raw sysfs value (up to the LED's max_brightness).
+`set` and `lightbar set` are remembered for the next login unless --keys is used.";
+
+const COMMANDS: &[&str] = &[
+    "set", "get", "lightbar", "off", "on", "profile", "preset", "effect", "watch", "daemon", "restore", "doctor", "schedule", "ambient", "help", "--help", "-h",
+];
+
+#[derive(Clone, Copy)]
+enum Level {
+    Raw(i32),
+    Percent(f64),
+}
+
+impl Level {
+    fn parse(s: &str) -> Option<Level> {
+        match s.strip_suffix('%') {
+            Some(p) => p
+                .trim()
+                .parse::<f64>()
+                .ok()
+                .filter(|p| (0.0..=100.0).contains(p))
+                .map(Level::Percent),
+            None => s.parse::<i32>().ok().filter(|n| *n >= 0).map(Level::Raw),
+        }
+    }
+
+    /// The raw value for an LED whose maximum is `max`.
+    fn resolve(self, max: i32) -> i32 {
+        match self {
+            Level::Raw(v) => v.min(max),
+            Level::Percent(p) => Brightness::from_percent(p, max).value,
+        }
+    }
+}
+
+#[derive(Default)]
+struct SetArgs {
+    color: Option<(u8, u8, u8)>,
+    brightness: Option<Level>,
+    keys: Option<String>,
+}
+
+fn parse_set_args(args: &[String], allow_keys: bool) -> Result<SetArgs, String> {
+    let mut out = SetArgs::default();
+    let mut it = args.iter();
+    while let Some(arg) = it.next() {
+        let mut value = |name: &str| {
+            it.next()
+                .cloned()
+                .ok_or_else(|| format!("{} needs a value", name))
+        };
+        match arg.as_str() {
+            "--color" => out.color = Some(parse_color(&value("--color")?)?),
+            "--brightness" => {
+                let v = value("--brightness")?;
+                let level = Level::parse(&v).ok_or_else(|| format!("invalid brightness '{}'", v))?;
+                out.brightness = Some(level);
+            }
+            "--keys" if allow_keys => out.keys = Some(value("--keys")?),
+            other => return Err(format!("unexpected argument '{}'", other)),
+        }
+    }
+    Ok(out)
+}
+
+/// Runs the command in `args` (without the program name). Returns `None` when
+/// there is no command and the GUI should start instead.
+pub fn run(shared: &Arc<dyn LedBackend>, args: &[String]) -> Option<i32> {
+    let backend = shared.as_ref();
+    let command = args.first()?;
+    if !COMMANDS.contains(&command.as_str()) {
+        eprintln!("Unknown command '{}'\n\n{}", command, USAGE);
+        return Some(2);
+    }
+    let rest = &args[1..];
+    let failed_before = write_failure_count();
+    // Commands that change the lighting start from the normal brightness.
+    if matches!(command.as_str(), "set" | "lightbar" | "off" | "on" | "profile" | "effect" | "restore")
+        && let Ok((config, _)) = load_config()
+    {
+        wake(backend, &config);
+    }
+    let result = match command.as_str() {
+        "set" => parse_set_args(rest, true).and_then(|a| set_keyboard(backend, a)),
+        "get" => get(backend),
+        "lightbar" => match rest.first().map(String::as_str) {
+            Some("set") => parse_set_args(&rest[1..], false).and_then(|a| set_lightbar(backend, a)),
+            Some("get") => get_lightbar(backend),
+            _ => Err("expected 'lightbar set' or 'lightbar get'".to_string()),
+        },
+        "off" => power(backend, false, None),
+        "on" => parse_set_args(rest, false).and_then(|a| {
+            if a.color.is_some() {
+                return Err("'on' only takes --brightness".to_string());
+            }
+            power(backend, true, a.brightness)
+        }),
+        "profile" => match rest {
+            [action] if action == "list" => list_profiles(),
+            [action, name] => profile(backend, action, name),
+            _ => Err("expected 'profile list' or 'profile save|switch|delete NAME'".to_string()),
+        },
+        "preset" => preset(rest),
+        "effect" => run_effect(backend, rest),
+        "watch" if rest.is_empty() => watch(shared),
+        "watch" => Err("watch takes no arguments".to_string()),
+        "daemon" if rest.is_empty() => run_daemon(Arc::clone(shared)),
+        "daemon" => Err("daemon takes no arguments".to_string()),
+        "doctor" => match rest {
+            [] => doctor(backend, false),
+            [flag] if flag == "--json" => doctor(backend, true),
+            _ => Err("doctor only takes --json".to_string()),
+        },
+        "restore" => match rest {
+            [] => restore(backend, None),
+            [file] => restore(backend, Some(PathBuf::from(file))),
+            _ => Err("restore takes at most one file".to_string()),
+        },
+        "schedule" if rest.is_empty() => show_schedule(),
+        "schedule" => Err("schedule takes no arguments".to_string()),
+        "ambient" if rest.is_empty() => show_ambient(),
+        "ambient" => Err("ambient takes no arguments".to_string()),
+        _ => {
+            println!("{}", USAGE);
+            return Some(0);
+        }
+    };
+    // Failed LED writes were reported as they happened, but still fail the
+    // command for scripts and the restore unit.
+    let result = result.and_then(|()| match write_failure_count() - failed_before {
+        0 => Ok(()),
+        1 => Err("1 LED write failed".to_string()),
+        n => Err(format!("{} LED writes failed", n)),
+    });
+    match result {
+        Ok(()) => Some(0),
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            Some(1)
+        }
+    }
+}
+
+fn doctor(backend: &dyn LedBackend, json: bool) -> Result<(), String> {
+    let checks = run_checks(backend);
+    if json {
+        println!("{}", format_json(&checks));
+    } else {
+        print!("{}", format_report(&checks));
+    }
+    match checks.iter().filter(|c| c.status == Status::Failed).count() {
+        0 => Ok(()),
+        1 => Err("1 check failed".to_string()),
+        n => Err(format!("{} checks failed", n)),
+    }
+}
+
+fn keyboard_paths(backend: &dyn LedBackend) -> Result<Vec<PathBuf>, String> {
+    let paths = backend.find(KB_BACKLIGHT_PATTERN);
+    if paths.is_empty() {
+        return Err("keyboard backlight not found".to_string());
+    }
+    Ok(paths)
+}
+
+fn lightbar_path(backend: &dyn LedBackend) -> Result<PathBuf, String> {
+    find_lightbar(backend).ok_or_else(|| "lightbar not found".to_string())
+}
+
+fn set_keyboard(backend: &dyn LedBackend, args: SetArgs) -> Result<(), String> {
+    if args.color.is_none() && args.brightness.is_none() {
+        return Err("nothing to set, pass --color and/or --brightness".to_string());
+    }
+    let all_paths = keyboard_paths(backend)?;
+    let targets = match &args.keys {
+        Some(spec) => KeyMap::resolve(&all_paths).paths_for(&parse_selection(spec)?),
+        None => {
+            // A broken config is reported when saving below, the write still happens.
+            let options = load_config().map(|(c, _)| c.options).unwrap_or_default();
+            keyboard_write_paths(&all_paths, &pick_primary(&all_paths).unwrap(), &options)
+        }
+    };
+    if targets.is_empty() {
+        return Err("none of the selected keys were found".to_string());
+    }
+    if let Some((r, g, b)) = args.color {
+        write_color_all(backend, &targets, r, g, b);
+    }
+    if let Some(level) = args.brightness {
+        let max = pick_primary(&all_paths)
+            .and_then(|p| read_max_brightness(backend, &p))
+            .unwrap_or(DEFAULT_KB_MAX_BRIGHTNESS);
+        write_brightness_all(backend, &targets, level.resolve(max));
+    }
+    if args.keys.is_none() {
+        // A new color for the whole keyboard paints over the per-key colors.
+        save_current_state(backend, args.color.is_some())?;
+    }
+    Ok(())
+}
+
+fn set_lightbar(backend: &dyn LedBackend, args: SetArgs) -> Result<(), String> {
+    if args.color.is_none() && args.brightness.is_none() {
+        return Err("nothing to set, pass --color and/or --brightness".to_string());
+    }
+    let path = lightbar_path(backend)?;
+    if let Some((r, g, b)) = args.color {
+        write_color(backend, &path, r, g, b);
+    }
+    if let Some(level) = args.brightness {
+        let max = read_max_brightness(backend, &path).unwrap_or(DEFAULT_LB_MAX_BRIGHTNESS);
+        write_brightness(backend, &path, level.resolve(max));
+    }
+    save_current_state(backend, false)
+}
+
+fn describe(color: Option<(u8, u8, u8)>, brightness: Option<i32>, max: Option<i32>) -> String {
+    let color = color.map(format_hex).unwrap_or_else(|| "unknown".to_string());
+    match (brightness, max) {
+        (Some(b), Some(max)) => format!(
+            "color {} brightness {}/{} ({:.0}%)",
+            color,
+            b,
+            max,
+            Brightness::new(b, max).percent()
+        ),
+        (Some(b), None) => format!("color {} brightness {}", color, b),
+        (None, _) => format!("color {} brightness ?", color),
+    }
+}
+
+fn get(backend: &dyn LedBackend) -> Result<(), String> {
+    let paths = backend.find(KB_BACKLIGHT_PATTERN);
+    match pick_primary(&paths) {
+        Some(primary) => println!(
+            "keyboard: {} ({} LEDs)",
+            describe(
+                read_color_parallel(backend, &paths),
+                read_brightness_parallel(backend, &paths),
+                read_max_brightness(backend, &primary),
+            ),
+            paths.len()
+        ),
+        None => println!("keyboard: not found"),
+    }
+    match find_lightbar(backend) {
+        Some(_) => get_lightbar(backend)?,
+        None => println!("lightbar: not found"),
+    }
+    Ok(())
+}
+
+fn get_lightbar(backend: &dyn LedBackend) -> Result<(), String> {
+    let path = lightbar_path(backend)?;
+    println!(
+        "lightbar: {}",
+        describe(
+            read_color(backend, &path),
+            read_brightness(backend, &path),
+            read_max_brightness(backend, &path),
+        )
+    );
+    Ok(())
+}
+
+fn power(backend: &dyn LedBackend, on: bool, brightness: Option<Level>) -> Result<(), String> {
+    let kb_paths = backend.find(KB_BACKLIGHT_PATTERN);
+    let lb_path = find_lightbar(backend);
+    if kb_paths.is_empty() && lb_path.is_none() {
+        return Err("no keyboard backlight or lightbar found".to_string());
+    }
+    if let Some(primary) = pick_primary(&kb_paths) {
+        let max = read_max_brightness(backend, &primary).unwrap_or(DEFAULT_KB_MAX_BRIGHTNESS);
+        let v = match (on, brightness) {
+            (false, _) => 0,
+            (true, Some(level)) => level.resolve(max),
+            (true, None) => max,
+        };
+        write_brightness_all(backend, &kb_paths, v);
+    }
+    if let Some(path) = lb_path {
+        let max = read_max_brightness(backend, &path).unwrap_or(DEFAULT_LB_MAX_BRIGHTNESS);
+        let v = match (on, brightness) {
+            (false, _) => 0,
+            (true, Some(level)) => level.resolve(max),
+            (true, None) => max,
+        };
+        write_brightness(backend, &path, v);
+    }
+    Ok(())
+}
+
+// Never replace a config we couldn't read, the user would lose their edits.
+fn load_config_for_saving() -> Result<Config, String> {
+    load_config()
+        .map(|(config, _)| config)
+        .map_err(|e| format!("not saving, {}", e))
+}
+
+// Saves what a command changed since it loaded `base`, on top of what the
+// window or the daemon saved meanwhile.
+fn save_changes(base: &Config, config: &Config) -> Result<(), String> {
+    save_merged(base, config)
+        .map(|_| ())
+        .map_err(|e| format!("could not save config: {}", e))
+}
+
+// Mirror what the GUI does after every change so the next login restores it.
+fn save_current_state(backend: &dyn LedBackend, clear_key_colors: bool) -> Result<(), String> {
+    let base = load_config_for_saving()?;
+    let mut config = base.clone();
+    capture_state(backend, &mut config);
+    if clear_key_colors {
+        config.keyboard.keys.clear();
+    }
+    save_changes(&base, &config)
+}
+
+fn capture_state(backend: &dyn LedBackend, config: &mut Config) {
+    let kb_paths = backend.find(KB_BACKLIGHT_PATTERN);
+    let lb_path = find_lightbar(backend);
+    let (r, g, b) = read_color_parallel(backend, &kb_paths).unwrap_or((255, 255, 255));
+    let kb_max = pick_primary(&kb_paths)
+        .and_then(|p| read_max_brightness(backend, &p))
+        .unwrap_or(DEFAULT_KB_MAX_BRIGHTNESS);
+    let lb_color = lb_path.as_ref().and_then(|p| read_color(backend, p));
+    config.set_state(
+        pack_rgb(r, g, b),
+        Brightness::new(read_brightness_parallel(backend, &kb_paths).unwrap_or(0), kb_max),
+        lb_color.map(|(r, g, b)| pack_rgb(r, g, b)),
+        lb_path.as_ref().filter(|_| lb_color.is_some()).map(|p| {
+            Brightness::new(
+                read_brightness(backend, p).unwrap_or(0),
+                read_max_brightness(backend, p).unwrap_or(DEFAULT_LB_MAX_BRIGHTNESS),
+            )
+        }),
+    );
+}
+
+fn list_profiles() -> Result<(), String> {
+    let (config, _) = load_config()?;
+    if config.profiles.is_empty() {
+        println!("No profiles, create one with 'profile save NAME'");
+    }
+    for profile in &config.profiles {
+        let lightbar = match &profile.lightbar {
+            Some(lb) => format!(
+                ", lightbar {} {}/{}",
+                format_hex(lb.color.tuple()),
+                lb.brightness,
+                lb.max_brightness
+            ),
+            None => String::new(),
+        };
+        println!(
+            "{}: keyboard {} {}/{} ({} painted keys){}",
+            profile.name,
+            format_hex(profile.keyboard.color.tuple()),
+            profile.keyboard.brightness,
+            profile.keyboard.max_brightness,
+            profile.keyboard.keys.len(),
+            lightbar
+        );
+    }
+    Ok(())
+}
+
+fn show_schedule() -> Result<(), String> {
+    let (config, _) = load_config()?;
+    if config.schedule.is_empty() {
+        println!("No schedule, add [[schedule]] entries to the config file");
+        return Ok(());
+    }
+    let now = unix_now();
+    let today = local_date(now);
+    if let Some(location) = config.location {
+        match sun_times(today, location) {
+            Some((rise, set)) => println!(
+                "Sunrise {}, sunset {}",
+                format_local_time(rise),
+                format_local_time(set)
+            ),
+            None => println!("The sun doesn't rise or set today"),
+        }
+    }
+    for entry in &config.schedule {
+        let time = ScheduleTime::parse(&entry.at)?.on(today, config.location);
+        let time = time.map_or("-".to_string(), format_local_time);
+        let missing = if config.profiles.iter().any(|p| p.name == entry.profile) { "" } else { " (no such profile)" };
+        println!("{:<14} {:<6} {}{}", entry.at, time, entry.profile, missing);
+    }
+    let (due, next) = due_and_next(&config.schedule, config.location, now);
+    if let Some(due) = due {
+        let name = &config.schedule[due.entry].profile;
+        println!("In effect: {} since {}", name, format_local(due.time));
+    }
+    if let Some(next) = next {
+        let name = &config.schedule[next.entry].profile;
+        println!("Next: {} at {}", name, format_local(next.time));
+    }
+    Ok(())
+}
+
+fn show_ambient() -> Result<(), String> {
+    let sensor = find_light_sensor()?;
+    let lux = sensor.lux().map_err(|e| format!("{}: {}", sensor.device.display(), e))?;
+    println!("{} ({}): {:.1} lux", sensor.device.display(), sensor.name(), lux);
+    let (config, _) = load_config()?;
+    match curve_percent(&config.auto_brightness.curve, lux) {
+        Some(percent) => println!("Auto brightness: {:.0}%", percent),
+        None => println!("Auto brightness is off, add an [auto_brightness] curve to the config file"),
+    }
+    Ok(())
+}
+
+fn profile(backend: &dyn LedBackend, action: &str, name: &str) -> Result<(), String> {
+    let base = load_config_for_saving()?;
+    let mut config = base.clone();
+    match action {
+        "save" => {
+            capture_state(backend, &mut config);
+            config.save_profile(name)?;
+        }
+        "switch" => {
+            config.activate_profile(name)?;
+            apply_lighting(backend, &config.keyboard, config.lightbar.as_ref(), &config.options)?;
+        }
+        "delete" => config.delete_profile(name)?,
+        other => return Err(format!("unknown profile action '{}'", other)),
+    }
+    save_changes(&base, &config)
+}
+
+fn preset(args: &[String]) -> Result<(), String> {
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    if args == ["list"] {
+        let (config, _) = load_config()?;
+        if config.presets.is_empty() {
+            println!("No presets, add one with 'preset add NAME COLOR'");
+        }
+        for (i, preset) in config.presets.iter().enumerate() {
+            println!("{:>2}. {} {}", i + 1, format_hex(preset.color.tuple()), preset.name);
+        }
+        return Ok(());
+    }
+    let base = load_config_for_saving()?;
+    let mut config = base.clone();
+    match args[..] {
+        ["add", name, color] => {
+            let (r, g, b) = parse_color(color)?;
+            config.add_preset(name, Rgb(r, g, b))?;
+        }
+        ["rename", name, new_name] => config.rename_preset(name, new_name)?,
+        ["move", name, position] => {
+            let position = position
+                .parse::<usize>()
+                .ok()
+                .filter(|p| *p > 0)
+                .ok_or_else(|| format!("invalid position '{}'", position))?;
+            config.move_preset(name, position - 1)?;
+        }
+        ["delete", name] => config.delete_preset(name)?,
+        ["import", file] => {
+            let added = config.import_presets(import_palette(Path::new(file))?);
+            println!("Imported {} new preset(s) from {}", added, file);
+        }
+        _ => {
+            return Err(
+                "expected 'preset list', 'preset add NAME COLOR', 'preset rename NAME NEW', \
+                 'preset move NAME POS', 'preset delete NAME' or 'preset import FILE'"
+                    .to_string(),
+            )
+        }
+    }
+    save_changes(&base, &config)
+}
+
+fn watch(backend: &Arc<dyn LedBackend>) -> Result<(), String> {
+    let (config, tx_persist) = spawn_persistence_worker(load_config_for_saving()?, |_| {});
+    spawn_brightness_watcher(Arc::clone(backend), config, tx_persist, |change| {
+        println!(
+            "{}: brightness {}/{} ({:.0}%)",
+            change.target.name(),
+            change.brightness.value,
+            change.brightness.max,
+            change.brightness.percent()
+        );
+    });
+    loop {
+        thread::park();
+    }
+}
+
+#[derive(Default)]
+struct EffectArgs {
+    seconds: Option<f64>,
+    input: Option<PathBuf>,
+}
+
+fn parse_effect_args(
+    args: &[String],
+    default_color: (u8, u8, u8),
+) -> Result<(EffectParams, EffectArgs), String> {
+    let (name, rest) = args.split_first().ok_or("effect needs a name")?;
+    let mut params = EffectParams {
+        effect: Effect::from_name(name)?,
+        color: default_color,
+        speed: 1.0,
+        direction: Direction::LeftToRight,
+    };
+    let mut extra = EffectArgs::default();
+    let mut it = rest.iter();
+    while let Some(arg) = it.next() {
+        let value = it.next().ok_or_else(|| format!("{} needs a value", arg))?;
+        let number = || {
+            value
+                .parse::<f64>()
+                .ok()
+                .filter(|n| n.is_finite() && *n > 0.0)
+                .ok_or_else(|| format!("invalid {} '{}'", arg.trim_start_matches('-'), value))
+        };
+        match arg.as_str() {
+            "--speed" => params.speed = number()?,
+            "--seconds" => extra.seconds = Some(number()?),
+            "--input" => extra.input = Some(PathBuf::from(value)),
+            "--direction" => params.direction = Direction::from_name(value)?,
+            "--color" => params.color = parse_color(value)?,
+            other => return Err(format!("unexpected argument '{}'", other)),
+        }
+    }
+    Ok((params, extra))
+}
+
+// Renders in the foreground, writing each frame before the next one.
+fn run_effect(backend: &dyn LedBackend, args: &[String]) -> Result<(), String> {
+    let config = load_config().map(|(c, _)| c).unwrap_or_default();
+    let (params, extra) = parse_effect_args(args, config.keyboard.color.tuple())?;
+    let kb_paths = backend.find(KB_BACKLIGHT_PATTERN);
+    let lb_path = find_lightbar(backend);
+    if kb_paths.is_empty() && lb_path.is_none() {
+        return Err("no keyboard backlight or lightbar found".to_string());
+    }
+    let targets = match pick_primary(&kb_paths) {
+        Some(primary) => keyboard_write_paths(&kb_paths, &primary, &config.options),
+        None => Vec::new(),
+    };
+    let presses = match (params.effect.is_reactive(), extra.input) {
+        (false, _) => None,
+        (true, Some(path)) => Some(spawn_key_reader(&path)?),
+        (true, None) => Some(spawn_key_reader(&input_source()?)?),
+    };
+    let mut input_ended: Option<Instant> = None;
+    let mut renderer = Renderer::new(&KeyMap::resolve(&kb_paths), &targets);
+    let started = Instant::now();
+    loop {
+        let elapsed = started.elapsed().as_secs_f64();
+        if let Some(rx) = &presses {
+            loop {
+                match rx.try_recv() {
+                    Ok(name) => renderer.press(name, elapsed),
+                    Err(mpsc::TryRecvError::Empty) => break,
+                    Err(mpsc::TryRecvError::Disconnected) => {
+                        input_ended.get_or_insert_with(Instant::now);
+                        break;
+                    }
+                }
+            }
+        }
+        let frame = renderer.render(&params, elapsed);
+        match frame.keyboard {
+            Some(KeyboardFrame::Uniform((r, g, b))) => write_color_all(backend, &targets, r, g, b),
+            Some(KeyboardFrame::PerKey(changed)) => {
+                for (path, (r, g, b)) in changed {
+                    write_color(backend, &path, r, g, b);
+                }
+            }
+            None => {}
+        }
+        if let (Some(path), Some((r, g, b))) = (&lb_path, frame.lightbar) {
+            write_color(backend, path, r, g, b);
+        }
+        if !params.effect.is_animated() {
+            return Ok(());
+        }
+        // A finished recording ends the effect once the last presses faded out.
+        let faded = input_ended.is_some_and(|t| t.elapsed() >= PRESS_FADE);
+        if faded || extra.seconds.is_some_and(|s| started.elapsed() >= Duration::from_secs_f64(s)) {
+            return apply_lighting(backend, &config.keyboard, config.lightbar.as_ref(), &config.options);
+        }
+        thread::sleep(FRAME_INTERVAL);
+    }
+}
+
+fn restore(backend: &dyn LedBackend, file: Option<PathBuf>) -> Result<(), String> {
+    // An explicit file that doesn't exist yet (first login) falls back like no file.
+    let config: Config = match file.filter(|p| p.exists()) {
+        Some(path) => {
+            println!("Restoring {}", path.display());
+            read_config_or_legacy(&path)?
+        }
+        None => {
+            let (config, source) = load_config()?;
+            match source {
+                Some(path) => println!("Restoring {}", path.display()),
+                None => println!("No saved config, restoring defaults"),
+            }
+            config
+        }
+    };
+
+    apply_lighting(backend, &config.keyboard, config.lightbar.as_ref(), &config.options)
+}
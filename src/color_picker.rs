@@ -0,0 +1,139 @@
+use crate::config::Preset;
+use crate::leds::{format_hex, format_hsv, parse_color};
+use crate::rgba_to_rgb8;
+use gdk4::RGBA;
+use gtk4::prelude::*;
+use gtk4::{Box, ColorButton, DropDown, Entry, Label, Orientation, StringList};
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+const CUSTOM: &str = "Custom";
+const ENTRY_HELP: &str = "#rrggbb, r,g,b or hsv(h,s%,v%); press Enter to apply";
+
+/// Preset dropdown, GTK color chooser and a text entry for one color. The
+/// dropdown shows "Custom" whenever the color isn't one of the presets.
+pub struct ColorPicker {
+    pub widget: Box,
+    names: StringList,
+    colors: Rc<RefCell<Vec<(u8, u8, u8)>>>,
+    current: Rc<Cell<(u8, u8, u8)>>,
+    updating: Rc<Cell<bool>>,
+    show: Rc<dyn Fn((u8, u8, u8))>,
+}
+
+fn dropdown_names(presets: &[Preset]) -> Vec<&str> {
+    let mut names: Vec<&str> = presets.iter().map(|p| p.name.as_str()).collect();
+    names.push(CUSTOM);
+    names
+}
+
+impl ColorPicker {
+    pub fn new(
+        presets: &[Preset],
+        initial: (u8, u8, u8),
+        on_change: impl Fn((u8, u8, u8)) + 'static,
+    ) -> Self {
+        let widget = Box::new(Orientation::Horizontal, 10);
+        let names = StringList::new(&dropdown_names(presets));
+        let dropdown = DropDown::builder().model(&names).build();
+        let button = ColorButton::new();
+        button.set_tooltip_text(Some("Pick any color"));
+        let entry = Entry::new();
+        entry.set_width_chars(16);
+        entry.set_tooltip_text(Some(ENTRY_HELP));
+        let values = Label::new(None);
+        values.set_selectable(true);
+        widget.append(&dropdown);
+        widget.append(&button);
+        widget.append(&entry);
+        widget.append(&values);
+
+        let colors = Rc::new(RefCell::new(
+            presets.iter().map(|p| p.color.tuple()).collect::<Vec<_>>(),
+        ));
+        let current = Rc::new(Cell::new(initial));
+        // Set while the widgets are updated from code, so their signals don't echo.
+        let updating = Rc::new(Cell::new(false));
+        let show: Rc<dyn Fn((u8, u8, u8))> = {
+            let (dropdown, button, entry) = (dropdown.clone(), button.clone(), entry.clone());
+            let colors = Rc::clone(&colors);
+            let current = Rc::clone(&current);
+            let updating = Rc::clone(&updating);
+            Rc::new(move |rgb: (u8, u8, u8)| {
+                updating.set(true);
+                current.set(rgb);
+                let colors = colors.borrow();
+                let index = colors.iter().position(|c| *c == rgb).unwrap_or(colors.len());
+                dropdown.set_selected(index as u32);
+                let (r, g, b) = rgb;
+                button.set_rgba(&RGBA::new(
+                    r as f32 / 255.0,
+                    g as f32 / 255.0,
+                    b as f32 / 255.0,
+                    1.0,
+                ));
+                entry.set_text(&format_hex(rgb));
+                entry.remove_css_class("error");
+                entry.set_tooltip_text(Some(ENTRY_HELP));
+                values.set_label(&format!("{},{},{}  {}", r, g, b, format_hsv(rgb)));
+                updating.set(false);
+            })
+        };
+        show(initial);
+
+        let on_change = Rc::new(on_change);
+        let pick = {
+            let show = Rc::clone(&show);
+            move |rgb| {
+                show(rgb);
+                on_change(rgb);
+            }
+        };
+        let pick = Rc::new(pick);
+
+        {
+            let pick = Rc::clone(&pick);
+            let colors = Rc::clone(&colors);
+            let updating = Rc::clone(&updating);
+            dropdown.connect_selected_notify(move |dd| {
+                // Choosing "Custom" keeps the current color; the chooser or entry sets it.
+                let rgb = colors.borrow().get(dd.selected() as usize).copied();
+                if let (false, Some(rgb)) = (updating.get(), rgb) {
+                    pick(rgb);
+                }
+            });
+        }
+        {
+            let pick = Rc::clone(&pick);
+            let updating = Rc::clone(&updating);
+            button.connect_color_set(move |b| {
+                if !updating.get() {
+                    pick(rgba_to_rgb8(&b.rgba()));
+                }
+            });
+        }
+        entry.connect_activate(move |e| match parse_color(&e.text()) {
+            Ok(rgb) => pick(rgb),
+            Err(err) => {
+                e.add_css_class("error");
+                e.set_tooltip_text(Some(&err));
+            }
+        });
+
+        ColorPicker { widget, names, colors, current, updating, show }
+    }
+
+    /// Replaces the dropdown entries after the palette was edited.
+    pub fn set_presets(&self, presets: &[Preset]) {
+        self.updating.set(true);
+        *self.colors.borrow_mut() = presets.iter().map(|p| p.color.tuple()).collect();
+        self.names.splice(0, self.names.n_items(), &dropdown_names(presets));
+        self.updating.set(false);
+        (self.show)(self.current.get());
+    }
+
+    /// Shows `rgb` without calling `on_change`, e.g. after a profile switch.
+    pub fn set_color(&self, rgb: (u8, u8, u8)) {
+        (self.show)(rgb);
+    }
+}
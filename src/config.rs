@@ -0,0 +1,990 @@
+// User configuration in `~/.rusty-kb/config.toml`.
+//
+//   version = 1
+//
+//   [keyboard]
+//   color = "#ffffff"
+//   brightness = 30
+//   max_brightness = 50
+//
+//   [keyboard.keys]          # per-key overrides of the keyboard color
+//   esc = "#ff0000"
+//
+//   [lightbar]               # omitted when there is no lightbar
+//   color = "#00ffcc"
+//   brightness = 80
+//   max_brightness = 100
+//
+//   [[presets]]              # the color dropdowns, in this order
+//   name = "Red"
+//   color = "#ff0000"
+//
+//   [[profiles]]             # named snapshots of [keyboard] and [lightbar]
+//   name = "night"
+//   keyboard = { color = "#ff0000", brightness = 5, max_brightness = 50 }
+//   idle = { after_minutes = 1 }   # optional, replaces [idle] when activated
+//
+//   [idle]                   # dimming without input, see idle.rs
+//   after_minutes = 5        # 0 (the default) never dims
+//   brightness = 0           # percent of the normal brightness while idle
+//   logind = false           # also wait for logind's idle hint
+//
+//   [[schedule]]             # profiles activated by the daemon, see schedule.rs
+//   at = "sunset+30m"        # HH:MM, sunrise or sunset, with an optional offset
+//   profile = "night"
+//
+//   [location]               # for sunrise and sunset, in degrees (east positive)
+//   latitude = 52.37
+//   longitude = 4.90
+//
+//   [auto_brightness]        # follow an ambient light sensor, see ambient.rs
+//   curve = [[0, 100], [50, 40], [400, 0]]   # [lux, percent of max brightness]
+//   lightbar = true          # the lightbar follows too
+//   hysteresis = 5           # percentage points the target must move first
+//   smoothing_seconds = 5    # how quickly readings are followed
+//
+//   [options]
+//   primary_only = false     # same as KBD_WRITE_PRIMARY_ONLY=1
+//   keys = "wasd,arrows"     # same as KBD_KEYS
+
+use crate::layout::{key, parse_selection};
+use crate::leds::{format_hex, pack_rgb, parse_color, unpack_rgb};
+use crate::persist::{
+    load_key_colors, load_state, rusty_kb_dir, state_path, Brightness, PersistState,
+    DEFAULT_KB_MAX_BRIGHTNESS, DEFAULT_LB_MAX_BRIGHTNESS, write_atomically,
+};
+use crate::schedule::ScheduleTime;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+pub const CONFIG_VERSION: u32 = 1;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Rgb(pub u8, pub u8, pub u8);
+
+impl Rgb {
+    pub fn packed(self) -> u32 {
+        pack_rgb(self.0, self.1, self.2)
+    }
+
+    pub fn unpacked(v: u32) -> Self {
+        let (r, g, b) = unpack_rgb(v);
+        Rgb(r, g, b)
+    }
+
+    pub fn tuple(self) -> (u8, u8, u8) {
+        (self.0, self.1, self.2)
+    }
+}
+
+impl TryFrom<String> for Rgb {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, String> {
+        let (r, g, b) = parse_color(&s)?;
+        Ok(Rgb(r, g, b))
+    }
+}
+
+impl From<Rgb> for String {
+    fn from(c: Rgb) -> String {
+        format_hex(c.tuple())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct KeyboardConfig {
+    pub color: Rgb,
+    pub brightness: i32,
+    pub max_brightness: i32,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub keys: BTreeMap<String, Rgb>,
+}
+
+impl Default for KeyboardConfig {
+    fn default() -> Self {
+        KeyboardConfig {
+            color: Rgb(255, 255, 255),
+            brightness: DEFAULT_KB_MAX_BRIGHTNESS,
+            max_brightness: DEFAULT_KB_MAX_BRIGHTNESS,
+            keys: BTreeMap::new(),
+        }
+    }
+}
+
+impl KeyboardConfig {
+    pub fn brightness(&self) -> Brightness {
+        Brightness::new(self.brightness, self.max_brightness)
+    }
+
+    fn validate(&self, section: &str) -> Result<(), String> {
+        check_brightness(section, self.brightness, self.max_brightness)?;
+        for name in self.keys.keys() {
+            if key(name).is_none() {
+                return Err(format!("{}.keys: unknown key '{}'", section, name));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LightbarConfig {
+    pub color: Rgb,
+    pub brightness: i32,
+    pub max_brightness: i32,
+}
+
+impl LightbarConfig {
+    pub fn brightness(&self) -> Brightness {
+        Brightness::new(self.brightness, self.max_brightness)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Preset {
+    pub name: String,
+    pub color: Rgb,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct IdleConfig {
+    pub after_minutes: u32,
+    pub brightness: u8,
+    pub logind: bool,
+}
+
+impl IdleConfig {
+    pub fn is_default(&self) -> bool {
+        *self == IdleConfig::default()
+    }
+
+    fn validate(&self, section: &str) -> Result<(), String> {
+        if self.brightness > 100 {
+            return Err(format!(
+                "{}.brightness is a percentage, got {}",
+                section, self.brightness
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AutoBrightnessConfig {
+    /// `(lux, percent)` points, in order of lux. Empty turns auto brightness off.
+    pub curve: Vec<(f64, u8)>,
+    pub lightbar: bool,
+    pub hysteresis: u8,
+    pub smoothing_seconds: u32,
+}
+
+impl Default for AutoBrightnessConfig {
+    fn default() -> Self {
+        AutoBrightnessConfig { curve: Vec::new(), lightbar: true, hysteresis: 5, smoothing_seconds: 5 }
+    }
+}
+
+impl AutoBrightnessConfig {
+    pub fn is_default(&self) -> bool {
+        *self == AutoBrightnessConfig::default()
+    }
+
+    fn validate(&self, section: &str) -> Result<(), String> {
+        for pair in self.curve.windows(2) {
+            if pair[1].0 <= pair[0].0 {
+                return Err(format!("{}.curve: lux must go up from point to point", section));
+            }
+        }
+        if let Some((lux, percent)) = self.curve.iter().find(|(lux, percent)| *lux < 0.0 || *percent > 100) {
+            return Err(format!(
+                "{}.curve: [{}, {}] needs lux of at least 0 and a percentage",
+                section, lux, percent
+            ));
+        }
+        if self.hysteresis > 100 {
+            return Err(format!("{}.hysteresis is in percentage points, got {}", section, self.hysteresis));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScheduleEntry {
+    pub at: String,
+    pub profile: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Location {
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    pub name: String,
+    pub keyboard: KeyboardConfig,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lightbar: Option<LightbarConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub idle: Option<IdleConfig>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Options {
+    pub primary_only: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keys: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub version: u32,
+    pub keyboard: KeyboardConfig,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lightbar: Option<LightbarConfig>,
+    pub presets: Vec<Preset>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub profiles: Vec<Profile>,
+    #[serde(skip_serializing_if = "IdleConfig::is_default")]
+    pub idle: IdleConfig,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub schedule: Vec<ScheduleEntry>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub location: Option<Location>,
+    #[serde(skip_serializing_if = "AutoBrightnessConfig::is_default")]
+    pub auto_brightness: AutoBrightnessConfig,
+    pub options: Options,
+}
+
+pub fn builtin_presets() -> Vec<Preset> {
+    [
+        ("Red", Rgb(255, 0, 0)),
+        ("Blue", Rgb(0, 0, 255)),
+        ("Green", Rgb(0, 255, 0)),
+        ("Pink", Rgb(255, 105, 181)),
+        ("Orange", Rgb(255, 128, 0)),
+        ("Light Blue", Rgb(0, 153, 255)),
+    ]
+    .into_iter()
+    .map(|(name, color)| Preset { name: name.to_string(), color })
+    .collect()
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            version: CONFIG_VERSION,
+            keyboard: KeyboardConfig::default(),
+            lightbar: None,
+            presets: builtin_presets(),
+            profiles: Vec::new(),
+            idle: IdleConfig::default(),
+            schedule: Vec::new(),
+            location: None,
+            auto_brightness: AutoBrightnessConfig::default(),
+            options: Options::default(),
+        }
+    }
+}
+
+fn check_names<'a>(section: &str, names: impl Iterator<Item = &'a str>) -> Result<(), String> {
+    let mut seen = BTreeSet::new();
+    for name in names {
+        if name.trim().is_empty() {
+            return Err(format!("{}: name can't be empty", section));
+        }
+        if !seen.insert(name) {
+            return Err(format!("{}: duplicate name '{}'", section, name));
+        }
+    }
+    Ok(())
+}
+
+fn check_brightness(section: &str, value: i32, max: i32) -> Result<(), String> {
+    if max <= 0 {
+        return Err(format!("{}.max_brightness must be positive, got {}", section, max));
+    }
+    if !(0..=max).contains(&value) {
+        return Err(format!(
+            "{}.brightness must be between 0 and {}, got {}",
+            section, max, value
+        ));
+    }
+    Ok(())
+}
+
+impl Config {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.version != CONFIG_VERSION {
+            return Err(format!(
+                "unsupported config version {} (expected {})",
+                self.version, CONFIG_VERSION
+            ));
+        }
+        self.keyboard.validate("keyboard")?;
+        if let Some(lb) = &self.lightbar {
+            check_brightness("lightbar", lb.brightness, lb.max_brightness)?;
+        }
+        check_names("presets", self.presets.iter().map(|p| p.name.as_str()))?;
+        check_names("profiles", self.profiles.iter().map(|p| p.name.as_str()))?;
+        self.idle.validate("idle")?;
+        self.auto_brightness.validate("auto_brightness")?;
+        if let Some(location) = &self.location
+            && (!(-90.0..=90.0).contains(&location.latitude)
+                || !(-180.0..=180.0).contains(&location.longitude))
+        {
+            return Err("location: latitude must be within ±90 and longitude within ±180".to_string());
+        }
+        for entry in &self.schedule {
+            let at = ScheduleTime::parse(&entry.at).map_err(|e| format!("schedule: {}", e))?;
+            if at.needs_location() && self.location.is_none() {
+                return Err(format!("schedule: '{}' needs a [location]", entry.at));
+            }
+        }
+        for profile in &self.profiles {
+            let section = format!("profiles.{}", profile.name);
+            profile.keyboard.validate(&format!("{}.keyboard", section))?;
+            if let Some(lb) = &profile.lightbar {
+                check_brightness(&format!("{}.lightbar", section), lb.brightness, lb.max_brightness)?;
+            }
+            if let Some(idle) = &profile.idle {
+                idle.validate(&format!("{}.idle", section))?;
+            }
+        }
+        if let Some(spec) = &self.options.keys {
+            parse_selection(spec).map_err(|e| format!("options.keys: {}", e))?;
+        }
+        Ok(())
+    }
+
+    pub fn parse(content: &str) -> Result<Self, String> {
+        let config: Config = toml::from_str(content).map_err(|e| e.to_string())?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn to_toml(&self) -> String {
+        toml::to_string_pretty(self).expect("config is always serializable")
+    }
+
+    /// Brings over a legacy `colors.txt` state and the painter's `keys.txt`.
+    pub fn from_legacy(state: &PersistState, keys: &[(String, (u8, u8, u8))]) -> Self {
+        let mut config = Config::default();
+        config.set_state(
+            state.kb_color,
+            state.kb_brightness,
+            state.lb_color,
+            state.lb_brightness,
+        );
+        config.keyboard.keys = keys
+            .iter()
+            .filter(|(name, _)| key(name).is_some())
+            .map(|(name, (r, g, b))| (name.clone(), Rgb(*r, *g, *b)))
+            .collect();
+        config
+    }
+
+    /// Records the live keyboard and lightbar state, as the window and CLI see it.
+    pub fn set_state(
+        &mut self,
+        kb_color: u32,
+        kb_brightness: Brightness,
+        lb_color: Option<u32>,
+        lb_brightness: Option<Brightness>,
+    ) {
+        self.keyboard.color = Rgb::unpacked(kb_color);
+        self.keyboard.brightness = kb_brightness.value;
+        self.keyboard.max_brightness = kb_brightness.max;
+        self.lightbar = match (lb_color, lb_brightness) {
+            (Some(color), brightness) => {
+                let brightness = brightness
+                    .unwrap_or(Brightness::new(0, DEFAULT_LB_MAX_BRIGHTNESS));
+                Some(LightbarConfig {
+                    color: Rgb::unpacked(color),
+                    brightness: brightness.value,
+                    max_brightness: brightness.max,
+                })
+            }
+            (None, _) => None,
+        };
+    }
+
+    pub fn profile(&self, name: &str) -> Option<&Profile> {
+        self.profiles.iter().find(|p| p.name == name)
+    }
+
+    pub fn profile_names(&self) -> Vec<String> {
+        self.profiles.iter().map(|p| p.name.clone()).collect()
+    }
+
+    /// Stores the current keyboard and lightbar as `name`, replacing a profile
+    /// of the same name.
+    pub fn save_profile(&mut self, name: &str) -> Result<(), String> {
+        let name = name.trim();
+        if name.is_empty() {
+            return Err("profile name can't be empty".to_string());
+        }
+        let profile = Profile {
+            name: name.to_string(),
+            keyboard: self.keyboard.clone(),
+            lightbar: self.lightbar.clone(),
+            idle: Some(self.idle.clone()),
+        };
+        match self.profiles.iter_mut().find(|p| p.name == name) {
+            Some(existing) => *existing = profile,
+            None => self.profiles.push(profile),
+        }
+        Ok(())
+    }
+
+    pub fn delete_profile(&mut self, name: &str) -> Result<(), String> {
+        let before = self.profiles.len();
+        self.profiles.retain(|p| p.name != name);
+        if self.profiles.len() == before {
+            return Err(format!("no profile named '{}'", name));
+        }
+        Ok(())
+    }
+
+    fn preset_index(&self, name: &str) -> Result<usize, String> {
+        self.presets
+            .iter()
+            .position(|p| p.name == name)
+            .ok_or_else(|| format!("no preset named '{}'", name))
+    }
+
+    /// Adds a swatch at the end of the palette, or recolors the one named `name`.
+    pub fn add_preset(&mut self, name: &str, color: Rgb) -> Result<(), String> {
+        let name = name.trim();
+        if name.is_empty() {
+            return Err("preset name can't be empty".to_string());
+        }
+        match self.presets.iter_mut().find(|p| p.name == name) {
+            Some(existing) => existing.color = color,
+            None => self.presets.push(Preset { name: name.to_string(), color }),
+        }
+        Ok(())
+    }
+
+    pub fn rename_preset(&mut self, name: &str, new_name: &str) -> Result<(), String> {
+        let new_name = new_name.trim();
+        if new_name.is_empty() {
+            return Err("preset name can't be empty".to_string());
+        }
+        let index = self.preset_index(name)?;
+        if new_name != name && self.presets.iter().any(|p| p.name == new_name) {
+            return Err(format!("a preset named '{}' already exists", new_name));
+        }
+        self.presets[index].name = new_name.to_string();
+        Ok(())
+    }
+
+    pub fn delete_preset(&mut self, name: &str) -> Result<(), String> {
+        let index = self.preset_index(name)?;
+        self.presets.remove(index);
+        Ok(())
+    }
+
+    /// Moves preset `name` to `position` (0 is the top of the dropdowns).
+    pub fn move_preset(&mut self, name: &str, position: usize) -> Result<(), String> {
+        let index = self.preset_index(name)?;
+        let preset = self.presets.remove(index);
+        let position = position.min(self.presets.len());
+        self.presets.insert(position, preset);
+        Ok(())
+    }
+
+    /// Merges an imported palette: swatches with a known name are recolored, the
+    /// rest are appended. Returns how many were new.
+    pub fn import_presets(&mut self, presets: Vec<Preset>) -> usize {
+        let before = self.presets.len();
+        for preset in presets {
+            // Imported names are already trimmed and non-empty.
+            let _ = self.add_preset(&preset.name, preset.color);
+        }
+        self.presets.len() - before
+    }
+
+    /// Makes profile `name` the current state. A profile saved without a lightbar
+    /// or idle settings leaves those as they are.
+    pub fn activate_profile(&mut self, name: &str) -> Result<(), String> {
+        let profile = self
+            .profile(name)
+            .cloned()
+            .ok_or_else(|| format!("no profile named '{}'", name))?;
+        self.keyboard = profile.keyboard;
+        if profile.lightbar.is_some() {
+            self.lightbar = profile.lightbar;
+        }
+        if let Some(idle) = profile.idle {
+            self.idle = idle;
+        }
+        Ok(())
+    }
+}
+
+// Shipped defaults, used when the user never saved anything.
+pub const DEFAULT_CONFIG_PATH: &str = "/usr/lib/rusty-kb/config.toml";
+
+pub fn config_path() -> Option<PathBuf> {
+    Some(rusty_kb_dir()?.join("config.toml"))
+}
+
+/// The last config that was replaced by a save, kept in case `config.toml`
+/// gets damaged.
+pub fn backup_path(path: &Path) -> PathBuf {
+    path.with_extension("toml.bak")
+}
+
+pub fn read_config(path: &Path) -> Result<Config, String> {
+    let content =
+        fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    Config::parse(&content).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+/// Reads a config file, or imports it if it is a legacy `colors.txt` state file.
+pub fn read_config_or_legacy(path: &Path) -> Result<Config, String> {
+    if path.extension().is_some_and(|ext| ext == "toml") {
+        read_config(path)
+    } else {
+        Ok(Config::from_legacy(&load_state(path)?, &load_key_colors()))
+    }
+}
+
+/// The user's config. Without a `config.toml`, the legacy `~/.rusty-kb/colors.txt`
+/// is imported (and written out as `config.toml`), then the shipped defaults are
+/// used. Returns the config and where it came from.
+pub fn load_config() -> Result<(Config, Option<PathBuf>), String> {
+    if let Some(path) = config_path().filter(|p| p.exists()) {
+        return match read_config(&path) {
+            Ok(config) => Ok((config, Some(path))),
+            Err(e) => recover_from_backup(&path, e),
+        };
+    }
+    if let Some(legacy) = state_path().filter(|p| p.exists()) {
+        let config = read_config_or_legacy(&legacy)?;
+        match save_config(&config) {
+            Ok(()) => println!("Imported {} into config.toml", legacy.display()),
+            Err(e) => eprintln!("Error: could not write config.toml: {}", e),
+        }
+        return Ok((config, Some(legacy)));
+    }
+    let defaults = PathBuf::from(DEFAULT_CONFIG_PATH);
+    if defaults.exists() {
+        return Ok((read_config_or_legacy(&defaults)?, Some(defaults)));
+    }
+    Ok((Config::default(), None))
+}
+
+// Falls back to the backup when `config.toml` doesn't parse. The damaged file
+// is moved to `config.toml.broken` so the next save doesn't destroy it.
+fn recover_from_backup(path: &Path, error: String) -> Result<(Config, Option<PathBuf>), String> {
+    let backup = backup_path(path);
+    if !backup.exists() {
+        return Err(error);
+    }
+    let config = read_config(&backup).map_err(|_| error.clone())?;
+    eprintln!("Error: {}", error);
+    let broken = path.with_extension("toml.broken");
+    match fs::rename(path, &broken) {
+        Ok(()) => eprintln!("Moved it to {}, using {}", broken.display(), backup.display()),
+        Err(e) => eprintln!("Error: could not move {}: {}", path.display(), e),
+    }
+    Ok((config, Some(backup)))
+}
+
+// Held while saving, so two saves in one process can't interleave their
+// backup and replace steps.
+static SAVING: Mutex<()> = Mutex::new(());
+
+// Locks `config.toml` against saves of other processes (the window, the daemon
+// and the command line all save it) until the returned file is dropped.
+fn lock_config(path: &Path) -> io::Result<fs::File> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let lock = fs::File::create(path.with_extension("lock"))?;
+    lock.lock()?;
+    Ok(lock)
+}
+
+// Replaces `path` with `config`, keeping the previous version as the backup if
+// it was intact.
+fn write_config(path: &Path, config: &Config) -> io::Result<()> {
+    let contents = config.to_toml();
+    if let Ok(previous) = fs::read_to_string(path)
+        && previous != contents
+        && Config::parse(&previous).is_ok()
+    {
+        write_atomically(&backup_path(path), &previous)?;
+    }
+    write_atomically(path, &contents)
+}
+
+/// Saves `config` to `config.toml`. The file is replaced atomically, and the
+/// previous version goes to `config.toml.bak` if it was intact.
+pub fn save_config(config: &Config) -> io::Result<()> {
+    let _saving = SAVING.lock().unwrap();
+    let path = config_path()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "HOME is not set"))?;
+    let _lock = lock_config(&path)?;
+    write_config(&path, config)
+}
+
+fn merge_field<T: Clone + PartialEq>(base: &T, ours: &T, theirs: &T) -> T {
+    if ours != base { ours.clone() } else { theirs.clone() }
+}
+
+/// Three-way merge of two processes' changes to one config: whatever `ours`
+/// changed since `base` (the config as it was read or last saved) wins, the
+/// rest comes from `theirs`. Colors, brightness and per-key colors merge
+/// separately; lists and the other sections as a whole.
+pub fn merge(base: &Config, ours: &Config, theirs: &Config) -> Config {
+    let brightness = |c: &Config| (c.keyboard.brightness, c.keyboard.max_brightness);
+    let (brightness, max_brightness) = merge_field(&brightness(base), &brightness(ours), &brightness(theirs));
+    let keyboard = KeyboardConfig {
+        color: merge_field(&base.keyboard.color, &ours.keyboard.color, &theirs.keyboard.color),
+        brightness,
+        max_brightness,
+        keys: merge_field(&base.keyboard.keys, &ours.keyboard.keys, &theirs.keyboard.keys),
+    };
+    let lightbar = match (&base.lightbar, &ours.lightbar, &theirs.lightbar) {
+        (Some(base), Some(ours), Some(theirs)) => {
+            let brightness = |c: &LightbarConfig| (c.brightness, c.max_brightness);
+            let (brightness, max_brightness) =
+                merge_field(&brightness(base), &brightness(ours), &brightness(theirs));
+            Some(LightbarConfig {
+                color: merge_field(&base.color, &ours.color, &theirs.color),
+                brightness,
+                max_brightness,
+            })
+        }
+        (base, ours, theirs) => merge_field(base, ours, theirs),
+    };
+    Config {
+        version: theirs.version,
+        keyboard,
+        lightbar,
+        presets: merge_field(&base.presets, &ours.presets, &theirs.presets),
+        profiles: merge_field(&base.profiles, &ours.profiles, &theirs.profiles),
+        idle: merge_field(&base.idle, &ours.idle, &theirs.idle),
+        schedule: merge_field(&base.schedule, &ours.schedule, &theirs.schedule),
+        location: merge_field(&base.location, &ours.location, &theirs.location),
+        auto_brightness: merge_field(&base.auto_brightness, &ours.auto_brightness, &theirs.auto_brightness),
+        options: merge_field(&base.options, &ours.options, &theirs.options),
+    }
+}
+
+/// Saves the changes from `base` to `config` into `config.toml`, on top of what
+/// other processes saved meanwhile (see `merge`). Returns what was written. A
+/// `config.toml` that can't be read or doesn't parse is left alone.
+pub fn save_merged(base: &Config, config: &Config) -> Result<Config, String> {
+    let _saving = SAVING.lock().unwrap();
+    let path = config_path().ok_or("HOME is not set")?;
+    let _lock = lock_config(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let merged = match fs::read_to_string(&path) {
+        Ok(content) => {
+            let theirs = Config::parse(&content)
+                .map_err(|e| format!("{}: {}", path.display(), e))?;
+            merge(base, config, &theirs)
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => config.clone(),
+        Err(e) => return Err(format!("{}: {}", path.display(), e)),
+    };
+    write_config(&path, &merged).map_err(|e| format!("{}: {}", path.display(), e))?;
+    Ok(merged)
+}
+
+/// Like `load_config`, but never fails: a `config.toml` that doesn't parse is
+/// moved aside to `config.toml.broken` so the next save doesn't overwrite it, and
+/// the defaults are used. Returns the error to show to the user.
+pub fn load_config_or_default() -> (Config, Option<String>) {
+    match load_config() {
+        Ok((config, _)) => (config, None),
+        Err(e) => {
+            let mut message = e;
+            let damaged = |p: &PathBuf| fs::read_to_string(p).is_ok_and(|c| Config::parse(&c).is_err());
+            if let Some(path) = config_path().filter(damaged) {
+                let broken = path.with_extension("toml.broken");
+                match fs::rename(&path, &broken) {
+                    Ok(()) => message.push_str(&format!("\nMoved it to {}", broken.display())),
+                    Err(e) => eprintln!("Error: could not move {}: {}", path.display(), e),
+                }
+            }
+            (Config::default(), Some(message))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp_dir::TempDir;
+
+    const SAMPLE: &str = r##"
+version = 1
+
+[keyboard]
+color = "#ff0000"
+brightness = 30
+max_brightness = 50
+
+[keyboard.keys]
+esc = "0,0,255"
+
+[lightbar]
+color = "00ff00"
+brightness = 80
+max_brightness = 100
+
+[options]
+keys = "wasd"
+"##;
+
+    fn invalid(change: impl FnOnce(&mut Config)) -> String {
+        let mut config = Config::default();
+        change(&mut config);
+        config.validate().expect_err("invalid config passed")
+    }
+
+    #[test]
+    fn parses_and_writes_back_a_config() {
+        let config = Config::parse(SAMPLE).unwrap();
+        assert_eq!(config.keyboard.keys["esc"], Rgb(0, 0, 255));
+        assert_eq!(config.lightbar.as_ref().unwrap().color, Rgb(0, 255, 0));
+        assert_eq!(config.options.keys.as_deref(), Some("wasd"));
+        assert_eq!(config.presets, builtin_presets());
+        assert_eq!(Config::parse(&config.to_toml()), Ok(config));
+        assert_eq!(Config::parse(""), Ok(Config::default()));
+    }
+
+    #[test]
+    fn rejects_unknown_settings() {
+        assert!(Config::parse("[keyboard]\ncolour = \"#ffffff\"").is_err());
+        assert!(Config::parse("[mouse]\ncolor = \"#ffffff\"").is_err());
+        assert!(Config::parse("[keyboard]\ncolor = \"white\"\nbrightness = 1\nmax_brightness = 2").is_err());
+    }
+
+    #[test]
+    fn validates_settings() {
+        assert!(invalid(|c| c.version = 2).contains("version"));
+        assert!(invalid(|c| c.keyboard.brightness = 51).contains("keyboard.brightness"));
+        assert!(invalid(|c| c.keyboard.max_brightness = 0).contains("keyboard.max_brightness"));
+        assert!(invalid(|c| {
+            c.keyboard.keys.insert("nokey".to_string(), Rgb(0, 0, 0));
+        })
+        .contains("nokey"));
+        assert!(invalid(|c| {
+            c.lightbar = Some(LightbarConfig { color: Rgb(0, 0, 0), brightness: -1, max_brightness: 100 })
+        })
+        .contains("lightbar.brightness"));
+        assert!(invalid(|c| c.presets[1].name = "Red".to_string()).contains("duplicate"));
+        assert!(invalid(|c| c.presets[0].name = " ".to_string()).contains("empty"));
+        assert!(invalid(|c| c.idle.brightness = 101).contains("idle.brightness"));
+        assert!(invalid(|c| c.location = Some(Location { latitude: 91.0, longitude: 0.0 })).contains("location"));
+        assert!(invalid(|c| c.options.keys = Some("nokey".to_string())).contains("options.keys"));
+    }
+
+    #[test]
+    fn imports_legacy_state_and_keys() {
+        let state = PersistState {
+            kb_color: pack_rgb(1, 2, 3),
+            kb_brightness: Brightness::new(4, 5),
+            lb_color: None,
+            lb_brightness: None,
+        };
+        let keys = [("w".to_string(), (9, 9, 9)), ("nokey".to_string(), (1, 1, 1))];
+        let config = Config::from_legacy(&state, &keys);
+        assert_eq!(config.keyboard.color, Rgb(1, 2, 3));
+        assert_eq!(config.keyboard.brightness(), Brightness::new(4, 5));
+        assert_eq!(config.lightbar, None);
+        assert_eq!(config.keyboard.keys.keys().collect::<Vec<_>>(), ["w"]);
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn saves_and_activates_profiles() {
+        let mut config = Config::parse(SAMPLE).unwrap();
+        config.save_profile(" night ").unwrap();
+        assert_eq!(config.profile_names(), ["night"]);
+        assert_eq!(config.profile("night").unwrap().keyboard, config.keyboard);
+
+        // Saving under a taken name replaces that profile.
+        config.keyboard.brightness = 5;
+        config.lightbar = None;
+        config.save_profile("night").unwrap();
+        assert_eq!(config.profiles.len(), 1);
+        assert_eq!(config.profiles[0].keyboard.brightness, 5);
+        assert_eq!(config.profiles[0].lightbar, None);
+        assert!(config.save_profile("  ").unwrap_err().contains("empty"));
+
+        let mut day = Config::parse(SAMPLE).unwrap();
+        day.profiles = config.profiles.clone();
+        day.activate_profile("night").unwrap();
+        assert_eq!(day.keyboard.brightness, 5);
+        // The profile has no lightbar, so the current one stays.
+        assert_eq!(day.lightbar, Config::parse(SAMPLE).unwrap().lightbar);
+        assert!(day.activate_profile("day").unwrap_err().contains("no profile named 'day'"));
+    }
+
+    #[test]
+    fn deletes_profiles() {
+        let mut config = Config::default();
+        config.save_profile("a").unwrap();
+        config.save_profile("b").unwrap();
+        config.delete_profile("a").unwrap();
+        assert_eq!(config.profile_names(), ["b"]);
+        assert!(config.delete_profile("a").unwrap_err().contains("no profile named 'a'"));
+    }
+
+    #[test]
+    fn validates_profiles() {
+        let mut config = Config::default();
+        config.save_profile("night").unwrap();
+        config.profiles.push(config.profiles[0].clone());
+        assert!(config.validate().unwrap_err().contains("duplicate"));
+        config.profiles.pop();
+        config.profiles[0].keyboard.brightness = 60;
+        assert!(config.validate().unwrap_err().contains("profiles.night.keyboard"));
+        config.profiles[0].keyboard.brightness = 5;
+        config.profiles[0].idle = Some(IdleConfig { brightness: 200, ..Default::default() });
+        assert!(config.validate().unwrap_err().contains("profiles.night.idle"));
+    }
+
+    #[test]
+    fn validates_the_schedule() {
+        let entry = |at: &str| ScheduleEntry { at: at.to_string(), profile: "night".to_string() };
+        assert!(invalid(|c| c.schedule.push(entry("25:00"))).starts_with("schedule:"));
+        assert!(invalid(|c| c.schedule.push(entry("sunrise"))).contains("needs a [location]"));
+    }
+
+    #[test]
+    fn validates_auto_brightness() {
+        assert!(invalid(|c| c.auto_brightness.curve = vec![(10.0, 50), (10.0, 40)]).contains("go up"));
+        assert!(invalid(|c| c.auto_brightness.curve = vec![(-1.0, 50)]).contains("lux"));
+        assert!(invalid(|c| c.auto_brightness.curve = vec![(0.0, 101)]).contains("percentage"));
+        assert!(invalid(|c| c.auto_brightness.hysteresis = 101).contains("hysteresis"));
+    }
+
+    fn preset_names(config: &Config) -> Vec<&str> {
+        config.presets.iter().map(|p| p.name.as_str()).collect()
+    }
+
+    #[test]
+    fn edits_presets() {
+        let mut config = Config::default();
+        config.add_preset(" Amber ", Rgb(255, 191, 0)).unwrap();
+        assert_eq!(config.presets.last(), Some(&Preset { name: "Amber".to_string(), color: Rgb(255, 191, 0) }));
+        // Adding a known name recolors it.
+        config.add_preset("Red", Rgb(200, 0, 0)).unwrap();
+        assert_eq!(config.presets[0].color, Rgb(200, 0, 0));
+        assert!(config.add_preset("  ", Rgb(0, 0, 0)).is_err());
+
+        config.move_preset("Amber", 0).unwrap();
+        config.move_preset("Red", 99).unwrap();
+        assert_eq!(preset_names(&config), ["Amber", "Blue", "Green", "Pink", "Orange", "Light Blue", "Red"]);
+
+        config.rename_preset("Blue", "Navy").unwrap();
+        config.rename_preset("Navy", "Navy").unwrap();
+        assert!(config.rename_preset("Navy", "Green").unwrap_err().contains("already exists"));
+        assert!(config.rename_preset("Navy", "").is_err());
+        assert!(config.rename_preset("Teal", "Cyan").unwrap_err().contains("no preset named 'Teal'"));
+
+        config.delete_preset("Navy").unwrap();
+        assert!(config.delete_preset("Navy").is_err());
+        assert!(config.move_preset("Navy", 0).is_err());
+        assert_eq!(preset_names(&config), ["Amber", "Green", "Pink", "Orange", "Light Blue", "Red"]);
+    }
+
+    #[test]
+    fn imports_presets_over_known_names() {
+        let mut config = Config::default();
+        let imported = vec![
+            Preset { name: "Green".to_string(), color: Rgb(0, 128, 0) },
+            Preset { name: "Teal".to_string(), color: Rgb(0, 128, 128) },
+        ];
+        assert_eq!(config.import_presets(imported), 1);
+        assert_eq!(config.presets.len(), builtin_presets().len() + 1);
+        assert_eq!(config.presets[2].color, Rgb(0, 128, 0));
+        assert_eq!(config.presets.last().unwrap().name, "Teal");
+    }
+
+    #[test]
+    fn merge_keeps_the_changes_of_both_sides() {
+        let base = Config::parse(SAMPLE).unwrap();
+        let mut ours = base.clone();
+        ours.keyboard.brightness = 10;
+        ours.lightbar.as_mut().unwrap().color = Rgb(1, 2, 3);
+        let mut theirs = base.clone();
+        theirs.keyboard.color = Rgb(0, 0, 0);
+        theirs.keyboard.keys.clear();
+        theirs.lightbar.as_mut().unwrap().brightness = 20;
+        theirs.presets.pop();
+
+        let merged = merge(&base, &ours, &theirs);
+        assert_eq!(merged.keyboard.brightness, 10);
+        assert_eq!(merged.keyboard.color, Rgb(0, 0, 0));
+        assert!(merged.keyboard.keys.is_empty());
+        let lightbar = merged.lightbar.unwrap();
+        assert_eq!((lightbar.color, lightbar.brightness), (Rgb(1, 2, 3), 20));
+        assert_eq!(merged.presets, theirs.presets);
+        assert_eq!(merged.profiles, base.profiles);
+    }
+
+    #[test]
+    fn merge_prefers_our_side_of_a_conflict() {
+        let base = Config::default();
+        let mut ours = base.clone();
+        ours.keyboard.brightness = 10;
+        ours.keyboard.max_brightness = 20;
+        let mut theirs = base.clone();
+        theirs.keyboard.brightness = 40;
+        theirs.lightbar = Some(LightbarConfig { color: Rgb(0, 0, 0), brightness: 1, max_brightness: 2 });
+
+        let merged = merge(&base, &ours, &theirs);
+        // Brightness and its maximum move together.
+        assert_eq!((merged.keyboard.brightness, merged.keyboard.max_brightness), (10, 20));
+        assert_eq!(merged.lightbar, theirs.lightbar);
+        assert_eq!(merge(&base, &base, &theirs), theirs);
+        assert_eq!(merge(&base, &ours, &base), ours);
+    }
+
+    #[test]
+    fn recovers_from_the_backup() {
+        let dir = TempDir::new("config");
+        let path = dir.join("config.toml");
+        let mut config = Config::default();
+        write_config(&path, &config).unwrap();
+        config.keyboard.brightness = 7;
+        write_config(&path, &config).unwrap();
+        assert_eq!(read_config(&backup_path(&path)).unwrap(), Config::default());
+
+        fs::write(&path, "[keyboard\n").unwrap();
+        let error = read_config(&path).unwrap_err();
+        let (recovered, from) = recover_from_backup(&path, error).unwrap();
+        assert_eq!(recovered, Config::default());
+        assert_eq!(from, Some(backup_path(&path)));
+        assert!(dir.join("config.toml.broken").exists());
+        // A damaged file never replaces the backup.
+        fs::write(&path, "[keyboard\n").unwrap();
+        write_config(&path, &config).unwrap();
+        assert_eq!(read_config(&backup_path(&path)).unwrap(), Config::default());
+    }
+}
@@ -0,0 +1,408 @@
+// Session bus interface for status bars, scripts and session hooks, served by
+// `lwl-x6-keyboard daemon`. Changes go through the same coalescing workers and
+// persistence worker as the window, so they are remembered for the next login.
+
+use crate::ambient::{spawn_auto_brightness, BrightnessOutput};
+use crate::backend::LedBackend;
+use crate::config::{load_config_or_default, Config, LightbarConfig, Rgb};
+use crate::hotplug::follow_hotplug;
+use crate::hw_brightness::spawn_brightness_watcher;
+use crate::idle::spawn_idle_timer;
+use crate::layout::KeyMap;
+use crate::leds::{
+    apply_lighting, find_kb_paths, find_lightbar, keyboard_write_paths, parse_color,
+    pick_primary, read_brightness, read_max_brightness,
+};
+use crate::persist::{
+    spawn_persistence_worker, update_config, SharedConfig, DEFAULT_KB_MAX_BRIGHTNESS,
+    DEFAULT_LB_MAX_BRIGHTNESS,
+};
+use crate::power::spawn_power_watcher;
+use crate::schedule::spawn_scheduler;
+use crate::workers::{
+    flush_workers, notify_coalescer, shared_targets, spawn_kb_brightness_worker,
+    spawn_kb_color_worker, spawn_key_color_worker, spawn_lb_brightness_worker,
+    spawn_lb_color_worker, Targets,
+};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicU32, Ordering};
+use std::sync::{mpsc, Arc, Mutex, OnceLock};
+use std::thread;
+use std::time::Duration;
+use zbus::fdo;
+use zbus::object_server::SignalEmitter;
+use zbus::zvariant::{OwnedValue, Value};
+
+pub const BUS_NAME: &str = "com.example.RustyKb";
+pub const OBJECT_PATH: &str = "/com/example/RustyKb";
+// Must match the `#[zbus::interface]` name below.
+const INTERFACE: &str = "com.example.RustyKb.Lighting";
+
+struct KeyboardControl {
+    color: Arc<AtomicU32>,
+    tx_color: mpsc::SyncSender<()>,
+    brightness: Arc<AtomicI32>,
+    tx_brightness: mpsc::SyncSender<()>,
+    keys: Arc<Mutex<BTreeMap<PathBuf, u32>>>,
+    tx_keys: mpsc::SyncSender<()>,
+    key_map: KeyMap,
+    targets: Targets,
+    max: i32,
+}
+
+// Exists whether or not there is a lightbar: one can appear later.
+struct LightbarControl {
+    targets: Targets,
+    color: Arc<AtomicU32>,
+    tx_color: mpsc::SyncSender<()>,
+    brightness: Arc<AtomicI32>,
+    tx_brightness: mpsc::SyncSender<()>,
+}
+
+pub struct Lighting {
+    backend: Arc<dyn LedBackend>,
+    keyboard: Option<KeyboardControl>,
+    lightbar: LightbarControl,
+    config: SharedConfig,
+    tx_persist: mpsc::SyncSender<()>,
+}
+
+fn owned<'a>(value: impl Into<Value<'a>>) -> OwnedValue {
+    value.into().try_into().expect("state holds no file descriptors")
+}
+
+fn invalid(e: String) -> fdo::Error {
+    fdo::Error::InvalidArgs(e)
+}
+
+/// The state as sent by `GetState` and `StateChanged`.
+fn state_dict(config: &Config) -> HashMap<String, OwnedValue> {
+    let mut state = HashMap::new();
+    let kb = &config.keyboard;
+    state.insert("keyboard_color".to_string(), owned(String::from(kb.color)));
+    state.insert("keyboard_brightness".to_string(), owned(kb.brightness));
+    state.insert("keyboard_max_brightness".to_string(), owned(kb.max_brightness));
+    let keys: HashMap<String, String> = kb
+        .keys
+        .iter()
+        .map(|(name, color)| (name.clone(), String::from(*color)))
+        .collect();
+    state.insert("key_colors".to_string(), owned(keys));
+    if let Some(lb) = &config.lightbar {
+        state.insert("lightbar_color".to_string(), owned(String::from(lb.color)));
+        state.insert("lightbar_brightness".to_string(), owned(lb.brightness));
+        state.insert("lightbar_max_brightness".to_string(), owned(lb.max_brightness));
+    }
+    state.insert("profiles".to_string(), owned(config.profile_names()));
+    state
+}
+
+impl Lighting {
+    /// `on_outside_change` is called when another process saved the config.
+    pub fn new(
+        backend: Arc<dyn LedBackend>,
+        on_outside_change: impl Fn(&Config) + Send + Sync + 'static,
+    ) -> Self {
+        let (config, config_error) = load_config_or_default();
+        if let Some(e) = config_error {
+            eprintln!("Error: {}", e);
+        }
+        let kb_paths = find_kb_paths(backend.as_ref());
+        let kb_targets = shared_targets(Vec::new());
+        let keyboard = pick_primary(&kb_paths).map(|primary| {
+            *kb_targets.lock().unwrap() = keyboard_write_paths(&kb_paths, &primary, &config.options);
+            let (color, tx_color) =
+                spawn_kb_color_worker(Arc::clone(&backend), Arc::clone(&kb_targets));
+            let (brightness, tx_brightness) =
+                spawn_kb_brightness_worker(Arc::clone(&backend), Arc::clone(&kb_targets));
+            let (keys, tx_keys) = spawn_key_color_worker(Arc::clone(&backend));
+            KeyboardControl {
+                color,
+                tx_color,
+                brightness,
+                tx_brightness,
+                keys,
+                tx_keys,
+                key_map: KeyMap::resolve(&kb_paths),
+                targets: Arc::clone(&kb_targets),
+                max: read_max_brightness(backend.as_ref(), &primary)
+                    .unwrap_or(DEFAULT_KB_MAX_BRIGHTNESS),
+            }
+        });
+        let lb_targets = shared_targets(find_lightbar(backend.as_ref()).into_iter().collect());
+        let (color, tx_color) = spawn_lb_color_worker(Arc::clone(&backend), Arc::clone(&lb_targets));
+        let (brightness, tx_brightness) =
+            spawn_lb_brightness_worker(Arc::clone(&backend), Arc::clone(&lb_targets));
+        let lightbar = LightbarControl {
+            targets: Arc::clone(&lb_targets),
+            color,
+            tx_color,
+            brightness,
+            tx_brightness,
+        };
+        let (config, tx_persist) = spawn_persistence_worker(config, on_outside_change);
+        follow_hotplug(Arc::clone(&backend), kb_targets, lb_targets, Arc::clone(&config));
+        Lighting { backend, keyboard, lightbar, config, tx_persist }
+    }
+
+    fn keyboard(&self) -> fdo::Result<&KeyboardControl> {
+        self.keyboard
+            .as_ref()
+            .ok_or_else(|| fdo::Error::Failed("keyboard backlight not found".to_string()))
+    }
+
+    // The lightbar's control, its LED and maximum brightness, if it is there now.
+    fn lightbar(&self) -> fdo::Result<(&LightbarControl, PathBuf, i32)> {
+        let path = self.lightbar.targets.lock().unwrap().first().cloned();
+        let path = path.ok_or_else(|| fdo::Error::Failed("lightbar not found".to_string()))?;
+        let max = read_max_brightness(self.backend.as_ref(), &path).unwrap_or(DEFAULT_LB_MAX_BRIGHTNESS);
+        Ok((&self.lightbar, path, max))
+    }
+
+    fn update(&self, change: impl FnOnce(&mut Config)) {
+        let _ = update_config(&self.config, &self.tx_persist, |c| {
+            change(c);
+            Ok(())
+        });
+    }
+
+    // The brightness workers, for auto brightness.
+    fn brightness_outputs(&self) -> (Option<BrightnessOutput>, Option<BrightnessOutput>) {
+        let keyboard = self.keyboard.as_ref().map(|kb| BrightnessOutput {
+            latest: Arc::clone(&kb.brightness),
+            tx: kb.tx_brightness.clone(),
+            targets: Arc::clone(&kb.targets),
+            default_max: DEFAULT_KB_MAX_BRIGHTNESS,
+        });
+        let lightbar = BrightnessOutput {
+            latest: Arc::clone(&self.lightbar.brightness),
+            tx: self.lightbar.tx_brightness.clone(),
+            targets: Arc::clone(&self.lightbar.targets),
+            default_max: DEFAULT_LB_MAX_BRIGHTNESS,
+        };
+        (keyboard, Some(lightbar))
+    }
+
+    // A lightbar the config doesn't know about yet starts from its current brightness.
+    fn lightbar_config<'a>(&self, path: &Path, max: i32, config: &'a mut Config) -> &'a mut LightbarConfig {
+        config.lightbar.get_or_insert_with(|| LightbarConfig {
+            color: Rgb(255, 255, 255),
+            brightness: read_brightness(self.backend.as_ref(), path).unwrap_or(0),
+            max_brightness: max,
+        })
+    }
+
+    async fn announce(&self, emitter: &SignalEmitter<'_>) {
+        let state = state_dict(&self.config.lock().unwrap());
+        if let Err(e) = Self::state_changed(emitter, state).await {
+            eprintln!("Error: could not emit StateChanged: {}", e);
+        }
+    }
+}
+
+#[zbus::interface(name = "com.example.RustyKb.Lighting")]
+impl Lighting {
+    /// `target` is "keyboard" or "lightbar"; `color` is anything `parse_color` accepts.
+    /// Coloring the whole keyboard drops the per-key colors.
+    async fn set_color(
+        &self,
+        target: &str,
+        color: &str,
+        #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+    ) -> fdo::Result<()> {
+        let (r, g, b) = parse_color(color).map_err(invalid)?;
+        let rgb = Rgb(r, g, b);
+        match target {
+            "keyboard" => {
+                let kb = self.keyboard()?;
+                kb.color.store(rgb.packed(), Ordering::Relaxed);
+                notify_coalescer(&kb.tx_color);
+                self.update(|c| {
+                    c.keyboard.color = rgb;
+                    c.keyboard.keys.clear();
+                });
+            }
+            "lightbar" => {
+                let (lb, path, max) = self.lightbar()?;
+                lb.color.store(rgb.packed(), Ordering::Relaxed);
+                notify_coalescer(&lb.tx_color);
+                self.update(|c| self.lightbar_config(&path, max, c).color = rgb);
+            }
+            other => return Err(invalid(format!("unknown target '{}'", other))),
+        }
+        self.announce(&emitter).await;
+        Ok(())
+    }
+
+    /// Raw brightness, between 0 and the LED's `max_brightness` (see `GetState`).
+    async fn set_brightness(
+        &self,
+        target: &str,
+        value: i32,
+        #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+    ) -> fdo::Result<()> {
+        let check = |max: i32| {
+            if (0..=max).contains(&value) {
+                Ok(())
+            } else {
+                Err(invalid(format!("brightness must be between 0 and {}", max)))
+            }
+        };
+        match target {
+            "keyboard" => {
+                let kb = self.keyboard()?;
+                check(kb.max)?;
+                kb.brightness.store(value, Ordering::Relaxed);
+                notify_coalescer(&kb.tx_brightness);
+                self.update(|c| {
+                    c.keyboard.brightness = value;
+                    c.keyboard.max_brightness = kb.max;
+                });
+            }
+            "lightbar" => {
+                let (lb, path, max) = self.lightbar()?;
+                check(max)?;
+                lb.brightness.store(value, Ordering::Relaxed);
+                notify_coalescer(&lb.tx_brightness);
+                self.update(|c| {
+                    let config = self.lightbar_config(&path, max, c);
+                    config.brightness = value;
+                    config.max_brightness = max;
+                });
+            }
+            other => return Err(invalid(format!("unknown target '{}'", other))),
+        }
+        self.announce(&emitter).await;
+        Ok(())
+    }
+
+    /// Colors individual keys, e.g. {"w": "#ff0000", "space": "0,0,255"}.
+    async fn set_key_colors(
+        &self,
+        colors: HashMap<String, String>,
+        #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+    ) -> fdo::Result<()> {
+        let kb = self.keyboard()?;
+        let mut parsed = Vec::new();
+        for (name, color) in &colors {
+            let path = kb
+                .key_map
+                .path(name)
+                .ok_or_else(|| invalid(format!("unknown key '{}'", name)))?;
+            let (r, g, b) = parse_color(color).map_err(invalid)?;
+            parsed.push((name.clone(), path.clone(), Rgb(r, g, b)));
+        }
+        {
+            let mut pending = kb.keys.lock().unwrap();
+            for (_, path, rgb) in &parsed {
+                pending.insert(path.clone(), rgb.packed());
+            }
+        }
+        notify_coalescer(&kb.tx_keys);
+        self.update(|c| {
+            for (name, _, rgb) in parsed {
+                c.keyboard.keys.insert(name, rgb);
+            }
+        });
+        self.announce(&emitter).await;
+        Ok(())
+    }
+
+    async fn activate_profile(
+        &self,
+        name: &str,
+        #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+    ) -> fdo::Result<()> {
+        let config = update_config(&self.config, &self.tx_persist, |c| {
+            c.activate_profile(name)?;
+            Ok(c.clone())
+        })
+        .map_err(invalid)?;
+        apply_lighting(
+            self.backend.as_ref(),
+            &config.keyboard,
+            config.lightbar.as_ref(),
+            &config.options,
+        )
+        .map_err(fdo::Error::Failed)?;
+        self.announce(&emitter).await;
+        Ok(())
+    }
+
+    /// Keyboard and lightbar colors and brightness, the per-key colors and the
+    /// profile names.
+    fn get_state(&self) -> HashMap<String, OwnedValue> {
+        state_dict(&self.config.lock().unwrap())
+    }
+
+    #[zbus(signal)]
+    async fn state_changed(
+        emitter: &SignalEmitter<'_>,
+        state: HashMap<String, OwnedValue>,
+    ) -> zbus::Result<()>;
+}
+
+fn emit_state_changed(connection: &zbus::blocking::Connection, config: &SharedConfig) {
+    let state = state_dict(&config.lock().unwrap());
+    if let Err(e) = connection.emit_signal(None::<&str>, OBJECT_PATH, INTERFACE, "StateChanged", &(state,)) {
+        eprintln!("Error: could not emit StateChanged: {}", e);
+    }
+}
+
+static STOP: AtomicBool = AtomicBool::new(false);
+const STOP_POLL: Duration = Duration::from_millis(200);
+
+extern "C" fn request_stop(_: libc::c_int) {
+    STOP.store(true, Ordering::Relaxed);
+}
+
+/// Serves the interface on the session bus (`DBUS_SESSION_BUS_ADDRESS`) until stopped.
+pub fn run_daemon(backend: Arc<dyn LedBackend>) -> Result<(), String> {
+    // Saves of the window and the command line are announced too, once there
+    // is a connection to do it on.
+    let announce = Arc::new(OnceLock::<(zbus::blocking::Connection, SharedConfig)>::new());
+    let announce_for_config = Arc::clone(&announce);
+    let lighting = Lighting::new(Arc::clone(&backend), move |_| {
+        if let Some((connection, config)) = announce_for_config.get() {
+            emit_state_changed(connection, config);
+        }
+    });
+    let (config, tx_persist) = (Arc::clone(&lighting.config), lighting.tx_persist.clone());
+    let (kb_output, lb_output) = lighting.brightness_outputs();
+    let connection = zbus::blocking::connection::Builder::session()
+        .and_then(|b| b.name(BUS_NAME))
+        .and_then(|b| b.serve_at(OBJECT_PATH, lighting))
+        .and_then(|b| b.build())
+        .map_err(|e| format!("could not serve {} on the session bus: {}", BUS_NAME, e))?;
+    println!("Serving {} at {}", BUS_NAME, OBJECT_PATH);
+    let _ = announce.set((connection.clone(), Arc::clone(&config)));
+    spawn_idle_timer(Arc::clone(&backend), Arc::clone(&config));
+    spawn_power_watcher(Arc::clone(&backend), Arc::clone(&config));
+    // Profiles switched by the schedule and brightness changed by the light
+    // sensor or with the Fn keys are announced like any other change.
+    let (config_for_schedule, connection_for_schedule) = (Arc::clone(&config), connection.clone());
+    spawn_scheduler(Arc::clone(&backend), Arc::clone(&config), tx_persist.clone(), move || {
+        emit_state_changed(&connection_for_schedule, &config_for_schedule)
+    });
+    let (config_for_ambient, connection_for_ambient) = (Arc::clone(&config), connection.clone());
+    spawn_auto_brightness(Arc::clone(&backend), Arc::clone(&config), kb_output, lb_output, tx_persist.clone(), move || {
+        emit_state_changed(&connection_for_ambient, &config_for_ambient)
+    });
+    let config_for_signal = Arc::clone(&config);
+    spawn_brightness_watcher(backend, config, tx_persist, move |_| {
+        emit_state_changed(&connection, &config_for_signal)
+    });
+    // Stopped with SIGTERM (logout, systemd) or SIGINT, after writing and
+    // saving what is still pending.
+    for signal in [libc::SIGTERM, libc::SIGINT] {
+        let handler = request_stop as extern "C" fn(libc::c_int);
+        // SAFETY: the handler only stores to an atomic.
+        unsafe { libc::signal(signal, handler as libc::sighandler_t) };
+    }
+    while !STOP.load(Ordering::Relaxed) {
+        thread::sleep(STOP_POLL);
+    }
+    flush_workers();
+    Ok(())
+}
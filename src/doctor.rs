@@ -0,0 +1,368 @@
+// `lwl-x6-keyboard doctor`: checks everything lighting depends on, from the LEDs
+// and their permissions to the installed udev rule, login service and saved
+// config, and says what to do about whatever is missing.
+
+use crate::backend::LedBackend;
+use crate::config::{backup_path, config_path, read_config};
+use crate::leds::{find_lightbar, KB_BACKLIGHT_PATTERN, LIGHTBAR_NAME};
+use crate::persist::{load_state, state_path};
+use crate::write_errors::{
+    group_entry, process_groups, WriteError, WriteErrorKind, LED_GROUP, UDEV_RELOAD,
+};
+use std::path::{Path, PathBuf};
+
+const UDEV_RULE: &str = "99-rusty-kb.rules";
+const UDEV_RULE_DIRS: &[&str] = &["/etc/udev/rules.d", "/usr/lib/udev/rules.d", "/lib/udev/rules.d"];
+const SERVICE: &str = "rusty-kb.service";
+const SERVICE_DIRS: &[&str] = &["/etc/systemd/user", "/usr/lib/systemd/user", "/lib/systemd/user"];
+// Where `systemctl --global enable` and `systemctl --user enable` put the link.
+const GLOBAL_WANTS: &str = "/etc/systemd/user/default.target.wants";
+const USER_WANTS: &str = ".config/systemd/user/default.target.wants";
+const WRITTEN_ATTRS: &[&str] = &["brightness", "multi_intensity"];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Status {
+    Ok,
+    Warning,
+    Failed,
+}
+
+impl Status {
+    fn name(self) -> &'static str {
+        match self {
+            Status::Ok => "ok",
+            Status::Warning => "warning",
+            Status::Failed => "failed",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Check {
+    /// Stable identifier for scripts.
+    pub id: &'static str,
+    pub title: &'static str,
+    pub status: Status,
+    pub detail: String,
+    pub remedy: Option<String>,
+}
+
+impl Check {
+    fn new(id: &'static str, title: &'static str, status: Status, detail: String) -> Self {
+        Check { id, title, status, detail, remedy: None }
+    }
+
+    fn remedy(mut self, remedy: impl Into<String>) -> Self {
+        self.remedy = Some(remedy.into());
+        self
+    }
+}
+
+fn check_keyboard(keyboard: &[PathBuf]) -> Check {
+    if keyboard.is_empty() {
+        return Check::new(
+            "keyboard",
+            "Keyboard LEDs",
+            Status::Failed,
+            format!("no {} LEDs", KB_BACKLIGHT_PATTERN),
+        )
+        .remedy("Install and load the lwl-driver kernel modules (https://comexr.github.io/).");
+    }
+    Check::new("keyboard", "Keyboard LEDs", Status::Ok, format!("{} found", keyboard.len()))
+}
+
+fn check_lightbar(lightbar: Option<&Path>) -> Check {
+    match lightbar {
+        Some(path) => Check::new("lightbar", "Lightbar LED", Status::Ok, path.display().to_string()),
+        None => Check::new(
+            "lightbar",
+            "Lightbar LED",
+            Status::Warning,
+            format!("no {} LED, lightbar settings are skipped", LIGHTBAR_NAME),
+        ),
+    }
+}
+
+fn check_access(backend: &dyn LedBackend, leds: &[PathBuf]) -> Check {
+    let mut total = 0;
+    let mut failures = Vec::new();
+    for led in leds {
+        for attr in WRITTEN_ATTRS {
+            // LEDs without a color channel have no multi_intensity.
+            if *attr == "multi_intensity" && backend.read_attr(led, attr).is_err() {
+                continue;
+            }
+            total += 1;
+            if let Err(e) = backend.check_writable(led, attr) {
+                failures.push(WriteError {
+                    kind: WriteErrorKind::classify(&e),
+                    file: led.join(attr),
+                    message: e.to_string(),
+                });
+            }
+        }
+    }
+    let Some(first) = failures.first() else {
+        return Check::new("access", "LED write access", Status::Ok, format!("{} files writable", total));
+    };
+    let mut detail = format!("{} of {} files not writable:", failures.len(), total);
+    for failure in &failures {
+        detail.push_str(&format!("\n{} ({})", failure.file.display(), failure.message));
+    }
+    Check::new("access", "LED write access", Status::Failed, detail).remedy(first.remedy())
+}
+
+fn check_group(leds_writable: bool) -> Vec<Check> {
+    let user = std::env::var("USER").unwrap_or_default();
+    group_checks(group_entry(LED_GROUP), &user, &process_groups(), leds_writable)
+}
+
+/// The group checks only fail when the LEDs can't be written, e.g. root
+/// doesn't need the group.
+fn group_checks(
+    group: Option<(u32, Vec<String>)>,
+    user: &str,
+    process_groups: &[u32],
+    leds_writable: bool,
+) -> Vec<Check> {
+    let missing = if leds_writable { Status::Warning } else { Status::Failed };
+    let Some((gid, members)) = group else {
+        return vec![Check::new("group", "LED group", missing, format!("no '{}' group in /etc/group", LED_GROUP))
+            .remedy(format!("Reinstall the package, or run: sudo groupadd -r {}", LED_GROUP))];
+    };
+    let group = Check::new("group", "LED group", Status::Ok, format!("'{}' has gid {}", LED_GROUP, gid));
+    let listed = !user.is_empty() && members.iter().any(|m| m == user);
+    let member = if listed {
+        Check::new("group_member", "Group membership", Status::Ok, format!("{} is listed in /etc/group", user))
+    } else {
+        Check::new(
+            "group_member",
+            "Group membership",
+            missing,
+            format!("{} is not listed in /etc/group", if user.is_empty() { "$USER" } else { user }),
+        )
+        .remedy(format!("Run: sudo usermod -aG {} $USER, then log out and back in", LED_GROUP))
+    };
+    let session = if process_groups.contains(&gid) {
+        Check::new("session_group", "Session groups", Status::Ok, format!("this session has '{}'", LED_GROUP))
+    } else {
+        let check = Check::new(
+            "session_group",
+            "Session groups",
+            missing,
+            format!("this session doesn't have '{}'", LED_GROUP),
+        );
+        if listed { check.remedy("You were added after logging in. Log out and back in.") } else { check }
+    };
+    vec![group, member, session]
+}
+
+fn find_in(dirs: &[&str], name: &str) -> Option<PathBuf> {
+    dirs.iter().map(|dir| Path::new(dir).join(name)).find(|p| p.exists())
+}
+
+fn check_udev_rule() -> Check {
+    match find_in(UDEV_RULE_DIRS, UDEV_RULE) {
+        Some(path) => Check::new("udev_rule", "udev rule", Status::Ok, path.display().to_string()),
+        None => Check::new("udev_rule", "udev rule", Status::Failed, format!("{} is not installed", UDEV_RULE))
+            .remedy(format!(
+                "Reinstall the package, or copy udev/{} to /etc/udev/rules.d/ and run: {}",
+                UDEV_RULE, UDEV_RELOAD
+            )),
+    }
+}
+
+fn check_service() -> Vec<Check> {
+    let Some(unit) = find_in(SERVICE_DIRS, SERVICE) else {
+        return vec![Check::new("service", "Login service", Status::Warning, format!("{} is not installed", SERVICE))
+            .remedy("Reinstall the package to restore the lighting at login.")];
+    };
+    let installed = Check::new("service", "Login service", Status::Ok, unit.display().to_string());
+    let mut wants = vec![Path::new(GLOBAL_WANTS).join(SERVICE)];
+    if let Some(home) = std::env::var_os("HOME") {
+        wants.push(Path::new(&home).join(USER_WANTS).join(SERVICE));
+    }
+    let enabled = match wants.iter().find(|p| p.exists()) {
+        Some(link) => Check::new("service_enabled", "Service enabled", Status::Ok, link.display().to_string()),
+        None => Check::new(
+            "service_enabled",
+            "Service enabled",
+            Status::Warning,
+            format!("{} is not enabled, the lighting isn't restored at login", SERVICE),
+        )
+        .remedy(format!("Run: sudo systemctl --global enable {}", SERVICE)),
+    };
+    vec![installed, enabled]
+}
+
+fn check_saved_state() -> Check {
+    let existing = |path: Option<PathBuf>| path.filter(|p| p.exists());
+    saved_state_check(existing(config_path()), existing(state_path()))
+}
+
+// `config` and `legacy` are the saved files that exist; config.toml wins.
+fn saved_state_check(config: Option<PathBuf>, legacy: Option<PathBuf>) -> Check {
+    let title = "Saved settings";
+    let backup_ok = config.as_deref().is_some_and(|p| read_config(&backup_path(p)).is_ok());
+    let result = match (config, legacy) {
+        (Some(path), _) => read_config(&path).map(|_| path),
+        (None, Some(path)) => load_state(&path).map(|_| path),
+        (None, None) => {
+            return Check::new("saved_state", title, Status::Ok, "nothing saved yet, the defaults are used".to_string());
+        }
+    };
+    match result {
+        Ok(path) => Check::new("saved_state", title, Status::Ok, format!("{} parses", path.display())),
+        Err(e) if backup_ok => Check::new("saved_state", title, Status::Warning, e)
+            .remedy("The backup config.toml.bak is intact and is used instead; the damaged file is moved aside."),
+        Err(e) => Check::new("saved_state", title, Status::Failed, e)
+            .remedy("Fix the file, or delete it to start over from the defaults. The window moves a broken config.toml aside."),
+    }
+}
+
+pub fn run_checks(backend: &dyn LedBackend) -> Vec<Check> {
+    let keyboard = backend.find(KB_BACKLIGHT_PATTERN);
+    let lightbar = find_lightbar(backend);
+    let leds: Vec<PathBuf> = keyboard.iter().cloned().chain(lightbar.clone()).collect();
+    let mut checks = vec![check_keyboard(&keyboard), check_lightbar(lightbar.as_deref())];
+    let access = check_access(backend, &leds);
+    let writable = access.status == Status::Ok && !leds.is_empty();
+    checks.push(access);
+    checks.extend(check_group(writable));
+    checks.push(check_udev_rule());
+    checks.extend(check_service());
+    checks.push(check_saved_state());
+    checks
+}
+
+pub fn format_report(checks: &[Check]) -> String {
+    let mut out = String::new();
+    for check in checks {
+        let mark = match check.status {
+            Status::Ok => "ok  ",
+            Status::Warning => "warn",
+            Status::Failed => "FAIL",
+        };
+        let mut lines = check.detail.lines();
+        out.push_str(&format!("[{}] {}: {}\n", mark, check.title, lines.next().unwrap_or_default()));
+        for line in lines {
+            out.push_str(&format!("       {}\n", line));
+        }
+        if let Some(remedy) = &check.remedy {
+            out.push_str(&format!("       -> {}\n", remedy));
+        }
+    }
+    out
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// `{"ok": bool, "checks": [{"id", "title", "status", "detail", "remedy"}]}`,
+/// one check per line. `ok` is false when a check failed.
+pub fn format_json(checks: &[Check]) -> String {
+    let items: Vec<String> = checks
+        .iter()
+        .map(|c| {
+            format!(
+                "    {{\"id\": {}, \"title\": {}, \"status\": {}, \"detail\": {}, \"remedy\": {}}}",
+                json_string(c.id),
+                json_string(c.title),
+                json_string(c.status.name()),
+                json_string(&c.detail),
+                c.remedy.as_deref().map_or("null".to_string(), json_string)
+            )
+        })
+        .collect();
+    let ok = checks.iter().all(|c| c.status != Status::Failed);
+    format!("{{\n  \"ok\": {},\n  \"checks\": [\n{}\n  ]\n}}", ok, items.join(",\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::MemoryBackend;
+    use crate::temp_dir::TempDir;
+    use std::fs;
+
+    fn find<'a>(checks: &'a [Check], id: &str) -> &'a Check {
+        checks.iter().find(|c| c.id == id).unwrap()
+    }
+
+    #[test]
+    fn reports_missing_keyboard_leds() {
+        let backend = MemoryBackend::new();
+        backend.add_rgb_led("rgb:lightbar", 100);
+        let checks = run_checks(&backend);
+        let keyboard = find(&checks, "keyboard");
+        assert_eq!(keyboard.status, Status::Failed);
+        assert!(keyboard.remedy.as_deref().unwrap().contains("lwl-driver"));
+        assert_eq!(find(&checks, "lightbar").status, Status::Ok);
+        assert!(format_json(&checks).contains("\"ok\": false"));
+    }
+
+    #[test]
+    fn only_warns_about_a_missing_lightbar() {
+        let backend = MemoryBackend::new();
+        backend.add_rgb_led("rgb:kbd_backlight", 50);
+        let checks = run_checks(&backend);
+        assert_eq!(find(&checks, "keyboard").status, Status::Ok);
+        assert_eq!(find(&checks, "lightbar").status, Status::Warning);
+        assert_eq!(find(&checks, "access").detail, "2 files writable");
+    }
+
+    #[test]
+    fn reports_a_config_that_does_not_parse() {
+        let dir = TempDir::new("doctor");
+        let config = dir.join("config.toml");
+        fs::write(&config, "version = \"one\"\n[keyboard\n").unwrap();
+        let check = saved_state_check(Some(config.clone()), None);
+        assert_eq!(check.status, Status::Failed);
+        assert!(check.detail.starts_with(&config.display().to_string()));
+        assert!(check.remedy.is_some());
+
+        // Only a warning while the backup is intact.
+        fs::write(backup_path(&config), "version = 1\n").unwrap();
+        assert_eq!(saved_state_check(Some(config), None).status, Status::Warning);
+
+        assert_eq!(saved_state_check(None, None).status, Status::Ok);
+    }
+
+    #[test]
+    fn checks_the_led_group() {
+        let member = || Some((990, vec!["alice".to_string()]));
+        let statuses = |checks: Vec<Check>| checks.iter().map(|c| (c.id, c.status)).collect::<Vec<_>>();
+        assert_eq!(
+            statuses(group_checks(member(), "alice", &[100, 990], false)),
+            [("group", Status::Ok), ("group_member", Status::Ok), ("session_group", Status::Ok)]
+        );
+
+        // Not in the group: fails while the LEDs can't be written.
+        let checks = group_checks(member(), "bob", &[100], false);
+        assert_eq!(find(&checks, "group_member").status, Status::Failed);
+        assert!(find(&checks, "group_member").remedy.as_deref().unwrap().contains("usermod"));
+        assert_eq!(find(&checks, "session_group").status, Status::Failed);
+        assert_eq!(find(&group_checks(member(), "bob", &[100], true), "group_member").status, Status::Warning);
+
+        // Added to the group after logging in.
+        let checks = group_checks(member(), "alice", &[100], false);
+        assert_eq!(find(&checks, "group_member").status, Status::Ok);
+        assert!(find(&checks, "session_group").remedy.as_deref().unwrap().contains("Log out"));
+
+        let checks = group_checks(None, "alice", &[100], false);
+        assert_eq!(statuses(checks), [("group", Status::Failed)]);
+    }
+}
@@ -0,0 +1,78 @@
+use crate::effects::{Direction, EffectEngine, EffectParams, EFFECTS};
+use crate::leds::unpack_rgb;
+use gtk4::prelude::*;
+use gtk4::{Box, DropDown, Label, Orientation, Scale, StringList};
+use std::cell::Cell;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+
+/// Effect picker with speed and direction. Breathing and starlight use the
+/// keyboard color in `color`; choosing "None" stops the effect and calls `on_stop`.
+pub fn effect_controls(
+    engine: EffectEngine,
+    color: Arc<AtomicU32>,
+    on_stop: impl Fn() + 'static,
+) -> Box {
+    let row = Box::new(Orientation::Horizontal, 10);
+    row.append(&Label::new(Some("Effect:")));
+
+    let mut names = vec!["None".to_string()];
+    names.extend(EFFECTS.iter().map(|e| {
+        let name = e.name();
+        name[..1].to_uppercase() + &name[1..]
+    }));
+    let names: Vec<&str> = names.iter().map(String::as_str).collect();
+    let effect_dd = DropDown::builder().model(&StringList::new(&names)).build();
+    row.append(&effect_dd);
+
+    row.append(&Label::new(Some("Speed:")));
+    let speed = Scale::with_range(Orientation::Horizontal, 0.25, 4.0, 0.25);
+    speed.set_value(1.0);
+    speed.set_digits(2);
+    speed.set_hexpand(true);
+    row.append(&speed);
+
+    let direction_dd = DropDown::builder()
+        .model(&StringList::new(&["Left to right", "Right to left"]))
+        .build();
+    row.append(&direction_dd);
+
+    let update = {
+        let effect_dd = effect_dd.clone();
+        let speed = speed.clone();
+        let direction_dd = direction_dd.clone();
+        let running = Cell::new(false);
+        move || {
+            let effect = (effect_dd.selected() as usize)
+                .checked_sub(1)
+                .and_then(|i| EFFECTS.get(i).copied());
+            let Some(effect) = effect else {
+                engine.set(None);
+                if running.replace(false) {
+                    on_stop();
+                }
+                return;
+            };
+            running.set(true);
+            engine.set(Some(EffectParams {
+                effect,
+                color: unpack_rgb(color.load(Ordering::Relaxed)),
+                speed: speed.value(),
+                direction: match direction_dd.selected() {
+                    0 => Direction::LeftToRight,
+                    _ => Direction::RightToLeft,
+                },
+            }));
+        }
+    };
+    let update = Rc::new(update);
+
+    let update_for_effect = Rc::clone(&update);
+    effect_dd.connect_selected_notify(move |_| update_for_effect());
+    let update_for_speed = Rc::clone(&update);
+    speed.connect_value_changed(move |_| update_for_speed());
+    direction_dd.connect_selected_notify(move |_| update());
+
+    row
+}
@@ -0,0 +1,423 @@
+// Animated lighting effects. A `Renderer` turns the time into frames; the GUI runs
+// it on the engine thread below, the CLI drives it directly.
+
+use crate::backend::LedBackend;
+use crate::input::{input_source, spawn_key_reader};
+use crate::layout::{key_rects, KeyMap, BOARD_HEIGHT, BOARD_WIDTH};
+use crate::leds::{hsv_to_rgb, pack_rgb};
+use crate::workers::{
+    notify_coalescer, spawn_kb_color_worker, spawn_key_color_worker, spawn_lb_color_worker,
+    Targets,
+};
+use std::collections::BTreeMap;
+use std::f64::consts::PI;
+use std::path::PathBuf;
+use std::sync::atomic::Ordering;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+pub const FRAME_INTERVAL: Duration = Duration::from_millis(50);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Effect {
+    Breathing,
+    Spectrum,
+    Wave,
+    Rainbow,
+    Starlight,
+    Reactive,
+    Ripple,
+}
+
+pub const EFFECTS: &[Effect] = &[
+    Effect::Breathing,
+    Effect::Spectrum,
+    Effect::Wave,
+    Effect::Rainbow,
+    Effect::Starlight,
+    Effect::Reactive,
+    Effect::Ripple,
+];
+
+impl Effect {
+    pub fn name(self) -> &'static str {
+        match self {
+            Effect::Breathing => "breathing",
+            Effect::Spectrum => "spectrum",
+            Effect::Wave => "wave",
+            Effect::Rainbow => "rainbow",
+            Effect::Starlight => "starlight",
+            Effect::Reactive => "reactive",
+            Effect::Ripple => "ripple",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Result<Effect, String> {
+        EFFECTS
+            .iter()
+            .copied()
+            .find(|e| e.name() == name)
+            .ok_or_else(|| format!("unknown effect '{}'", name))
+    }
+
+    /// Static effects only need their first frame.
+    pub fn is_animated(self) -> bool {
+        self != Effect::Rainbow
+    }
+
+    /// Effects that light up in response to key presses.
+    pub fn is_reactive(self) -> bool {
+        matches!(self, Effect::Reactive | Effect::Ripple)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    LeftToRight,
+    RightToLeft,
+}
+
+impl Direction {
+    pub fn from_name(name: &str) -> Result<Direction, String> {
+        match name {
+            "right" => Ok(Direction::LeftToRight),
+            "left" => Ok(Direction::RightToLeft),
+            other => Err(format!("invalid direction '{}', expected left or right", other)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct EffectParams {
+    pub effect: Effect,
+    /// Base color of breathing and starlight.
+    pub color: (u8, u8, u8),
+    /// 1.0 is the normal pace, 2.0 twice as fast.
+    pub speed: f64,
+    pub direction: Direction,
+}
+
+pub enum KeyboardFrame {
+    Uniform((u8, u8, u8)),
+    /// Only the keys that changed since the previous frame.
+    PerKey(Vec<(PathBuf, (u8, u8, u8))>),
+}
+
+pub struct Frame {
+    pub keyboard: Option<KeyboardFrame>,
+    pub lightbar: Option<(u8, u8, u8)>,
+}
+
+fn scale((r, g, b): (u8, u8, u8), factor: f64) -> (u8, u8, u8) {
+    let f = factor.clamp(0.0, 1.0);
+    let c = |v: u8| (v as f64 * f).round() as u8;
+    (c(r), c(g), c(b))
+}
+
+// One breath every 4s.
+fn breathing(params: &EffectParams, t: f64) -> (u8, u8, u8) {
+    scale(params.color, (1.0 - (t * PI / 2.0).cos()) / 2.0)
+}
+
+// Through all hues in 10s.
+fn spectrum(t: f64) -> (u8, u8, u8) {
+    hsv_to_rgb(t / 10.0, 1.0, 1.0)
+}
+
+struct KeyLed {
+    // None for the stand-in used when there are no per-key LEDs.
+    path: Option<PathBuf>,
+    name: &'static str,
+    // Horizontal position on the board, 0.0 (left) to 1.0.
+    x: f64,
+    // Center, in key units.
+    center: (f64, f64),
+}
+
+struct Press {
+    name: &'static str,
+    center: (f64, f64),
+    at: f64,
+}
+
+// Presses fade out well within this many seconds (at speed 1.0).
+const PRESS_LIFETIME: f64 = 3.0;
+
+pub struct Renderer {
+    keys: Vec<KeyLed>,
+    whole_keyboard: KeyLed,
+    last_keys: BTreeMap<PathBuf, (u8, u8, u8)>,
+    last_uniform: Option<(u8, u8, u8)>,
+    stars: Vec<f64>,
+    presses: Vec<Press>,
+    last_t: f64,
+    rng: u64,
+}
+
+impl Renderer {
+    /// Per-key effects cover the keys of `key_map` among `targets`; without any,
+    /// they are rendered as one color for the whole keyboard.
+    pub fn new(key_map: &KeyMap, targets: &[PathBuf]) -> Self {
+        let keys: Vec<KeyLed> = key_rects()
+            .into_iter()
+            .filter_map(|(key, rect)| {
+                let path = key_map.path(key.name).filter(|p| targets.contains(p))?;
+                let center = (rect.x + rect.width / 2.0, rect.y + rect.height / 2.0);
+                Some(KeyLed {
+                    path: Some(path.clone()),
+                    name: key.name,
+                    x: center.0 / BOARD_WIDTH,
+                    center,
+                })
+            })
+            .collect();
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos() as u64);
+        Renderer {
+            stars: vec![0.0; keys.len()],
+            keys,
+            whole_keyboard: KeyLed {
+                path: None,
+                name: "",
+                x: 0.5,
+                center: (BOARD_WIDTH / 2.0, BOARD_HEIGHT / 2.0),
+            },
+            last_keys: BTreeMap::new(),
+            last_uniform: None,
+            presses: Vec::new(),
+            last_t: 0.0,
+            rng: seed | 1,
+        }
+    }
+
+    // xorshift64, plenty for twinkling.
+    fn random(&mut self) -> f64 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        (self.rng >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Forgets what was written, so the next frame is complete.
+    pub fn reset(&mut self) {
+        self.last_keys.clear();
+        self.last_uniform = None;
+        self.presses.clear();
+    }
+
+    /// Records a key press at `elapsed` seconds for the reactive effects.
+    pub fn press(&mut self, name: &str, elapsed: f64) {
+        let Some((key_name, center)) = key_rects()
+            .into_iter()
+            .find(|(k, _)| k.name == name)
+            .map(|(k, r)| (k.name, (r.x + r.width / 2.0, r.y + r.height / 2.0)))
+        else {
+            return;
+        };
+        self.presses.push(Press { name: key_name, center, at: elapsed });
+    }
+
+    fn key_color(&mut self, params: &EffectParams, t: f64, dt: f64, led: Option<usize>) -> (u8, u8, u8) {
+        let key = led.map_or(&self.whole_keyboard, |i| &self.keys[i]);
+        let (name, center) = (key.name, key.center);
+        let x = match params.direction {
+            Direction::LeftToRight => key.x,
+            Direction::RightToLeft => 1.0 - key.x,
+        };
+        match params.effect {
+            // One rainbow across the board, moving one board width per 2s.
+            Effect::Wave => hsv_to_rgb(x - t / 2.0, 1.0, 1.0),
+            Effect::Rainbow => hsv_to_rgb(x, 1.0, 1.0),
+            Effect::Starlight => {
+                // About one new star per key every 8s, fading in half a second.
+                let i = led.unwrap_or(0);
+                if self.stars.is_empty() {
+                    self.stars.push(0.0);
+                }
+                self.stars[i] *= (-dt * 4.0).exp();
+                if self.random() < 1.0 - (-dt / 8.0).exp() {
+                    self.stars[i] = 1.0;
+                }
+                scale(params.color, self.stars[i])
+            }
+            // The pressed key lights up and fades out in about a second.
+            Effect::Reactive => {
+                let level = self
+                    .presses
+                    .iter()
+                    .filter(|p| name.is_empty() || p.name == name)
+                    .map(|p| (-(t - p.at * params.speed) * 3.0).exp())
+                    .fold(0.0, f64::max);
+                scale(params.color, level)
+            }
+            // A ring spreading from the pressed key at 8 keys per second.
+            Effect::Ripple => {
+                let level = self
+                    .presses
+                    .iter()
+                    .map(|p| {
+                        let age = t - p.at * params.speed;
+                        let fade = (-age * 1.5).exp();
+                        if name.is_empty() {
+                            return fade;
+                        }
+                        let dist = (center.0 - p.center.0).hypot(center.1 - p.center.1);
+                        let off_ring = (dist - age * 8.0) / 0.8;
+                        fade * (-off_ring * off_ring).exp()
+                    })
+                    .fold(0.0, f64::max);
+                scale(params.color, level)
+            }
+            // The same color everywhere; `render` doesn't go through here for them.
+            Effect::Breathing => breathing(params, t),
+            Effect::Spectrum => spectrum(t),
+        }
+    }
+
+    /// The frame at `elapsed` seconds since the effect started.
+    pub fn render(&mut self, params: &EffectParams, elapsed: f64) -> Frame {
+        let t = elapsed * params.speed;
+        let dt = (t - self.last_t).max(0.0);
+        self.presses.retain(|p| t - p.at * params.speed < PRESS_LIFETIME);
+        let uniform = match params.effect {
+            Effect::Breathing => Some(breathing(params, t)),
+            Effect::Spectrum => Some(spectrum(t)),
+            _ if self.keys.is_empty() => Some(self.key_color(params, t, dt, None)),
+            _ => None,
+        };
+        let frame = match uniform {
+            Some(color) => Frame {
+                keyboard: (self.last_uniform != Some(color)).then_some(KeyboardFrame::Uniform(color)),
+                lightbar: Some(color),
+            },
+            None => {
+                let mut changed = Vec::new();
+                for i in 0..self.keys.len() {
+                    let color = self.key_color(params, t, dt, Some(i));
+                    let Some(path) = self.keys[i].path.clone() else { continue };
+                    if self.last_keys.insert(path.clone(), color) != Some(color) {
+                        changed.push((path, color));
+                    }
+                }
+                let lightbar = match params.effect {
+                    Effect::Wave | Effect::Rainbow => Some(self.key_color(params, t, dt, None)),
+                    _ => None,
+                };
+                Frame { keyboard: Some(KeyboardFrame::PerKey(changed)), lightbar }
+            }
+        };
+        if let Some(KeyboardFrame::Uniform(color)) = frame.keyboard {
+            self.last_uniform = Some(color);
+            self.last_keys.clear();
+        }
+        self.last_t = t;
+        frame
+    }
+}
+
+pub struct EffectEngine {
+    params: Arc<Mutex<Option<EffectParams>>>,
+    tx: mpsc::SyncSender<()>,
+}
+
+impl EffectEngine {
+    /// Starts `params`, or stops the running effect with `None`. Stopping leaves
+    /// the last frame on the keyboard; callers restore the static colors.
+    pub fn set(&self, params: Option<EffectParams>) {
+        *self.params.lock().unwrap() = params;
+        notify_coalescer(&self.tx);
+    }
+}
+
+// Timer thread rendering frames into the coalescing workers: when writing falls
+// behind, frames are dropped rather than queued. The renderer is rebuilt when
+// hotplug changes `kb_targets`, so per-key frames only go to keys that exist.
+pub fn spawn_effect_engine(
+    backend: Arc<dyn LedBackend>,
+    kb_targets: Targets,
+    key_map: Arc<KeyMap>,
+    lb_targets: Targets,
+) -> EffectEngine {
+    let mut rendered_targets = kb_targets.lock().unwrap().clone();
+    let mut renderer = Renderer::new(&key_map, &rendered_targets);
+    let kb_targets_for_thread = Arc::clone(&kb_targets);
+    let (kb_color, tx_kb_color) = spawn_kb_color_worker(Arc::clone(&backend), kb_targets);
+    let (pending_keys, tx_keys) = spawn_key_color_worker(Arc::clone(&backend));
+    let (lb_color, tx_lb) = spawn_lb_color_worker(Arc::clone(&backend), lb_targets);
+
+    let params = Arc::new(Mutex::new(None::<EffectParams>));
+    let params_for_thread = Arc::clone(&params);
+    let (tx, rx) = mpsc::sync_channel::<()>(1);
+    thread::spawn(move || {
+        let mut started = Instant::now();
+        let mut running = None;
+        // Key presses are only read while a reactive effect runs.
+        let mut presses: Option<mpsc::Receiver<&'static str>> = None;
+        loop {
+            let current = *params_for_thread.lock().unwrap();
+            {
+                let targets = kb_targets_for_thread.lock().unwrap();
+                if *targets != rendered_targets {
+                    rendered_targets = targets.clone();
+                    renderer = Renderer::new(&key_map, &rendered_targets);
+                }
+            }
+            if current.map(|p| p.effect) != running.map(|p: EffectParams| p.effect) {
+                started = Instant::now();
+                renderer.reset();
+                presses = match current.filter(|p| p.effect.is_reactive()) {
+                    Some(_) => match input_source().and_then(|p| spawn_key_reader(&p)) {
+                        Ok(rx) => Some(rx),
+                        Err(e) => {
+                            eprintln!("Error: {}", e);
+                            None
+                        }
+                    },
+                    None => None,
+                };
+            }
+            running = current;
+            let Some(params) = running else {
+                if rx.recv().is_err() {
+                    break;
+                }
+                continue;
+            };
+            let elapsed = started.elapsed().as_secs_f64();
+            for name in presses.iter().flat_map(|rx| rx.try_iter()) {
+                renderer.press(name, elapsed);
+            }
+            let frame = renderer.render(&params, elapsed);
+            match frame.keyboard {
+                Some(KeyboardFrame::Uniform((r, g, b))) => {
+                    kb_color.store(pack_rgb(r, g, b), Ordering::Relaxed);
+                    notify_coalescer(&tx_kb_color);
+                }
+                Some(KeyboardFrame::PerKey(changed)) if !changed.is_empty() => {
+                    let mut pending = pending_keys.lock().unwrap();
+                    for (path, (r, g, b)) in changed {
+                        pending.insert(path, pack_rgb(r, g, b));
+                    }
+                    drop(pending);
+                    notify_coalescer(&tx_keys);
+                }
+                _ => {}
+            }
+            if let Some((r, g, b)) = frame.lightbar {
+                lb_color.store(pack_rgb(r, g, b), Ordering::Relaxed);
+                notify_coalescer(&tx_lb);
+            }
+            let wait = if params.effect.is_animated() {
+                rx.recv_timeout(FRAME_INTERVAL)
+            } else {
+                rx.recv().map_err(|_| mpsc::RecvTimeoutError::Disconnected)
+            };
+            if wait == Err(mpsc::RecvTimeoutError::Disconnected) {
+                break;
+            }
+        }
+    });
+    EffectEngine { params, tx }
+}
@@ -0,0 +1,67 @@
+use crate::write_errors::{take_write_error, WriteErrorKind};
+use gtk4::prelude::*;
+use gtk4::{Box, Button, Label, Orientation};
+use std::cell::Cell;
+use std::rc::Rc;
+use std::time::Duration;
+
+const CHECK_INTERVAL: Duration = Duration::from_millis(500);
+
+fn title(kind: WriteErrorKind) -> &'static str {
+    match kind {
+        WriteErrorKind::PermissionDenied => "Not allowed to change the lighting",
+        WriteErrorKind::Missing => "Keyboard LED not found",
+        WriteErrorKind::Io => "The keyboard controller reported an error",
+        WriteErrorKind::Other => "Could not change the lighting",
+    }
+}
+
+/// Hidden banner that shows the latest failed LED write with what to do about
+/// it. Dismissing it hides the kind of failure shown until a different one comes.
+pub fn write_error_banner() -> Box {
+    let banner = Box::new(Orientation::Horizontal, 10);
+    banner.set_visible(false);
+    let text = Label::builder()
+        .wrap(true)
+        .selectable(true)
+        .hexpand(true)
+        .xalign(0.0)
+        .build();
+    text.add_css_class("error");
+    banner.append(&text);
+    let dismiss = Button::with_label("Dismiss");
+    banner.append(&dismiss);
+
+    let shown: Rc<Cell<Option<WriteErrorKind>>> = Rc::new(Cell::new(None));
+    let dismissed: Rc<Cell<Option<WriteErrorKind>>> = Rc::new(Cell::new(None));
+    let banner_for_dismiss = banner.clone();
+    let shown_for_dismiss = Rc::clone(&shown);
+    let dismissed_for_dismiss = Rc::clone(&dismissed);
+    dismiss.connect_clicked(move |_| {
+        banner_for_dismiss.set_visible(false);
+        dismissed_for_dismiss.set(shown_for_dismiss.get());
+    });
+
+    let banner_weak = banner.downgrade();
+    glib::timeout_add_local(CHECK_INTERVAL, move || {
+        let Some(banner) = banner_weak.upgrade() else {
+            return glib::ControlFlow::Break;
+        };
+        if let Some(error) = take_write_error()
+            && dismissed.get() != Some(error.kind)
+        {
+            text.set_label(&format!(
+                "{}: {} ({})\n{}",
+                title(error.kind),
+                error.file.display(),
+                error.message,
+                error.remedy()
+            ));
+            shown.set(Some(error.kind));
+            dismissed.set(None);
+            banner.set_visible(true);
+        }
+        glib::ControlFlow::Continue
+    });
+    banner
+}
@@ -0,0 +1,185 @@
+// Keyboard LEDs disappear and come back when the ITE controller re-enumerates
+// (resume, driver reload), possibly with a different set of keys. The LED class
+// directory is polled, which needs no netlink socket and also works for fake
+// trees and the in-memory backend.
+
+use crate::backend::LedBackend;
+use crate::channels::forget_channel_layouts;
+use crate::leds::{
+    apply_lighting, find_lightbar, keyboard_write_paths, pick_primary, KB_BACKLIGHT_PATTERN,
+};
+use crate::persist::SharedConfig;
+use crate::workers::Targets;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+pub const HOTPLUG_INTERVAL: Duration = Duration::from_secs(2);
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LedSet {
+    pub keyboard: Vec<PathBuf>,
+    pub lightbar: Option<PathBuf>,
+}
+
+impl LedSet {
+    pub fn scan(backend: &dyn LedBackend) -> Self {
+        LedSet {
+            keyboard: backend.find(KB_BACKLIGHT_PATTERN),
+            lightbar: find_lightbar(backend),
+        }
+    }
+
+    fn all(&self) -> impl Iterator<Item = &PathBuf> {
+        self.keyboard.iter().chain(self.lightbar.iter())
+    }
+}
+
+pub struct LedChange {
+    pub added: Vec<PathBuf>,
+    pub removed: Vec<PathBuf>,
+    pub current: LedSet,
+}
+
+impl LedChange {
+    fn between(old: &LedSet, new: LedSet) -> Self {
+        LedChange {
+            added: new.all().filter(|p| !old.all().any(|o| o == *p)).cloned().collect(),
+            removed: old.all().filter(|p| !new.all().any(|n| n == *p)).cloned().collect(),
+            current: new,
+        }
+    }
+}
+
+/// Calls `on_change` from a background thread whenever LEDs appear or vanish.
+pub fn spawn_led_watcher(
+    backend: Arc<dyn LedBackend>,
+    initial: LedSet,
+    on_change: impl Fn(&LedChange) + Send + 'static,
+) {
+    thread::spawn(move || {
+        let mut known = initial;
+        loop {
+            thread::sleep(HOTPLUG_INTERVAL);
+            let current = LedSet::scan(backend.as_ref());
+            if current == known {
+                continue;
+            }
+            let change = LedChange::between(&known, current.clone());
+            println!(
+                "LEDs changed: {} added, {} removed",
+                change.added.len(),
+                change.removed.len()
+            );
+            // A re-enumerated LED may come back with another channel layout.
+            forget_channel_layouts(&change.added);
+            forget_channel_layouts(&change.removed);
+            on_change(&change);
+            known = current;
+        }
+    });
+}
+
+/// Keeps `kb_targets` and `lb_targets` on the keyboard LEDs and the lightbar
+/// that exist and gives LEDs that (re)appear the state saved in `config`.
+pub fn follow_hotplug(
+    backend: Arc<dyn LedBackend>,
+    kb_targets: Targets,
+    lb_targets: Targets,
+    config: SharedConfig,
+) {
+    let initial = LedSet::scan(backend.as_ref());
+    let backend_for_apply = Arc::clone(&backend);
+    spawn_led_watcher(backend, initial, move |change| {
+        apply_change(backend_for_apply.as_ref(), &kb_targets, &lb_targets, &config, change)
+    });
+}
+
+fn apply_change(
+    backend: &dyn LedBackend,
+    kb_targets: &Targets,
+    lb_targets: &Targets,
+    config: &SharedConfig,
+    change: &LedChange,
+) {
+    let snapshot = config.lock().unwrap().clone();
+    let keyboard = &change.current.keyboard;
+    *kb_targets.lock().unwrap() = match pick_primary(keyboard) {
+        Some(primary) => keyboard_write_paths(keyboard, &primary, &snapshot.options),
+        None => Vec::new(),
+    };
+    *lb_targets.lock().unwrap() = change.current.lightbar.iter().cloned().collect();
+    if change.added.is_empty() {
+        return;
+    }
+    if let Err(e) = apply_lighting(backend, &snapshot.keyboard, snapshot.lightbar.as_ref(), &snapshot.options) {
+        eprintln!("Error: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::MemoryBackend;
+    use crate::config::{Config, LightbarConfig, Rgb};
+    use crate::layout::X6_KEYS;
+    use crate::workers::shared_targets;
+    use std::sync::Mutex;
+
+    fn attr(backend: &dyn LedBackend, name: &str, attr: &str) -> String {
+        backend.read_attr(&backend.led(name), attr).unwrap().trim().to_string()
+    }
+
+    #[test]
+    fn scans_keyboard_and_lightbar() {
+        let backend = MemoryBackend::simulated_x6();
+        let leds = LedSet::scan(&backend);
+        assert_eq!(leds.keyboard.len(), X6_KEYS.len());
+        assert_eq!(leds.lightbar, Some(backend.led("rgb:lightbar")));
+        assert_eq!(LedSet::scan(&MemoryBackend::new()), LedSet::default());
+    }
+
+    #[test]
+    fn lists_added_and_removed_leds() {
+        let backend = MemoryBackend::new();
+        backend.add_rgb_led("rgb:kbd_backlight", 50);
+        backend.add_rgb_led("rgb:lightbar", 100);
+        let old = LedSet::scan(&backend);
+        let new = LedSet { keyboard: vec![backend.led("rgb:kbd_backlight_1")], lightbar: old.lightbar.clone() };
+        let change = LedChange::between(&old, new.clone());
+        assert_eq!(change.added, [backend.led("rgb:kbd_backlight_1")]);
+        assert_eq!(change.removed, [backend.led("rgb:kbd_backlight")]);
+        assert_eq!(change.current, new);
+    }
+
+    #[test]
+    fn gives_appearing_leds_the_saved_state() {
+        let backend = MemoryBackend::new();
+        let before = LedSet::scan(&backend);
+        backend.add_rgb_led("rgb:kbd_backlight", 50);
+        backend.add_rgb_led("rgb:lightbar", 100);
+        let change = LedChange::between(&before, LedSet::scan(&backend));
+
+        let mut config = Config::default();
+        config.keyboard.color = Rgb(255, 0, 0);
+        config.lightbar = Some(LightbarConfig { color: Rgb(0, 0, 255), brightness: 50, max_brightness: 100 });
+        let config = Arc::new(Mutex::new(config));
+        let (kb_targets, lb_targets) = (shared_targets(Vec::new()), shared_targets(Vec::new()));
+        apply_change(&backend, &kb_targets, &lb_targets, &config, &change);
+
+        assert_eq!(*kb_targets.lock().unwrap(), [backend.led("rgb:kbd_backlight")]);
+        assert_eq!(*lb_targets.lock().unwrap(), [backend.led("rgb:lightbar")]);
+        assert_eq!(attr(&backend, "rgb:kbd_backlight", "multi_intensity"), "255 0 0");
+        assert_eq!(attr(&backend, "rgb:lightbar", "multi_intensity"), "0 0 255");
+        assert_eq!(attr(&backend, "rgb:lightbar", "brightness"), "50");
+
+        // LEDs that only go away are dropped from the targets, nothing is written.
+        config.lock().unwrap().keyboard.color = Rgb(0, 255, 0);
+        let without_keyboard = LedSet { keyboard: Vec::new(), lightbar: change.current.lightbar.clone() };
+        let gone = LedChange::between(&change.current, without_keyboard);
+        apply_change(&backend, &kb_targets, &lb_targets, &config, &gone);
+        assert!(kb_targets.lock().unwrap().is_empty());
+        assert_eq!(attr(&backend, "rgb:kbd_backlight", "multi_intensity"), "255 0 0");
+    }
+}
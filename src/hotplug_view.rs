@@ -0,0 +1,30 @@
+use crate::backend::LedBackend;
+use crate::hotplug::{self, HOTPLUG_INTERVAL};
+use crate::persist::SharedConfig;
+use crate::workers::Targets;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Keeps the window's workers on the LEDs that exist, like the daemon does, and
+/// calls `show_lightbar` whenever the lightbar comes, goes or is replaced.
+/// `shown` is the lightbar the window was built with.
+pub fn follow_hotplug(
+    backend: Arc<dyn LedBackend>,
+    kb_targets: Targets,
+    lb_targets: Targets,
+    config: SharedConfig,
+    shown: Option<PathBuf>,
+    show_lightbar: impl Fn(Option<&Path>) + 'static,
+) {
+    let lightbar = Arc::clone(&lb_targets);
+    let mut shown = shown;
+    glib::timeout_add_local(HOTPLUG_INTERVAL, move || {
+        let current = lightbar.lock().unwrap().first().cloned();
+        if current != shown {
+            show_lightbar(current.as_deref());
+            shown = current;
+        }
+        glib::ControlFlow::Continue
+    });
+    hotplug::follow_hotplug(backend, kb_targets, lb_targets, config);
+}
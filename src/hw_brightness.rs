@@ -0,0 +1,285 @@
+// Brightness changed by the firmware, e.g. with the Fn backlight keys. LEDs that
+// support it report such changes in `brightness_hw_changed`, which our own writes
+// don't touch. For the others `brightness` is compared with what this process
+// last wrote and with the saved value. The window, the daemon and the command
+// line all write it, and the saved value catches up with the others' writes
+// once their save is picked up (see persist.rs), so a difference only counts as
+// a hardware change once it outlasted that.
+
+use crate::ambient::auto_level;
+use crate::backend::LedBackend;
+use crate::idle::lighting_dimmed;
+use crate::leds::{
+    find_lightbar, pick_primary, read_brightness, read_max_brightness, written_brightness,
+    KB_BACKLIGHT_PATTERN,
+};
+use crate::persist::{
+    update_config, Brightness, SharedConfig, CONFIG_POLL, DEFAULT_KB_MAX_BRIGHTNESS,
+    DEFAULT_LB_MAX_BRIGHTNESS,
+};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub const BRIGHTNESS_POLL: Duration = Duration::from_secs(1);
+// How often the window moves its sliders to the changes found.
+pub const SLIDER_POLL: Duration = Duration::from_millis(250);
+// Long enough for another process's save to reach our config.
+const SETTLE: Duration = CONFIG_POLL.saturating_mul(2);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Target {
+    Keyboard,
+    Lightbar,
+}
+
+impl Target {
+    pub fn name(self) -> &'static str {
+        match self {
+            Target::Keyboard => "keyboard",
+            Target::Lightbar => "lightbar",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct HwBrightness {
+    pub target: Target,
+    pub brightness: Brightness,
+}
+
+#[derive(Default)]
+struct Watch {
+    path: Option<PathBuf>,
+    // Whether the LED has `brightness_hw_changed`, and its last value. Reading it
+    // fails with ENODATA until the first hardware change.
+    hw_attr: bool,
+    last_hw: Option<i32>,
+    // `brightness` differs from the saved value, since when.
+    pending: Option<(i32, Instant)>,
+}
+
+fn read_hw_changed(backend: &dyn LedBackend, path: &Path) -> io::Result<i32> {
+    backend
+        .read_attr(path, "brightness_hw_changed")?
+        .trim()
+        .parse()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+impl Watch {
+    fn follow(&mut self, backend: &dyn LedBackend, path: Option<PathBuf>) {
+        if path == self.path {
+            return;
+        }
+        *self = Watch { path, ..Watch::default() };
+        if let Some(path) = &self.path {
+            let hw = read_hw_changed(backend, path);
+            self.hw_attr = !matches!(&hw, Err(e) if e.kind() == io::ErrorKind::NotFound);
+            self.last_hw = hw.ok();
+        }
+    }
+
+    /// The new raw brightness if the hardware changed it since the last poll.
+    fn poll(&mut self, backend: &dyn LedBackend, saved: Option<Brightness>, max: i32) -> Option<i32> {
+        let path = self.path.as_ref()?;
+        if self.hw_attr {
+            let value = read_hw_changed(backend, path).ok()?;
+            let changed = self.last_hw != Some(value);
+            self.last_hw = Some(value);
+            return changed.then_some(value);
+        }
+        // Without a saved value there is nothing to tell our writes from others,
+        // and while idle dimming is on the LEDs are meant to differ from it.
+        let saved = saved?;
+        if lighting_dimmed() {
+            self.pending = None;
+            return None;
+        }
+        let actual = read_brightness(backend, path)?;
+        // Auto brightness levels are ours too, even before they are saved.
+        if saved.scaled_to(max) == actual || written_brightness(path) == Some(actual) || auto_level(path) == Some(actual) {
+            self.pending = None;
+            return None;
+        }
+        match self.pending {
+            Some((value, since)) if value == actual => {
+                if since.elapsed() < SETTLE {
+                    return None;
+                }
+                self.pending = None;
+                Some(actual)
+            }
+            _ => {
+                self.pending = Some((actual, Instant::now()));
+                None
+            }
+        }
+    }
+}
+
+/// Records hardware brightness changes in `config` and passes them to
+/// `on_change`, from a background thread.
+pub fn spawn_brightness_watcher(
+    backend: Arc<dyn LedBackend>,
+    config: SharedConfig,
+    tx_persist: mpsc::SyncSender<()>,
+    on_change: impl Fn(HwBrightness) + Send + 'static,
+) {
+    thread::spawn(move || {
+        let backend = backend.as_ref();
+        let mut keyboard = Watch::default();
+        let mut lightbar = Watch::default();
+        loop {
+            // Looked up every time, LEDs come and go with the controller.
+            keyboard.follow(backend, pick_primary(&backend.find(KB_BACKLIGHT_PATTERN)));
+            lightbar.follow(backend, find_lightbar(backend));
+            let (kb_saved, lb_saved) = {
+                let c = config.lock().unwrap();
+                (c.keyboard.brightness(), c.lightbar.as_ref().map(|lb| lb.brightness()))
+            };
+            let watches = [
+                (Target::Keyboard, &mut keyboard, Some(kb_saved), DEFAULT_KB_MAX_BRIGHTNESS),
+                (Target::Lightbar, &mut lightbar, lb_saved, DEFAULT_LB_MAX_BRIGHTNESS),
+            ];
+            for (target, watch, saved, default_max) in watches {
+                let max = watch
+                    .path
+                    .as_ref()
+                    .and_then(|p| read_max_brightness(backend, p))
+                    .unwrap_or(default_max);
+                let Some(value) = watch.poll(backend, saved, max) else {
+                    continue;
+                };
+                let brightness = Brightness::new(value, max);
+                let _ = update_config(&config, &tx_persist, |c| {
+                    match target {
+                        Target::Keyboard => {
+                            c.keyboard.brightness = brightness.value;
+                            c.keyboard.max_brightness = brightness.max;
+                        }
+                        Target::Lightbar => {
+                            if let Some(lb) = c.lightbar.as_mut() {
+                                lb.brightness = brightness.value;
+                                lb.max_brightness = brightness.max;
+                            }
+                        }
+                    }
+                    Ok(())
+                });
+                on_change(HwBrightness { target, brightness });
+            }
+            thread::sleep(BRIGHTNESS_POLL);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::MemoryBackend;
+    use crate::leds::write_brightness;
+    use crate::temp_dir::runtime_dir;
+    use std::fs;
+
+    fn watch(backend: &MemoryBackend, name: &str) -> (Watch, PathBuf) {
+        let path = backend.led(name);
+        let mut watch = Watch::default();
+        watch.follow(backend, Some(path.clone()));
+        (watch, path)
+    }
+
+    fn set(backend: &MemoryBackend, path: &Path, attr: &str, value: i32) {
+        backend.write_attr(path, attr, &format!("{}\n", value)).unwrap();
+    }
+
+    #[test]
+    fn reports_brightness_hw_changed() {
+        let backend = MemoryBackend::new();
+        backend.add_led("rgb:kbd_backlight_hw", &[("brightness", "10\n"), ("brightness_hw_changed", "10\n")]);
+        let (mut watch, path) = watch(&backend, "rgb:kbd_backlight_hw");
+        assert!(watch.hw_attr);
+        let saved = Some(Brightness::new(10, 50));
+        assert_eq!(watch.poll(&backend, saved, 50), None);
+        // Our own writes don't touch brightness_hw_changed.
+        set(&backend, &path, "brightness", 30);
+        assert_eq!(watch.poll(&backend, saved, 50), None);
+        set(&backend, &path, "brightness_hw_changed", 20);
+        assert_eq!(watch.poll(&backend, saved, 50), Some(20));
+        assert_eq!(watch.poll(&backend, saved, 50), None);
+    }
+
+    #[test]
+    fn reports_a_brightness_that_stays_off_the_saved_value() {
+        let _runtime = runtime_dir();
+        let backend = MemoryBackend::new();
+        backend.add_rgb_led("rgb:kbd_backlight_poll", 50);
+        let (mut watch, path) = watch(&backend, "rgb:kbd_backlight_poll");
+        assert!(!watch.hw_attr);
+        let saved = Some(Brightness::new(10, 50));
+        set(&backend, &path, "brightness", 10);
+        assert_eq!(watch.poll(&backend, saved, 50), None);
+        assert_eq!(watch.poll(&backend, None, 50), None);
+
+        set(&backend, &path, "brightness", 40);
+        assert_eq!(watch.poll(&backend, saved, 50), None);
+        assert!(watch.pending.is_some_and(|(value, _)| value == 40));
+        // Counted once it outlasted a save of another process.
+        watch.pending = Some((40, Instant::now() - SETTLE));
+        assert_eq!(watch.poll(&backend, saved, 50), Some(40));
+        assert_eq!(watch.pending, None);
+
+        // Back at the saved value before it settled: nothing happened.
+        set(&backend, &path, "brightness", 0);
+        assert_eq!(watch.poll(&backend, saved, 50), None);
+        set(&backend, &path, "brightness", 10);
+        assert_eq!(watch.poll(&backend, saved, 50), None);
+        assert_eq!(watch.pending, None);
+    }
+
+    #[test]
+    fn ignores_brightness_written_by_this_process() {
+        let _runtime = runtime_dir();
+        let backend = MemoryBackend::new();
+        backend.add_rgb_led("rgb:kbd_backlight_own", 50);
+        let (mut watch, path) = watch(&backend, "rgb:kbd_backlight_own");
+        let saved = Some(Brightness::new(10, 50));
+        write_brightness(&backend, &path, 25);
+        assert_eq!(watch.poll(&backend, saved, 50), None);
+        assert_eq!(watch.pending, None);
+    }
+
+    #[test]
+    fn ignores_brightness_while_dimmed_for_idleness() {
+        let runtime = runtime_dir();
+        let backend = MemoryBackend::new();
+        backend.add_rgb_led("rgb:kbd_backlight_idle", 50);
+        let (mut watch, path) = watch(&backend, "rgb:kbd_backlight_idle");
+        let saved = Some(Brightness::new(10, 50));
+        fs::write(runtime.join("rusty-kb-dimmed"), "").unwrap();
+        set(&backend, &path, "brightness", 2);
+        watch.pending = Some((2, Instant::now() - SETTLE));
+        assert_eq!(watch.poll(&backend, saved, 50), None);
+        assert_eq!(watch.pending, None);
+    }
+
+    #[test]
+    fn ignores_levels_set_by_auto_brightness() {
+        let runtime = runtime_dir();
+        let backend = MemoryBackend::new();
+        backend.add_rgb_led("rgb:kbd_backlight_auto", 50);
+        let (mut watch, path) = watch(&backend, "rgb:kbd_backlight_auto");
+        let saved = Some(Brightness::new(10, 50));
+        // Written by the daemon, not saved yet.
+        fs::write(runtime.join("rusty-kb-auto-levels"), format!("35 {}\n", path.display())).unwrap();
+        set(&backend, &path, "brightness", 35);
+        watch.pending = Some((35, Instant::now() - SETTLE));
+        assert_eq!(watch.poll(&backend, saved, 50), None);
+        // Anything else still counts.
+        set(&backend, &path, "brightness", 30);
+        watch.pending = Some((30, Instant::now() - SETTLE));
+        assert_eq!(watch.poll(&backend, saved, 50), Some(30));
+    }
+}
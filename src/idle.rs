@@ -0,0 +1,337 @@
+// Dims the keyboard and lightbar after a while without key presses and brings
+// them back on the next one. Only the LEDs change: the config keeps the normal
+// brightness. While dimmed (or switched off for suspend, see power.rs) a marker
+// file exists, so that the Fn-key watchers of the daemon and the window don't
+// take the dimmed LEDs for a brightness change, and so that the window and the
+// command line can wake the LEDs before using them; the daemon treats that like
+// a key press.
+
+use crate::backend::LedBackend;
+use crate::config::Config;
+use crate::input::{input_source, spawn_key_reader};
+use crate::leds::{
+    apply_lighting, find_lightbar, keyboard_write_paths, pick_primary, read_max_brightness,
+    write_brightness, write_brightness_all, KB_BACKLIGHT_PATTERN,
+};
+use crate::persist::{runtime_path, SharedConfig, DEFAULT_KB_MAX_BRIGHTNESS, DEFAULT_LB_MAX_BRIGHTNESS};
+use crate::power::{logind_connection, logind_proxy};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub const IDLE_POLL: Duration = Duration::from_secs(1);
+const FADE_STEPS: i32 = 20;
+const FADE_STEP: Duration = Duration::from_millis(50);
+
+fn marker_path() -> Option<PathBuf> {
+    runtime_path("dimmed")
+}
+
+/// Whether the LEDs are dimmed or switched off right now, by any process.
+pub fn lighting_dimmed() -> bool {
+    marker_path().is_some_and(|p| p.exists())
+}
+
+/// Runs `change` on the LEDs unless they are dimmed or switched off. Those get
+/// the config's state from `wake` when they come back, so nothing is lost;
+/// `None` then.
+pub fn unless_dimmed<T>(change: impl FnOnce() -> T) -> Option<T> {
+    (!lighting_dimmed()).then(change)
+}
+
+pub fn set_dimmed(dimmed: bool) {
+    let Some(path) = marker_path() else {
+        return;
+    };
+    let result = if dimmed { fs::write(&path, "") } else { fs::remove_file(&path) };
+    if let Err(e) = result
+        && e.kind() != std::io::ErrorKind::NotFound
+    {
+        eprintln!("Error: {}: {}", path.display(), e);
+    }
+}
+
+// The LEDs to dim with their normal raw brightness, from the saved config.
+struct Levels {
+    keyboard: Vec<PathBuf>,
+    keyboard_normal: i32,
+    lightbar: Option<(PathBuf, i32)>,
+}
+
+impl Levels {
+    fn of(backend: &dyn LedBackend, config: &Config) -> Self {
+        let all = backend.find(KB_BACKLIGHT_PATTERN);
+        let (keyboard, kb_max) = match pick_primary(&all) {
+            Some(primary) => (
+                keyboard_write_paths(&all, &primary, &config.options),
+                read_max_brightness(backend, &primary).unwrap_or(DEFAULT_KB_MAX_BRIGHTNESS),
+            ),
+            None => (Vec::new(), DEFAULT_KB_MAX_BRIGHTNESS),
+        };
+        // A lightbar the config doesn't know is left alone, there is nothing to
+        // bring it back to.
+        let lightbar = find_lightbar(backend).zip(config.lightbar.as_ref()).map(|(path, lb)| {
+            let max = read_max_brightness(backend, &path).unwrap_or(DEFAULT_LB_MAX_BRIGHTNESS);
+            let normal = lb.brightness().scaled_to(max);
+            (path, normal)
+        });
+        Levels {
+            keyboard,
+            keyboard_normal: config.keyboard.brightness().scaled_to(kb_max),
+            lightbar,
+        }
+    }
+
+    /// Writes `percent` of the normal brightness.
+    fn write(&self, backend: &dyn LedBackend, percent: i32) {
+        write_brightness_all(backend, &self.keyboard, self.keyboard_normal * percent / 100);
+        if let Some((path, normal)) = &self.lightbar {
+            write_brightness(backend, path, normal * percent / 100);
+        }
+    }
+}
+
+struct Activity {
+    last_input: Instant,
+    dimmed: bool,
+}
+
+fn restore(backend: &dyn LedBackend, config: &SharedConfig) {
+    wake(backend, &config.lock().unwrap().clone());
+}
+
+/// Switches the LEDs off without touching the saved brightness, until `wake`.
+pub fn switch_off(backend: &dyn LedBackend, config: &Config) {
+    set_dimmed(true);
+    Levels::of(backend, config).write(backend, 0);
+}
+
+/// Gives dimmed LEDs the state saved in `config` back, colors included as they
+/// may have changed meanwhile (e.g. by the schedule).
+pub fn wake(backend: &dyn LedBackend, config: &Config) {
+    if lighting_dimmed() {
+        if let Err(e) = apply_lighting(backend, &config.keyboard, config.lightbar.as_ref(), &config.options) {
+            eprintln!("Error: {}", e);
+        }
+        set_dimmed(false);
+    }
+}
+
+// Fades from full to `target` percent, giving up as soon as a key wakes the LEDs.
+fn fade_out(backend: &dyn LedBackend, config: &SharedConfig, activity: &Mutex<Activity>, target: i32) {
+    let snapshot = config.lock().unwrap().clone();
+    let levels = Levels::of(backend, &snapshot);
+    for step in 1..=FADE_STEPS {
+        {
+            // Held while writing, so a wake-up can't be overwritten by a fade step.
+            let activity = activity.lock().unwrap();
+            if !activity.dimmed {
+                return;
+            }
+            levels.write(backend, 100 - (100 - target) * step / FADE_STEPS);
+        }
+        thread::sleep(FADE_STEP);
+    }
+}
+
+// logind's IdleHint for the session the daemon runs in, set by desktops that
+// track the mouse and other devices too.
+fn session_idle(logind: &mut Option<zbus::blocking::Connection>) -> zbus::Result<bool> {
+    if logind.is_none() {
+        *logind = Some(logind_connection()?);
+    }
+    let session = logind_proxy(
+        logind.as_ref().unwrap(),
+        "/org/freedesktop/login1/session/auto",
+        "org.freedesktop.login1.Session",
+    )?;
+    session.get_property("IdleHint")
+}
+
+/// Runs the idle timer with the `[idle]` settings in `config`, which may change
+/// while it runs (e.g. with a profile). Key presses come from the keyboard's
+/// input device.
+pub fn spawn_idle_timer(backend: Arc<dyn LedBackend>, config: SharedConfig) {
+    // Left behind if the last run was killed while dimmed.
+    restore(backend.as_ref(), &config);
+    let keys = match input_source().and_then(|path| spawn_key_reader(&path)) {
+        Ok(keys) => keys,
+        Err(e) => {
+            if config.lock().unwrap().idle.after_minutes > 0 {
+                eprintln!("Error: idle dimming needs key presses: {}", e);
+            }
+            return;
+        }
+    };
+    let activity = Arc::new(Mutex::new(Activity { last_input: Instant::now(), dimmed: false }));
+
+    let activity_for_keys = Arc::clone(&activity);
+    let backend_for_keys = Arc::clone(&backend);
+    let config_for_keys = Arc::clone(&config);
+    thread::spawn(move || {
+        for _ in keys {
+            let mut activity = activity_for_keys.lock().unwrap();
+            activity.last_input = Instant::now();
+            if activity.dimmed {
+                activity.dimmed = false;
+                restore(backend_for_keys.as_ref(), &config_for_keys);
+            }
+        }
+    });
+
+    thread::spawn(move || {
+        let backend = backend.as_ref();
+        let mut logind = None;
+        let mut logind_failed = false;
+        loop {
+            thread::sleep(IDLE_POLL);
+            let idle = config.lock().unwrap().idle.clone();
+            let timeout = Duration::from_secs(u64::from(idle.after_minutes) * 60);
+            {
+                let mut activity = activity.lock().unwrap();
+                if idle.after_minutes == 0 {
+                    // Turned off (e.g. by a profile) while dimmed.
+                    if activity.dimmed {
+                        activity.dimmed = false;
+                        restore(backend, &config);
+                    }
+                    continue;
+                }
+                if activity.dimmed && !lighting_dimmed() {
+                    // Woken by the window or the command line.
+                    activity.dimmed = false;
+                    activity.last_input = Instant::now();
+                }
+                // Switched off for suspend or the lid, not ours to fade.
+                if activity.dimmed || lighting_dimmed() || activity.last_input.elapsed() < timeout {
+                    continue;
+                }
+            }
+            // Without the hint only the keyboard counts.
+            if idle.logind && !logind_failed {
+                match session_idle(&mut logind) {
+                    Ok(false) => continue,
+                    Ok(true) => {}
+                    Err(e) => {
+                        eprintln!("Error: could not read logind's idle hint, going by key presses only: {}", e);
+                        logind_failed = true;
+                    }
+                }
+            }
+            {
+                let mut activity = activity.lock().unwrap();
+                // A key may have come in while asking logind.
+                if activity.last_input.elapsed() < timeout {
+                    continue;
+                }
+                activity.dimmed = true;
+                set_dimmed(true);
+            }
+            fade_out(backend, &config, &activity, i32::from(idle.brightness));
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::MemoryBackend;
+    use crate::config::{LightbarConfig, Rgb};
+    use crate::leds::read_brightness;
+    use crate::temp_dir::runtime_dir;
+
+    // A keyboard at 40 of 50 and a lightbar at 60 of 100.
+    fn setup() -> (MemoryBackend, Config) {
+        let backend = MemoryBackend::new();
+        backend.add_rgb_led("rgb:kbd_backlight", 50);
+        backend.add_rgb_led("rgb:lightbar", 100);
+        let mut config = Config::default();
+        config.keyboard.brightness = 40;
+        config.keyboard.max_brightness = 50;
+        config.lightbar = Some(LightbarConfig {
+            color: Rgb(255, 255, 255),
+            brightness: 60,
+            max_brightness: 100,
+        });
+        (backend, config)
+    }
+
+    fn brightness(backend: &MemoryBackend, name: &str) -> i32 {
+        read_brightness(backend, &backend.led(name)).unwrap()
+    }
+
+    #[test]
+    fn marks_the_lighting_dimmed() {
+        let runtime = runtime_dir();
+        assert!(!lighting_dimmed());
+        set_dimmed(true);
+        assert!(runtime.join("rusty-kb-dimmed").exists());
+        assert!(lighting_dimmed());
+        set_dimmed(false);
+        assert!(!lighting_dimmed());
+        // Waking twice is fine.
+        set_dimmed(false);
+        assert_eq!(unless_dimmed(|| 1), Some(1));
+        set_dimmed(true);
+        assert_eq!(unless_dimmed(|| 1), None);
+        set_dimmed(false);
+    }
+
+    #[test]
+    fn writes_a_share_of_the_saved_brightness() {
+        let (backend, config) = setup();
+        let levels = Levels::of(&backend, &config);
+        assert_eq!(levels.keyboard, [backend.led("rgb:kbd_backlight")]);
+        levels.write(&backend, 50);
+        assert_eq!(brightness(&backend, "rgb:kbd_backlight"), 20);
+        assert_eq!(brightness(&backend, "rgb:lightbar"), 30);
+        levels.write(&backend, 100);
+        assert_eq!(brightness(&backend, "rgb:kbd_backlight"), 40);
+        assert_eq!(brightness(&backend, "rgb:lightbar"), 60);
+    }
+
+    #[test]
+    fn wakes_only_dimmed_leds() {
+        let _runtime = runtime_dir();
+        let (backend, config) = setup();
+        wake(&backend, &config);
+        assert_eq!(brightness(&backend, "rgb:kbd_backlight"), 0);
+
+        set_dimmed(true);
+        wake(&backend, &config);
+        assert!(!lighting_dimmed());
+        assert_eq!(brightness(&backend, "rgb:kbd_backlight"), 40);
+        assert_eq!(brightness(&backend, "rgb:lightbar"), 60);
+    }
+
+    #[test]
+    fn switches_off_until_woken() {
+        let _runtime = runtime_dir();
+        let (backend, config) = setup();
+        Levels::of(&backend, &config).write(&backend, 100);
+        switch_off(&backend, &config);
+        assert!(lighting_dimmed());
+        assert_eq!(brightness(&backend, "rgb:kbd_backlight"), 0);
+        assert_eq!(brightness(&backend, "rgb:lightbar"), 0);
+        wake(&backend, &config);
+        assert_eq!(brightness(&backend, "rgb:kbd_backlight"), 40);
+    }
+
+    #[test]
+    fn fades_out_until_woken() {
+        let (backend, config) = setup();
+        let config: SharedConfig = Arc::new(Mutex::new(config));
+        let activity = Mutex::new(Activity { last_input: Instant::now(), dimmed: true });
+        fade_out(&backend, &config, &activity, 25);
+        assert_eq!(brightness(&backend, "rgb:kbd_backlight"), 10);
+        assert_eq!(brightness(&backend, "rgb:lightbar"), 15);
+
+        // A key press before the fade leaves the LEDs alone.
+        Levels::of(&backend, &config.lock().unwrap()).write(&backend, 100);
+        activity.lock().unwrap().dimmed = false;
+        fade_out(&backend, &config, &activity, 0);
+        assert_eq!(brightness(&backend, "rgb:kbd_backlight"), 40);
+    }
+}
@@ -0,0 +1,234 @@
+// Key presses from the kernel's evdev interface. Events are read as raw
+// `struct input_event` records, so a recording made with
+// `cat /dev/input/eventN > typing.bin` plays back like the device itself, and a
+// uinput virtual keyboard works like a real one.
+
+use crate::layout::{key, Key};
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+// `RUSTY_KB_INPUT=/dev/input/event5` or a recording; otherwise the keyboard is
+// looked up in /proc/bus/input/devices.
+pub const INPUT_ENV: &str = "RUSTY_KB_INPUT";
+const INPUT_DEVICES: &str = "/proc/bus/input/devices";
+
+const EV_KEY: u16 = 0x01;
+const KEY_PRESSED: i32 = 1;
+// 64-bit `struct timeval` followed by `__u16 type`, `__u16 code` and `__s32 value`.
+const EVENT_SIZE: usize = 24;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InputEvent {
+    pub time: Duration,
+    pub kind: u16,
+    pub code: u16,
+    pub value: i32,
+}
+
+pub fn parse_event(buf: &[u8; EVENT_SIZE]) -> InputEvent {
+    let sec = i64::from_ne_bytes(buf[0..8].try_into().unwrap());
+    let usec = i64::from_ne_bytes(buf[8..16].try_into().unwrap());
+    InputEvent {
+        time: Duration::from_secs(sec.max(0) as u64) + Duration::from_micros(usec.max(0) as u64),
+        kind: u16::from_ne_bytes(buf[16..18].try_into().unwrap()),
+        code: u16::from_ne_bytes(buf[18..20].try_into().unwrap()),
+        value: i32::from_ne_bytes(buf[20..24].try_into().unwrap()),
+    }
+}
+
+// Key codes from linux/input-event-codes.h. `fn` is handled by the firmware and
+// never reaches evdev.
+#[rustfmt::skip]
+const KEY_CODES: &[(u16, &str)] = &[
+    (1, "esc"), (2, "1"), (3, "2"), (4, "3"), (5, "4"), (6, "5"), (7, "6"), (8, "7"),
+    (9, "8"), (10, "9"), (11, "0"), (12, "-"), (13, "="), (14, "backspace"), (15, "tab"),
+    (16, "q"), (17, "w"), (18, "e"), (19, "r"), (20, "t"), (21, "y"), (22, "u"), (23, "i"),
+    (24, "o"), (25, "p"), (26, "["), (27, "]"), (28, "return"), (29, "l-ctrl"), (30, "a"),
+    (31, "s"), (32, "d"), (33, "f"), (34, "g"), (35, "h"), (36, "j"), (37, "k"), (38, "l"),
+    (39, ";"), (40, "'"), (41, "~"), (42, "l-shift"), (43, "\\"), (44, "z"), (45, "x"),
+    (46, "c"), (47, "v"), (48, "b"), (49, "n"), (50, "m"), (51, ","), (52, "."), (53, "/"),
+    (54, "r-shift"), (55, "num-*"), (56, "alt"), (57, "space"), (58, "caps"), (59, "f1"),
+    (60, "f2"), (61, "f3"), (62, "f4"), (63, "f5"), (64, "f6"), (65, "f7"), (66, "f8"),
+    (67, "f9"), (68, "f10"), (69, "num"), (70, "sc"), (71, "num-7"), (72, "num-8"),
+    (73, "num-9"), (74, "num--"), (75, "num-4"), (76, "num-5"), (77, "num-6"), (78, "num-+"),
+    (79, "num-1"), (80, "num-2"), (81, "num-3"), (82, "num-0"), (83, "num-."), (86, "<"),
+    (87, "f11"), (88, "f12"), (96, "num-return"), (97, "r-ctrl"), (98, "num-/"),
+    (99, "prtsc"), (100, "altgr"), (102, "home"), (103, "up-arrow"), (104, "pgup"),
+    (105, "left-arrow"), (106, "right-arrow"), (107, "end"), (108, "down-arrow"),
+    (109, "pgdn"), (111, "del"), (125, "super"),
+];
+
+pub fn key_for_code(code: u16) -> Option<&'static Key> {
+    KEY_CODES
+        .iter()
+        .find(|(c, _)| *c == code)
+        .and_then(|(_, name)| key(name))
+}
+
+// A device with the `kbd` handler that reports key, repeat and LED events
+// (EV=120013), i.e. a real keyboard rather than a power button or hotkey device.
+fn keyboard_in_devices(content: &str) -> Option<PathBuf> {
+    content.split("\n\n").find_map(|block| {
+        let handlers = block.lines().find_map(|l| l.strip_prefix("H: Handlers="))?;
+        let ev = block.lines().find_map(|l| l.strip_prefix("B: EV="))?;
+        let words: Vec<&str> = handlers.split_whitespace().collect();
+        if !words.contains(&"kbd") || ev.trim() != "120013" {
+            return None;
+        }
+        let event = words.iter().find(|w| w.starts_with("event"))?;
+        Some(Path::new("/dev/input").join(event))
+    })
+}
+
+pub fn input_source() -> Result<PathBuf, String> {
+    if let Ok(path) = env::var(INPUT_ENV) {
+        return Ok(PathBuf::from(path));
+    }
+    let devices = fs::read_to_string(INPUT_DEVICES)
+        .map_err(|e| format!("{}: {}", INPUT_DEVICES, e))?;
+    keyboard_in_devices(&devices).ok_or_else(|| "no keyboard input device found".to_string())
+}
+
+fn read_event(reader: &mut impl Read) -> io::Result<Option<InputEvent>> {
+    let mut buf = [0u8; EVENT_SIZE];
+    match reader.read_exact(&mut buf) {
+        Ok(()) => Ok(Some(parse_event(&buf))),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Sends the name of every pressed key (auto-repeat excluded) until the source
+/// ends or the receiver is dropped. A recording is replayed at its own pace.
+pub fn spawn_key_reader(path: &Path) -> Result<mpsc::Receiver<&'static str>, String> {
+    let file = File::open(path).map_err(|e| match e.kind() {
+        io::ErrorKind::PermissionDenied => format!(
+            "{}: permission denied, reading key presses needs the 'input' group",
+            path.display()
+        ),
+        _ => format!("{}: {}", path.display(), e),
+    })?;
+    let recording = file.metadata().is_ok_and(|m| m.is_file());
+    let (tx, rx) = mpsc::channel();
+    let path = path.to_path_buf();
+    thread::spawn(move || {
+        let mut reader = io::BufReader::new(file);
+        let mut previous: Option<Duration> = None;
+        loop {
+            let event = match read_event(&mut reader) {
+                Ok(Some(event)) => event,
+                Ok(None) => break,
+                Err(e) => {
+                    eprintln!("Error: reading {}: {}", path.display(), e);
+                    break;
+                }
+            };
+            if event.kind != EV_KEY || event.value != KEY_PRESSED {
+                continue;
+            }
+            if recording {
+                if let Some(gap) = previous.and_then(|p| event.time.checked_sub(p)) {
+                    thread::sleep(gap.min(Duration::from_secs(5)));
+                }
+                previous = Some(event.time);
+            }
+            if let Some(k) = key_for_code(event.code)
+                && tx.send(k.name).is_err()
+            {
+                break;
+            }
+        }
+    });
+    Ok(rx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp_dir::TempDir;
+
+    const EV_SYN: u16 = 0x00;
+    const EV_MSC: u16 = 0x04;
+
+    fn event(time: Duration, kind: u16, code: u16, value: i32) -> [u8; EVENT_SIZE] {
+        let mut buf = [0u8; EVENT_SIZE];
+        buf[0..8].copy_from_slice(&(time.as_secs() as i64).to_ne_bytes());
+        buf[8..16].copy_from_slice(&(time.subsec_micros() as i64).to_ne_bytes());
+        buf[16..18].copy_from_slice(&kind.to_ne_bytes());
+        buf[18..20].copy_from_slice(&code.to_ne_bytes());
+        buf[20..24].copy_from_slice(&value.to_ne_bytes());
+        buf
+    }
+
+    // What an AT keyboard and a power button look like in /proc/bus/input/devices.
+    const DEVICES: &str = "\
+I: Bus=0019 Vendor=0000 Product=0001 Version=0000
+N: Name=\"Power Button\"
+H: Handlers=kbd event2
+B: PROP=0
+B: EV=3
+B: KEY=10000000000000 0
+
+I: Bus=0011 Vendor=0001 Product=0001 Version=ab83
+N: Name=\"AT Translated Set 2 keyboard\"
+H: Handlers=sysrq kbd leds event3
+B: PROP=0
+B: EV=120013
+B: LED=7
+";
+
+    #[test]
+    fn parses_events() {
+        let time = Duration::from_millis(1_500);
+        assert_eq!(
+            parse_event(&event(time, EV_KEY, 30, KEY_PRESSED)),
+            InputEvent { time, kind: EV_KEY, code: 30, value: KEY_PRESSED }
+        );
+        assert_eq!(key_for_code(30).map(|k| k.name), Some("a"));
+        assert_eq!(key_for_code(103).map(|k| k.name), Some("up-arrow"));
+        assert!(key_for_code(0).is_none());
+    }
+
+    #[test]
+    fn finds_the_keyboard_among_input_devices() {
+        assert_eq!(keyboard_in_devices(DEVICES), Some(PathBuf::from("/dev/input/event3")));
+        let without_keyboard = DEVICES.split("\n\n").next().unwrap();
+        assert_eq!(keyboard_in_devices(without_keyboard), None);
+        assert_eq!(keyboard_in_devices(""), None);
+    }
+
+    #[test]
+    fn replays_a_recording_of_key_presses() {
+        let ms = Duration::from_millis;
+        let events = [
+            event(ms(0), EV_MSC, 4, 30),
+            event(ms(0), EV_KEY, 30, KEY_PRESSED),
+            event(ms(0), EV_SYN, 0, 0),
+            event(ms(300), EV_KEY, 30, 2),
+            event(ms(350), EV_KEY, 30, 0),
+            event(ms(350), EV_KEY, 0x1d0, KEY_PRESSED),
+            event(ms(400), EV_KEY, 57, KEY_PRESSED),
+            event(ms(400), EV_SYN, 0, 0),
+        ];
+        let dir = TempDir::new("input");
+        let path = dir.join("typing.bin");
+        fs::write(&path, events.concat()).unwrap();
+        let started = std::time::Instant::now();
+        let presses: Vec<&str> = spawn_key_reader(&path).unwrap().iter().collect();
+        // Auto-repeat, releases, other event types and unknown keys are left out,
+        // and the recording plays at its own pace.
+        assert_eq!(presses, ["a", "space"]);
+        assert!(started.elapsed() >= ms(400));
+    }
+
+    #[test]
+    fn a_missing_input_device_is_reported() {
+        let error = spawn_key_reader(Path::new("/nonexistent/event0")).unwrap_err();
+        assert!(error.starts_with("/nonexistent/event0:"));
+    }
+}
@@ -0,0 +1,251 @@
+use crate::backend::LedBackend;
+use crate::layout::{key_rects, Key, KeyMap, KeyRect, BOARD_HEIGHT, BOARD_WIDTH};
+use crate::leds::{pack_rgb, read_key_colors};
+use crate::config::Rgb;
+use crate::persist::{persist_key_colors, SharedConfig};
+use crate::rgba_to_rgb8;
+use crate::workers::{notify_coalescer, spawn_key_color_worker};
+use gdk4::RGBA;
+use gtk4::cairo::{Context, FontSlant, FontWeight};
+use gtk4::gio;
+use gtk4::prelude::*;
+use gtk4::{Box, Button, ColorButton, DrawingArea, GestureDrag, Label, Orientation};
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+use std::rc::Rc;
+use std::sync::{mpsc, Arc};
+use std::time::{Duration, Instant};
+
+type KeyColors = BTreeMap<&'static str, (u8, u8, u8)>;
+
+// Gap between drawn keys, in key units.
+const KEY_GAP: f64 = 0.08;
+// Don't overwrite freshly painted keys with a readback taken before the write landed.
+const READBACK_QUIET: Duration = Duration::from_secs(2);
+
+struct Board {
+    rects: Vec<(&'static Key, KeyRect)>,
+    scale: Cell<f64>,
+    origin: Cell<(f64, f64)>,
+}
+
+impl Board {
+    fn fit(&self, width: f64, height: f64) {
+        let scale = (width / BOARD_WIDTH).min(height / BOARD_HEIGHT);
+        self.scale.set(scale);
+        self.origin.set((
+            (width - BOARD_WIDTH * scale) / 2.0,
+            (height - BOARD_HEIGHT * scale) / 2.0,
+        ));
+    }
+
+    fn key_at(&self, x: f64, y: f64) -> Option<&'static Key> {
+        let scale = self.scale.get();
+        if scale <= 0.0 {
+            return None;
+        }
+        let (ox, oy) = self.origin.get();
+        let (kx, ky) = ((x - ox) / scale, (y - oy) / scale);
+        self.rects
+            .iter()
+            .find(|(_, rect)| rect.contains(kx, ky))
+            .map(|(k, _)| *k)
+    }
+}
+
+fn draw_board(cr: &Context, board: &Board, colors: &KeyColors, key_map: &KeyMap) {
+    let scale = board.scale.get();
+    let (ox, oy) = board.origin.get();
+    cr.select_font_face("Sans", FontSlant::Normal, FontWeight::Normal);
+    cr.set_font_size(scale * 0.28);
+    cr.set_line_width(1.0);
+    for (key, rect) in &board.rects {
+        let x = ox + (rect.x + KEY_GAP / 2.0) * scale;
+        let y = oy + (rect.y + KEY_GAP / 2.0) * scale;
+        let w = (rect.width - KEY_GAP) * scale;
+        let h = (rect.height - KEY_GAP) * scale;
+        cr.rectangle(x, y, w, h);
+
+        let present = key_map.path(key.name).is_some();
+        let (r, g, b) = colors.get(key.name).copied().unwrap_or((40, 40, 40));
+        if present {
+            cr.set_source_rgb(r as f64 / 255.0, g as f64 / 255.0, b as f64 / 255.0);
+            let _ = cr.fill_preserve();
+        }
+        cr.set_source_rgb(0.2, 0.2, 0.2);
+        let _ = cr.stroke();
+
+        // Dark text on bright keys, light text on dark ones.
+        let luma = 0.299 * r as f64 + 0.587 * g as f64 + 0.114 * b as f64;
+        if present && luma > 140.0 {
+            cr.set_source_rgb(0.0, 0.0, 0.0);
+        } else {
+            cr.set_source_rgb(0.85, 0.85, 0.85);
+        }
+        if let Ok(ext) = cr.text_extents(key.name) {
+            cr.move_to(
+                x + (w - ext.width()) / 2.0 - ext.x_bearing(),
+                y + (h - ext.height()) / 2.0 - ext.y_bearing(),
+            );
+            let _ = cr.show_text(key.name);
+        }
+    }
+}
+
+/// Drawn X6 keyboard: click or drag across keys to paint them with the chosen color.
+/// Save stores the keys that differ from the keyboard color in the config.
+pub fn keyboard_painter(
+    backend: Arc<dyn LedBackend>,
+    key_map: Arc<KeyMap>,
+    config: SharedConfig,
+    tx_persist: mpsc::SyncSender<()>,
+) -> Box {
+    let section = Box::new(Orientation::Vertical, 8);
+
+    let controls = Box::new(Orientation::Horizontal, 10);
+    controls.append(&Label::new(Some("Paint:")));
+    let color_button = ColorButton::with_rgba(&RGBA::new(1.0, 1.0, 1.0, 1.0));
+    controls.append(&color_button);
+    let refresh_btn = Button::with_label("Refresh");
+    controls.append(&refresh_btn);
+    let save_btn = Button::with_label("Save");
+    controls.append(&save_btn);
+    section.append(&controls);
+
+    let board = Rc::new(Board {
+        rects: key_rects(),
+        scale: Cell::new(0.0),
+        origin: Cell::new((0.0, 0.0)),
+    });
+    // Filled in by the first readback once the board is shown.
+    let colors: Rc<RefCell<KeyColors>> = Rc::new(RefCell::new(BTreeMap::new()));
+    let last_paint = Rc::new(Cell::new(None::<Instant>));
+    let (pending_keys, tx_keys) = spawn_key_color_worker(Arc::clone(&backend));
+
+    let area = DrawingArea::new();
+    area.set_content_width(640);
+    area.set_content_height(200);
+    area.set_hexpand(true);
+    let board_for_draw = Rc::clone(&board);
+    let colors_for_draw = Rc::clone(&colors);
+    let key_map_for_draw = Arc::clone(&key_map);
+    area.set_draw_func(move |_, cr, width, height| {
+        board_for_draw.fit(width as f64, height as f64);
+        draw_board(cr, &board_for_draw, &colors_for_draw.borrow(), &key_map_for_draw);
+    });
+
+    let paint_at = {
+        let board = Rc::clone(&board);
+        let colors = Rc::clone(&colors);
+        let key_map = Arc::clone(&key_map);
+        let last_paint = Rc::clone(&last_paint);
+        let color_button = color_button.clone();
+        move |area: &DrawingArea, x: f64, y: f64| {
+            let Some(key) = board.key_at(x, y) else { return };
+            let Some(path) = key_map.path(key.name) else { return };
+            let rgb = rgba_to_rgb8(&color_button.rgba());
+            if colors.borrow_mut().insert(key.name, rgb) == Some(rgb) {
+                return;
+            }
+            last_paint.set(Some(Instant::now()));
+            pending_keys
+                .lock()
+                .unwrap()
+                .insert(path.clone(), pack_rgb(rgb.0, rgb.1, rgb.2));
+            notify_coalescer(&tx_keys);
+            area.queue_draw();
+        }
+    };
+    let paint_at = Rc::new(paint_at);
+
+    // A plain click is a drag without movement, so one gesture covers both.
+    let drag = GestureDrag::new();
+    let paint_on_begin = Rc::clone(&paint_at);
+    let area_for_begin = area.clone();
+    drag.connect_drag_begin(move |_, x, y| {
+        paint_on_begin(&area_for_begin, x, y);
+    });
+    let paint_on_update = Rc::clone(&paint_at);
+    let area_for_update = area.clone();
+    drag.connect_drag_update(move |gesture, dx, dy| {
+        if let Some((x, y)) = gesture.start_point() {
+            paint_on_update(&area_for_update, x + dx, y + dy);
+        }
+    });
+    area.add_controller(drag);
+    section.append(&area);
+
+    // Reading every key takes a sysfs round trip per LED, so it runs on a worker
+    // thread; at most one readback is in flight and none while the board is hidden.
+    let reading = Rc::new(Cell::new(false));
+    let refresh = {
+        let backend = Arc::clone(&backend);
+        let key_map = Arc::clone(&key_map);
+        let colors = Rc::clone(&colors);
+        let last_paint = Rc::clone(&last_paint);
+        let area = area.downgrade();
+        move || -> bool {
+            let Some(area) = area.upgrade() else { return false };
+            if !area.is_mapped() || reading.replace(true) {
+                return true;
+            }
+            let started = Instant::now();
+            let backend = Arc::clone(&backend);
+            let key_map = Arc::clone(&key_map);
+            let colors = Rc::clone(&colors);
+            let last_paint = Rc::clone(&last_paint);
+            let reading = Rc::clone(&reading);
+            glib::spawn_future_local(async move {
+                let read =
+                    gio::spawn_blocking(move || read_key_colors(backend.as_ref(), &key_map)).await;
+                reading.set(false);
+                // Keys painted meanwhile may not have reached the hardware yet.
+                let painted = last_paint.get().is_some_and(|t| t >= started);
+                if let Ok(read) = read
+                    && !painted
+                {
+                    *colors.borrow_mut() = read;
+                    area.queue_draw();
+                }
+            });
+            true
+        }
+    };
+    let refresh = Rc::new(refresh);
+
+    let refresh_for_btn = Rc::clone(&refresh);
+    refresh_btn.connect_clicked(move |_| {
+        refresh_for_btn();
+    });
+    let refresh_for_map = Rc::clone(&refresh);
+    area.connect_map(move |_| {
+        refresh_for_map();
+    });
+
+    // Live readback so changes made elsewhere (presets, other tools) show up.
+    let refresh_for_timer = Rc::clone(&refresh);
+    glib::timeout_add_local(Duration::from_secs(1), move || {
+        let painting = last_paint
+            .get()
+            .is_some_and(|t| t.elapsed() < READBACK_QUIET);
+        if painting || refresh_for_timer() {
+            glib::ControlFlow::Continue
+        } else {
+            glib::ControlFlow::Break
+        }
+    });
+
+    let colors_for_save = Rc::clone(&colors);
+    save_btn.connect_clicked(move |_| {
+        let base = config.lock().unwrap().keyboard.color.tuple();
+        let overrides = colors_for_save
+            .borrow()
+            .iter()
+            .filter(|(_, rgb)| **rgb != base)
+            .map(|(name, (r, g, b))| (name.to_string(), Rgb(*r, *g, *b)))
+            .collect();
+        persist_key_colors(&config, &tx_persist, overrides);
+    });
+
+    section
+}
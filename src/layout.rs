@@ -0,0 +1,461 @@
+// Key map of the X6 keyboard (US/ISO layout), ported from the `KEY_MAP` of the old setcolor.sh.
+//
+// The controller exposes one LED class device per key. The first one is named
+// `rgb:kbd_backlight`, the others `rgb:kbd_backlight_N`, and N follows the key
+// matrix: 21 columns per row, counted from the bottom-left key (left Ctrl = 0)
+// upwards to the function row (Esc = 105).
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+const MATRIX_COLUMNS: u16 = 21;
+const MATRIX_ROWS: u16 = 6;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Key {
+    pub name: &'static str,
+    pub led: u16,
+}
+
+impl Key {
+    /// Row in the key matrix, 0 being the function row at the top.
+    pub fn row(&self) -> u16 {
+        MATRIX_ROWS - 1 - self.led / MATRIX_COLUMNS
+    }
+
+    /// Column in the key matrix, 0 being the leftmost key.
+    pub fn col(&self) -> u16 {
+        self.led % MATRIX_COLUMNS
+    }
+}
+
+const fn k(name: &'static str, led: u16) -> Key {
+    Key { name, led }
+}
+
+pub const X6_KEYS: &[Key] = &[
+    // Bottom row
+    k("l-ctrl", 0),
+    k("fn", 2),
+    k("super", 3),
+    k("alt", 4),
+    k("space", 7),
+    k("altgr", 10),
+    k("r-ctrl", 12),
+    k("left-arrow", 13),
+    k("up-arrow", 14),
+    k("right-arrow", 15),
+    k("num-0", 16),
+    k("num-.", 17),
+    k("down-arrow", 18),
+    // Shift row
+    k("l-shift", 22),
+    k("<", 23),
+    k("z", 24),
+    k("x", 25),
+    k("c", 26),
+    k("v", 27),
+    k("b", 28),
+    k("n", 29),
+    k("m", 30),
+    k(",", 31),
+    k(".", 32),
+    k("/", 33),
+    k("r-shift", 35),
+    k("num-1", 36),
+    k("num-2", 37),
+    k("num-3", 38),
+    k("num-return", 39),
+    // Home row
+    k("caps", 42),
+    k("a", 44),
+    k("s", 45),
+    k("d", 46),
+    k("f", 47),
+    k("g", 48),
+    k("h", 49),
+    k("j", 50),
+    k("k", 51),
+    k("l", 52),
+    k(";", 53),
+    k("'", 54),
+    k("\\", 55),
+    k("num-4", 57),
+    k("num-5", 58),
+    k("num-6", 59),
+    // Top letter row
+    k("tab", 63),
+    k("q", 65),
+    k("w", 66),
+    k("e", 67),
+    k("r", 68),
+    k("t", 69),
+    k("y", 70),
+    k("u", 71),
+    k("i", 72),
+    k("o", 73),
+    k("p", 74),
+    k("[", 75),
+    k("]", 76),
+    k("return", 77),
+    k("num-7", 78),
+    k("num-8", 79),
+    k("num-9", 80),
+    k("num-+", 81),
+    // Number row
+    k("~", 84),
+    k("1", 85),
+    k("2", 86),
+    k("3", 87),
+    k("4", 88),
+    k("5", 89),
+    k("6", 90),
+    k("7", 91),
+    k("8", 92),
+    k("9", 93),
+    k("0", 94),
+    k("-", 95),
+    k("=", 96),
+    k("backspace", 98),
+    k("num", 99),
+    k("num-/", 100),
+    k("num-*", 101),
+    k("num--", 102),
+    // Function row
+    k("esc", 105),
+    k("f1", 106),
+    k("f2", 107),
+    k("f3", 108),
+    k("f4", 109),
+    k("f5", 110),
+    k("f6", 111),
+    k("f7", 112),
+    k("f8", 113),
+    k("f9", 114),
+    k("f10", 115),
+    k("f11", 116),
+    k("f12", 117),
+    k("sc", 118),
+    k("prtsc", 119),
+    k("del", 120),
+    k("home", 121),
+    k("pgup", 122),
+    k("pgdn", 123),
+    k("end", 124),
+];
+
+pub const GROUPS: &[&str] = &[
+    "all",
+    "letters",
+    "digits",
+    "function",
+    "numpad",
+    "arrows",
+    "modifiers",
+    "navigation",
+    "wasd",
+    "function-row",
+    "number-row",
+    "top-row",
+    "home-row",
+    "shift-row",
+    "bottom-row",
+];
+
+pub fn led_name(led: u16) -> String {
+    if led == 0 {
+        "rgb:kbd_backlight".to_string()
+    } else {
+        format!("rgb:kbd_backlight_{}", led)
+    }
+}
+
+/// Inverse of [`led_name`] for a sysfs path.
+pub fn led_index(path: &Path) -> Option<u16> {
+    let name = path.file_name()?.to_str()?;
+    if name == "rgb:kbd_backlight" {
+        return Some(0);
+    }
+    name.strip_prefix("rgb:kbd_backlight_")?.parse().ok()
+}
+
+pub fn key(name: &str) -> Option<&'static Key> {
+    X6_KEYS.iter().find(|k| k.name == name)
+}
+
+fn keys_in_row(row: u16) -> Vec<&'static Key> {
+    let mut keys: Vec<&Key> = X6_KEYS.iter().filter(|k| k.row() == row).collect();
+    keys.sort_by_key(|k| k.col());
+    keys
+}
+
+fn named(names: &[&str]) -> Vec<&'static Key> {
+    names.iter().filter_map(|n| key(n)).collect()
+}
+
+pub fn group(name: &str) -> Option<Vec<&'static Key>> {
+    let keys = match name {
+        "all" => X6_KEYS.iter().collect(),
+        "letters" => X6_KEYS
+            .iter()
+            .filter(|k| k.name.len() == 1 && k.name.as_bytes()[0].is_ascii_lowercase())
+            .collect(),
+        "digits" => X6_KEYS
+            .iter()
+            .filter(|k| k.name.len() == 1 && k.name.as_bytes()[0].is_ascii_digit())
+            .collect(),
+        "function" => X6_KEYS
+            .iter()
+            .filter(|k| k.name.starts_with('f') && k.name[1..].parse::<u8>().is_ok())
+            .collect(),
+        "numpad" => X6_KEYS
+            .iter()
+            .filter(|k| k.name == "num" || k.name.starts_with("num-"))
+            .collect(),
+        "arrows" => named(&["left-arrow", "up-arrow", "down-arrow", "right-arrow"]),
+        "modifiers" => named(&[
+            "l-ctrl", "fn", "super", "alt", "altgr", "r-ctrl", "l-shift", "r-shift", "caps",
+        ]),
+        "navigation" => named(&["prtsc", "sc", "del", "home", "pgup", "pgdn", "end"]),
+        "wasd" => named(&["w", "a", "s", "d"]),
+        "function-row" => keys_in_row(0),
+        "number-row" => keys_in_row(1),
+        "top-row" => keys_in_row(2),
+        "home-row" => keys_in_row(3),
+        "shift-row" => keys_in_row(4),
+        "bottom-row" => keys_in_row(5),
+        _ => return None,
+    };
+    Some(keys)
+}
+
+/// Resolves a comma separated list of key and group names, e.g. `wasd,arrows,space`.
+pub fn parse_selection(spec: &str) -> Result<Vec<&'static Key>, String> {
+    let mut out: Vec<&'static Key> = Vec::new();
+    for part in spec.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+        let keys = match group(part) {
+            Some(keys) => keys,
+            None => vec![key(part).ok_or_else(|| {
+                format!("unknown key or group '{}' (groups: {})", part, GROUPS.join(", "))
+            })?],
+        };
+        for k in keys {
+            if !out.contains(&k) {
+                out.push(k);
+            }
+        }
+    }
+    Ok(out)
+}
+
+/// The keys of the layout matched to the LED paths that were actually found.
+pub struct KeyMap {
+    paths: BTreeMap<&'static str, PathBuf>,
+}
+
+impl KeyMap {
+    pub fn resolve(found: &[PathBuf]) -> Self {
+        let mut paths = BTreeMap::new();
+        for p in found {
+            let Some(led) = led_index(p) else { continue };
+            if let Some(k) = X6_KEYS.iter().find(|k| k.led == led) {
+                paths.insert(k.name, p.clone());
+            }
+        }
+        KeyMap { paths }
+    }
+
+    pub fn len(&self) -> usize {
+        self.paths.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.paths.is_empty()
+    }
+
+    pub fn path(&self, name: &str) -> Option<&PathBuf> {
+        self.paths.get(name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&'static str, &PathBuf)> {
+        self.paths.iter().map(|(name, path)| (*name, path))
+    }
+
+    pub fn paths_for(&self, keys: &[&Key]) -> Vec<PathBuf> {
+        keys.iter().filter_map(|k| self.path(k.name)).cloned().collect()
+    }
+}
+
+/// Where a key sits on the physical keyboard, in key units (1.0 = one letter key).
+#[derive(Clone, Copy, Debug)]
+pub struct KeyRect {
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+impl KeyRect {
+    pub fn contains(&self, x: f64, y: f64) -> bool {
+        x >= self.x && x < self.x + self.width && y >= self.y && y < self.y + self.height
+    }
+}
+
+pub const BOARD_WIDTH: f64 = 19.5;
+pub const BOARD_HEIGHT: f64 = 6.0;
+
+// Physical rows from top to bottom as (key, width) pairs. Empty names are gaps;
+// the keys in TALL_KEYS also cover the gap below them in the next row.
+const FKEY: f64 = BOARD_WIDTH / 20.0;
+#[rustfmt::skip]
+const PHYSICAL_ROWS: &[&[(&str, f64)]] = &[
+    &[
+        ("esc", FKEY), ("f1", FKEY), ("f2", FKEY), ("f3", FKEY), ("f4", FKEY), ("f5", FKEY),
+        ("f6", FKEY), ("f7", FKEY), ("f8", FKEY), ("f9", FKEY), ("f10", FKEY), ("f11", FKEY),
+        ("f12", FKEY), ("sc", FKEY), ("prtsc", FKEY), ("del", FKEY), ("home", FKEY),
+        ("pgup", FKEY), ("pgdn", FKEY), ("end", FKEY),
+    ],
+    &[
+        ("~", 1.0), ("1", 1.0), ("2", 1.0), ("3", 1.0), ("4", 1.0), ("5", 1.0), ("6", 1.0),
+        ("7", 1.0), ("8", 1.0), ("9", 1.0), ("0", 1.0), ("-", 1.0), ("=", 1.0),
+        ("backspace", 2.0), ("", 0.5), ("num", 1.0), ("num-/", 1.0), ("num-*", 1.0),
+        ("num--", 1.0),
+    ],
+    &[
+        ("tab", 1.5), ("q", 1.0), ("w", 1.0), ("e", 1.0), ("r", 1.0), ("t", 1.0), ("y", 1.0),
+        ("u", 1.0), ("i", 1.0), ("o", 1.0), ("p", 1.0), ("[", 1.0), ("]", 1.0),
+        ("return", 1.5), ("", 0.5), ("num-7", 1.0), ("num-8", 1.0), ("num-9", 1.0),
+        ("num-+", 1.0),
+    ],
+    &[
+        ("caps", 1.75), ("a", 1.0), ("s", 1.0), ("d", 1.0), ("f", 1.0), ("g", 1.0), ("h", 1.0),
+        ("j", 1.0), ("k", 1.0), ("l", 1.0), (";", 1.0), ("'", 1.0), ("\\", 0.75), ("", 2.0),
+        ("num-4", 1.0), ("num-5", 1.0), ("num-6", 1.0),
+    ],
+    &[
+        ("l-shift", 1.25), ("<", 1.0), ("z", 1.0), ("x", 1.0), ("c", 1.0), ("v", 1.0),
+        ("b", 1.0), ("n", 1.0), ("m", 1.0), (",", 1.0), (".", 1.0), ("/", 1.0),
+        ("r-shift", 1.0), ("up-arrow", 1.0), ("", 1.25), ("num-1", 1.0), ("num-2", 1.0),
+        ("num-3", 1.0), ("num-return", 1.0),
+    ],
+    &[
+        ("l-ctrl", 1.25), ("fn", 1.0), ("super", 1.0), ("alt", 1.25), ("space", 5.0),
+        ("altgr", 1.25), ("r-ctrl", 1.25), ("", 0.25), ("left-arrow", 1.0),
+        ("down-arrow", 1.0), ("right-arrow", 1.0), ("", 0.25), ("num-0", 2.0), ("num-.", 1.0),
+    ],
+];
+const TALL_KEYS: &[&str] = &["return", "num-+", "num-return"];
+
+/// Every key of the layout with its position on the drawn keyboard.
+pub fn key_rects() -> Vec<(&'static Key, KeyRect)> {
+    let mut out = Vec::new();
+    for (row, keys) in PHYSICAL_ROWS.iter().enumerate() {
+        let mut x = 0.0;
+        for (name, width) in keys.iter() {
+            if let Some(k) = key(name) {
+                let height = if TALL_KEYS.contains(name) { 2.0 } else { 1.0 };
+                out.push((k, KeyRect { x, y: row as f64, width: *width, height }));
+            }
+            x += width;
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(keys: &[&Key]) -> Vec<&'static str> {
+        keys.iter().map(|k| k.name).collect()
+    }
+
+    #[test]
+    fn groups_pick_the_right_keys() {
+        assert_eq!(group("all").unwrap().len(), X6_KEYS.len());
+        assert_eq!(group("letters").unwrap().len(), 26);
+        assert_eq!(group("digits").unwrap().len(), 10);
+        assert_eq!(group("function").unwrap().len(), 12);
+        assert_eq!(names(&group("wasd").unwrap()), ["w", "a", "s", "d"]);
+        assert_eq!(names(&group("function-row").unwrap())[..3], ["esc", "f1", "f2"]);
+        assert_eq!(names(&group("bottom-row").unwrap())[0], "l-ctrl");
+        assert!(group("numpad").unwrap().iter().all(|k| k.name.starts_with("num")));
+        assert!(group("nope").is_none());
+        for name in GROUPS {
+            assert!(group(name).is_some_and(|keys| !keys.is_empty()), "{}", name);
+        }
+    }
+
+    #[test]
+    fn selection_merges_keys_and_groups() {
+        let keys = parse_selection("wasd, arrows,space,w,,").unwrap();
+        assert_eq!(
+            names(&keys),
+            ["w", "a", "s", "d", "left-arrow", "up-arrow", "down-arrow", "right-arrow", "space"]
+        );
+        assert!(parse_selection("").unwrap().is_empty());
+
+        let err = parse_selection("wasd,hyper").unwrap_err();
+        assert!(err.contains("unknown key or group 'hyper'"), "{}", err);
+    }
+
+    #[test]
+    fn key_map_matches_led_names() {
+        assert_eq!(led_name(0), "rgb:kbd_backlight");
+        assert_eq!(led_name(66), "rgb:kbd_backlight_66");
+
+        let root = Path::new("/sys/class/leds");
+        let found = [
+            root.join("rgb:kbd_backlight"),
+            root.join("rgb:kbd_backlight_66"),
+            root.join("rgb:kbd_backlight_1"),
+            root.join("rgb:lightbar"),
+        ];
+        let map = KeyMap::resolve(&found);
+        // LED 1 has no key in the layout and the lightbar is not a key.
+        assert_eq!(map.len(), 2);
+        assert_eq!(map.path("l-ctrl"), Some(&found[0]));
+        assert_eq!(map.path("w"), Some(&found[1]));
+        assert_eq!(map.paths_for(&named(&["w", "q", "l-ctrl"])), [found[1].clone(), found[0].clone()]);
+        assert!(KeyMap::resolve(&[]).is_empty());
+    }
+
+    #[test]
+    fn key_rects_cover_every_key_once_inside_the_board() {
+        let rects = key_rects();
+        assert_eq!(rects.len(), X6_KEYS.len());
+        for k in X6_KEYS {
+            assert_eq!(rects.iter().filter(|(r, _)| r.name == k.name).count(), 1, "{}", k.name);
+        }
+        for (k, rect) in &rects {
+            // The up arrow is drawn in the shift row but wired into the bottom matrix row.
+            let row = if k.name == "up-arrow" { k.row() - 1 } else { k.row() };
+            assert_eq!(rect.y, row as f64, "{}", k.name);
+            assert!(rect.x >= 0.0 && rect.x + rect.width <= BOARD_WIDTH + 1e-9, "{}", k.name);
+            assert!(rect.y + rect.height <= BOARD_HEIGHT, "{}", k.name);
+        }
+    }
+
+    #[test]
+    fn key_rects_follow_the_physical_rows() {
+        let rects = key_rects();
+        let rect = |name: &str| rects.iter().find(|(k, _)| k.name == name).unwrap().1;
+
+        // Keys of a row sit left to right without overlapping.
+        for row in 0..MATRIX_ROWS {
+            let mut in_row: Vec<KeyRect> =
+                rects.iter().map(|(_, r)| *r).filter(|r| r.y == row as f64).collect();
+            in_row.sort_by(|a, b| a.x.total_cmp(&b.x));
+            for pair in in_row.windows(2) {
+                assert!(pair[0].x + pair[0].width <= pair[1].x + 1e-9);
+            }
+        }
+        assert_eq!(rect("esc").x, 0.0);
+        assert_eq!(rect("q").x, 1.5);
+        assert_eq!(rect("space").width, 5.0);
+        assert_eq!(rect("return").height, 2.0);
+        assert!(rect("return").contains(14.0, 3.5));
+        assert!(rect("w").contains(2.5, 2.1));
+        assert!(!rect("w").contains(3.5, 2.1));
+    }
+}
@@ -0,0 +1,460 @@
+use crate::backend::LedBackend;
+use crate::channels::channel_layout;
+use crate::config::{KeyboardConfig, LightbarConfig, Options};
+use crate::layout::{parse_selection, KeyMap};
+use crate::persist::{DEFAULT_KB_MAX_BRIGHTNESS, DEFAULT_LB_MAX_BRIGHTNESS};
+use crate::write_errors::record_write_error;
+use crossbeam::thread::scope;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+pub const KB_BACKLIGHT_PATTERN: &str = "rgb:kbd_backlight*";
+pub const LIGHTBAR_NAME: &str = "rgb:lightbar";
+
+fn should_write_all_keyboard_paths() -> bool {
+    match std::env::var("KBD_WRITE_ALL") {
+        Ok(v) => !v.is_empty() && v != "0",
+        Err(_) => false,
+    }
+}
+
+// The environment variable wins over `options.primary_only` in the config.
+fn should_write_primary_only_keyboard_path(options: &Options) -> bool {
+    match std::env::var("KBD_WRITE_PRIMARY_ONLY") {
+        Ok(v) => !v.is_empty() && v != "0",
+        Err(_) => options.primary_only,
+    }
+}
+
+pub fn keyboard_write_paths(
+    all_paths: &[PathBuf],
+    primary_path: &Path,
+    options: &Options,
+) -> Vec<PathBuf> {
+    // `KBD_KEYS=wasd,arrows,space` (or `options.keys`) limits writes to the listed
+    // keys and key groups.
+    if let Some(spec) = std::env::var("KBD_KEYS").ok().or_else(|| options.keys.clone()) {
+        match parse_selection(&spec) {
+            Ok(keys) => return KeyMap::resolve(all_paths).paths_for(&keys),
+            Err(e) => eprintln!("Ignoring key selection: {}", e),
+        }
+    }
+    // Many devices expose one LED per-key (or per-zone) as separate sysfs entries
+    // (e.g. `rgb:kbd_backlight_1`, `rgb:kbd_backlight_2`, ...). In that case,
+    // writing only the "primary" path often doesn't affect the visible backlight.
+    // Default to writing all paths to match user expectations.
+    if all_paths.len() <= 1
+        || should_write_all_keyboard_paths()
+        || !should_write_primary_only_keyboard_path(options)
+    {
+        return all_paths.to_vec();
+    }
+    vec![primary_path.to_path_buf()]
+}
+
+pub fn pack_rgb(r: u8, g: u8, b: u8) -> u32 {
+    ((r as u32) << 16) | ((g as u32) << 8) | (b as u32)
+}
+
+pub fn unpack_rgb(v: u32) -> (u8, u8, u8) {
+    (((v >> 16) & 0xFF) as u8, ((v >> 8) & 0xFF) as u8, (v & 0xFF) as u8)
+}
+
+/// Accepts `#ff8800`, `ff8800`, `255,136,0` or `hsv(32,100%,100%)`.
+pub fn parse_color(s: &str) -> Result<(u8, u8, u8), String> {
+    let s = s.trim();
+    if let Some(inner) = s.strip_prefix("hsv(").and_then(|r| r.strip_suffix(')')) {
+        return parse_hsv(inner).ok_or_else(|| format!("invalid color '{}'", s));
+    }
+    if s.contains(',') {
+        let parts: Vec<u8> = s
+            .split(',')
+            .map(|p| p.trim().parse::<u8>())
+            .collect::<Result<_, _>>()
+            .map_err(|_| format!("invalid color '{}'", s))?;
+        if let [r, g, b] = parts[..] {
+            return Ok((r, g, b));
+        }
+        return Err(format!("invalid color '{}'", s));
+    }
+    let hex = s.strip_prefix('#').unwrap_or(s);
+    if hex.len() != 6 || !hex.is_ascii() {
+        return Err(format!("invalid color '{}'", s));
+    }
+    let v = u32::from_str_radix(hex, 16).map_err(|_| format!("invalid color '{}'", s))?;
+    Ok(unpack_rgb(v))
+}
+
+pub fn format_hex((r, g, b): (u8, u8, u8)) -> String {
+    format!("#{:02x}{:02x}{:02x}", r, g, b)
+}
+
+// Hue in degrees, saturation and value in percent (the `%` is optional).
+fn parse_hsv(s: &str) -> Option<(u8, u8, u8)> {
+    let parts: Vec<f64> = s
+        .split(',')
+        .map(|p| p.trim().trim_end_matches('%').trim().parse::<f64>().ok())
+        .collect::<Option<_>>()?;
+    let [h, sat, v] = parts[..] else { return None };
+    let percent = 0.0..=100.0;
+    if !(0.0..=360.0).contains(&h) || !percent.contains(&sat) || !percent.contains(&v) {
+        return None;
+    }
+    Some(hsv_to_rgb(h / 360.0, sat / 100.0, v / 100.0))
+}
+
+pub fn format_hsv((r, g, b): (u8, u8, u8)) -> String {
+    let (r, g, b) = (r as f64 / 255.0, g as f64 / 255.0, b as f64 / 255.0);
+    let max = r.max(g).max(b);
+    let delta = max - r.min(g).min(b);
+    let hue = if delta == 0.0 {
+        0.0
+    } else if max == r {
+        60.0 * ((g - b) / delta).rem_euclid(6.0)
+    } else if max == g {
+        60.0 * ((b - r) / delta + 2.0)
+    } else {
+        60.0 * ((r - g) / delta + 4.0)
+    };
+    let saturation = if max == 0.0 { 0.0 } else { delta / max };
+    format!(
+        "hsv({:.0},{:.0}%,{:.0}%)",
+        hue,
+        saturation * 100.0,
+        max * 100.0
+    )
+}
+
+/// `hue` wraps around at 1.0; saturation and value are 0.0..=1.0.
+pub fn hsv_to_rgb(hue: f64, saturation: f64, value: f64) -> (u8, u8, u8) {
+    let h = hue.rem_euclid(1.0) * 6.0;
+    let (s, v) = (saturation.clamp(0.0, 1.0), value.clamp(0.0, 1.0));
+    let f = h - h.floor();
+    let (p, q, t) = (v * (1.0 - s), v * (1.0 - s * f), v * (1.0 - s * (1.0 - f)));
+    let (r, g, b) = match h as u32 {
+        0 => (v, t, p),
+        1 => (q, v, p),
+        2 => (p, v, t),
+        3 => (p, q, v),
+        4 => (t, p, v),
+        _ => (v, p, q),
+    };
+    let to_u8 = |c: f64| (c * 255.0).round() as u8;
+    (to_u8(r), to_u8(g), to_u8(b))
+}
+
+pub fn find_kb_paths(backend: &dyn LedBackend) -> Vec<PathBuf> {
+    let out = backend.find(KB_BACKLIGHT_PATTERN);
+    for e in &out {
+        println!("Found keyboard backlight path: {:?}", e);
+    }
+    println!("Total keyboard backlight paths found: {}", out.len());
+    out
+}
+
+pub fn find_lightbar(backend: &dyn LedBackend) -> Option<PathBuf> {
+    let path = backend.led(LIGHTBAR_NAME);
+    if backend.exists(&path) {
+        Some(path)
+    } else {
+        None
+    }
+}
+
+pub fn pick_primary(paths: &[PathBuf]) -> Option<PathBuf> {
+    if paths.is_empty() {
+        return None;
+    }
+    for p in paths {
+        if p.file_name().and_then(|s| s.to_str()) == Some("rgb:kbd_backlight") {
+            return Some(p.clone());
+        }
+    }
+    Some(paths[0].clone())
+}
+
+static WRITE_FAILURES: AtomicUsize = AtomicUsize::new(0);
+
+/// How many LED writes failed since the start, for commands to fail with them.
+pub fn write_failure_count() -> usize {
+    WRITE_FAILURES.load(Ordering::Relaxed)
+}
+
+pub fn write_color(backend: &dyn LedBackend, path: &Path, r: u8, g: u8, b: u8) {
+    // An unsupported layout was already reported by `channel_layout`.
+    let Ok(layout) = channel_layout(backend, path) else {
+        return;
+    };
+    if let Err(e) = backend.write_attr(path, "multi_intensity", &layout.encode((r, g, b))) {
+        WRITE_FAILURES.fetch_add(1, Ordering::Relaxed);
+        record_write_error(path, "multi_intensity", &e);
+    }
+}
+
+// The brightness this process last wrote to each LED, for the Fn-key watcher to
+// tell its own writes from the hardware's.
+static WRITTEN_BRIGHTNESS: Mutex<BTreeMap<PathBuf, i32>> = Mutex::new(BTreeMap::new());
+
+pub fn written_brightness(path: &Path) -> Option<i32> {
+    WRITTEN_BRIGHTNESS.lock().unwrap().get(path).copied()
+}
+
+pub fn write_brightness(backend: &dyn LedBackend, path: &Path, val: i32) {
+    // Recorded before writing so the watcher can't see the new value first, and
+    // taken back if the write fails.
+    let previous = WRITTEN_BRIGHTNESS.lock().unwrap().insert(path.to_path_buf(), val);
+    if let Err(e) = backend.write_attr(path, "brightness", &format!("{}\n", val)) {
+        let mut written = WRITTEN_BRIGHTNESS.lock().unwrap();
+        match previous {
+            Some(previous) => written.insert(path.to_path_buf(), previous),
+            None => written.remove(path),
+        };
+        drop(written);
+        WRITE_FAILURES.fetch_add(1, Ordering::Relaxed);
+        record_write_error(path, "brightness", &e);
+    }
+}
+
+pub fn write_color_all(backend: &dyn LedBackend, paths: &[PathBuf], r: u8, g: u8, b: u8) {
+    scope(|s| {
+        paths.iter().for_each(|p| {
+            s.spawn(move |_| {
+                write_color(backend, p, r, g, b);
+            });
+        });
+    }).unwrap();
+}
+
+pub fn write_brightness_all(backend: &dyn LedBackend, paths: &[PathBuf], val: i32) {
+    scope(|s| {
+        paths.iter().for_each(|p| {
+            s.spawn(move |_| {
+                write_brightness(backend, p, val);
+            });
+        });
+    }).unwrap();
+}
+
+pub fn read_color(backend: &dyn LedBackend, path: &Path) -> Option<(u8, u8, u8)> {
+    let layout = channel_layout(backend, path).ok()?;
+    let content = backend.read_attr(path, "multi_intensity").ok()?;
+    let color = layout.decode(&content);
+    if color.is_none() {
+        eprintln!(
+            "Error: {}: multi_intensity '{}' doesn't match its {} channel(s)",
+            path.display(),
+            content.trim(),
+            layout.channels.len()
+        );
+    }
+    color
+}
+
+pub fn read_brightness(backend: &dyn LedBackend, path: &Path) -> Option<i32> {
+    if let Ok(content) = backend.read_attr(path, "brightness") {
+        return content.trim().parse().ok();
+    }
+    None
+}
+
+pub fn read_max_brightness(backend: &dyn LedBackend, path: &Path) -> Option<i32> {
+    backend
+        .read_attr(path, "max_brightness")
+        .ok()
+        .and_then(|content| content.trim().parse().ok())
+}
+
+pub fn read_color_parallel(backend: &dyn LedBackend, paths: &[PathBuf]) -> Option<(u8, u8, u8)> {
+    let result = Arc::new(Mutex::new(None));
+    scope(|s| {
+        paths.iter().for_each(|p| {
+            let result = Arc::clone(&result);
+            s.spawn(move |_| {
+                if let Some(color) = read_color(backend, p) {
+                    let mut r = result.lock().unwrap();
+                    if r.is_none() {
+                        *r = Some(color);
+                    }
+                }
+            });
+        });
+    }).unwrap();
+    let inner = Arc::try_unwrap(result).ok().unwrap();
+    inner.into_inner().unwrap()
+}
+
+pub fn read_brightness_parallel(backend: &dyn LedBackend, paths: &[PathBuf]) -> Option<i32> {
+    let result = Arc::new(Mutex::new(None));
+    scope(|s| {
+        paths.iter().for_each(|p| {
+            let result = Arc::clone(&result);
+            s.spawn(move |_| {
+                if let Some(brightness) = read_brightness(backend, p) {
+                    let mut r = result.lock().unwrap();
+                    if r.is_none() {
+                        *r = Some(brightness);
+                    }
+                }
+            });
+        });
+    }).unwrap();
+    let inner = Arc::try_unwrap(result).ok().unwrap();
+    inner.into_inner().unwrap()
+}
+
+/// Current color of every key in `key_map`, read in parallel.
+pub fn read_key_colors(
+    backend: &dyn LedBackend,
+    key_map: &KeyMap,
+) -> BTreeMap<&'static str, (u8, u8, u8)> {
+    let result = Mutex::new(BTreeMap::new());
+    scope(|s| {
+        key_map.iter().for_each(|(name, p)| {
+            let result = &result;
+            s.spawn(move |_| {
+                if let Some(color) = read_color(backend, p) {
+                    result.lock().unwrap().insert(name, color);
+                }
+            });
+        });
+    }).unwrap();
+    result.into_inner().unwrap()
+}
+
+/// Writes a saved keyboard and lightbar state to the hardware: keyboard color,
+/// then per-key overrides, then brightness rescaled to each LED's maximum.
+pub fn apply_lighting(
+    backend: &dyn LedBackend,
+    keyboard: &KeyboardConfig,
+    lightbar: Option<&LightbarConfig>,
+    options: &Options,
+) -> Result<(), String> {
+    let kb_paths = backend.find(KB_BACKLIGHT_PATTERN);
+    let lb_path = find_lightbar(backend);
+    if kb_paths.is_empty() && lb_path.is_none() {
+        return Err("no keyboard backlight or lightbar found".to_string());
+    }
+    if let Some(primary) = pick_primary(&kb_paths) {
+        let targets = keyboard_write_paths(&kb_paths, &primary, options);
+        let (r, g, b) = keyboard.color.tuple();
+        write_color_all(backend, &targets, r, g, b);
+        // Keys painted individually in the window override the keyboard color.
+        let key_map = KeyMap::resolve(&kb_paths);
+        for (name, color) in &keyboard.keys {
+            let (r, g, b) = color.tuple();
+            match key_map.path(name) {
+                Some(p) => write_color(backend, p, r, g, b),
+                None => eprintln!("Skipping color for key '{}', it has no LED", name),
+            }
+        }
+        let max = read_max_brightness(backend, &primary).unwrap_or(DEFAULT_KB_MAX_BRIGHTNESS);
+        write_brightness_all(backend, &targets, keyboard.brightness().scaled_to(max));
+    }
+    if let (Some(path), Some(lightbar)) = (lb_path, lightbar) {
+        let (r, g, b) = lightbar.color.tuple();
+        write_color(backend, &path, r, g, b);
+        let max = read_max_brightness(backend, &path).unwrap_or(DEFAULT_LB_MAX_BRIGHTNESS);
+        write_brightness(backend, &path, lightbar.brightness().scaled_to(max));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::MemoryBackend;
+    use crate::config::Rgb;
+    use crate::layout::{key, led_name};
+
+    fn intensity(backend: &dyn LedBackend, name: &str) -> String {
+        backend.read_attr(&backend.led(name), "multi_intensity").unwrap().trim().to_string()
+    }
+
+    fn brightness(backend: &dyn LedBackend, name: &str) -> String {
+        backend.read_attr(&backend.led(name), "brightness").unwrap().trim().to_string()
+    }
+
+    #[test]
+    fn parses_colors() {
+        assert_eq!(parse_color("#ff8800"), Ok((255, 136, 0)));
+        assert_eq!(parse_color(" FF8800 "), Ok((255, 136, 0)));
+        assert_eq!(parse_color("255, 136,0"), Ok((255, 136, 0)));
+        assert_eq!(parse_color("hsv(120,100%,100%)"), Ok((0, 255, 0)));
+        assert_eq!(parse_color("hsv(0, 0, 50)"), Ok((128, 128, 128)));
+        assert_eq!(parse_color("hsv(360,100%,100%)"), Ok((255, 0, 0)));
+        for bad in ["", "#ff88", "#gg8800", "256,0,0", "1,2", "hsv(400,100%,100%)", "hsv(10,100%)", "é12345"] {
+            assert!(parse_color(bad).is_err(), "{:?} parsed", bad);
+        }
+    }
+
+    #[test]
+    fn formats_colors_that_parse_back() {
+        for color in [(255, 136, 0), (0, 0, 0), (18, 52, 86)] {
+            assert_eq!(parse_color(&format_hex(color)), Ok(color));
+        }
+        assert_eq!(format_hsv((0, 255, 0)), "hsv(120,100%,100%)");
+        assert_eq!(format_hsv((0, 0, 0)), "hsv(0,0%,0%)");
+        assert_eq!(parse_color(&format_hsv((255, 0, 255))), Ok((255, 0, 255)));
+    }
+
+    #[test]
+    fn remembers_only_brightness_that_was_written() {
+        let backend = MemoryBackend::new();
+        backend.add_rgb_led("rgb:kbd_backlight_written", 50);
+        backend.add_led("rgb:kbd_backlight_read_only", &[("max_brightness", "50\n")]);
+        let written = backend.led("rgb:kbd_backlight_written");
+        let read_only = backend.led("rgb:kbd_backlight_read_only");
+        write_brightness(&backend, &written, 20);
+        write_brightness(&backend, &read_only, 20);
+        assert_eq!(written_brightness(&written), Some(20));
+        assert_eq!(written_brightness(&read_only), None);
+    }
+
+    #[test]
+    fn applies_lighting_rescaled_to_each_led() {
+        let backend = MemoryBackend::new();
+        backend.add_rgb_led("rgb:kbd_backlight", 50);
+        backend.add_rgb_led(LIGHTBAR_NAME, 100);
+        let keyboard = KeyboardConfig { color: Rgb(255, 0, 128), brightness: 10, max_brightness: 20, ..Default::default() };
+        let lightbar = LightbarConfig { color: Rgb(0, 255, 0), brightness: 3, max_brightness: 4 };
+        apply_lighting(&backend, &keyboard, Some(&lightbar), &Options::default()).unwrap();
+        assert_eq!(intensity(&backend, "rgb:kbd_backlight"), "255 0 128");
+        assert_eq!(brightness(&backend, "rgb:kbd_backlight"), "25");
+        assert_eq!(intensity(&backend, LIGHTBAR_NAME), "0 255 0");
+        assert_eq!(brightness(&backend, LIGHTBAR_NAME), "75");
+    }
+
+    #[test]
+    fn applies_per_key_colors_over_the_keyboard_color() {
+        let backend = MemoryBackend::simulated_x6();
+        let mut keyboard = KeyboardConfig { color: Rgb(0, 0, 255), ..Default::default() };
+        keyboard.keys.insert("esc".to_string(), Rgb(255, 0, 0));
+        apply_lighting(&backend, &keyboard, None, &Options::default()).unwrap();
+        let esc = led_name(key("esc").unwrap().led);
+        let f1 = led_name(key("f1").unwrap().led);
+        assert_eq!(intensity(&backend, &esc), "255 0 0");
+        assert_eq!(intensity(&backend, &f1), "0 0 255");
+        assert_eq!(brightness(&backend, &f1), "50");
+        // No lightbar config, so the lightbar is left alone.
+        assert_eq!(brightness(&backend, LIGHTBAR_NAME), "0");
+    }
+
+    #[test]
+    fn applies_the_keyboard_color_to_selected_keys_only() {
+        let backend = MemoryBackend::simulated_x6();
+        let keyboard = KeyboardConfig { color: Rgb(0, 255, 0), ..Default::default() };
+        let options = Options { keys: Some("wasd".to_string()), ..Default::default() };
+        apply_lighting(&backend, &keyboard, None, &options).unwrap();
+        assert_eq!(intensity(&backend, &led_name(key("w").unwrap().led)), "0 255 0");
+        assert_eq!(intensity(&backend, &led_name(key("q").unwrap().led)), "255 255 255");
+    }
+
+    #[test]
+    fn applying_without_leds_fails() {
+        let backend = MemoryBackend::new();
+        let result = apply_lighting(&backend, &KeyboardConfig::default(), None, &Options::default());
+        assert!(result.is_err());
+    }
+}
@@ -1,61 +1,60 @@
 use gtk4::prelude::*;
 use gtk4::{
-    Application, ApplicationWindow, Box, Button, DropDown, Label, Orientation, Scale, StringList,
+    Application, ApplicationWindow, Box, Button, Label, Orientation, Scale,
 };
 use gdk4::RGBA;
 use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
 use std::env;
-use std::fs;
-use std::path::PathBuf;
-use glob::glob;
 use std::rc::Rc;
-use std::sync::{mpsc, Arc, Mutex};
+use std::sync::Arc;
 use std::sync::atomic::{AtomicI32, AtomicU32, Ordering};
-use std::thread;
-use crossbeam::thread::scope;
 
-const KB_BACKLIGHT_PATTERN: &str = "/sys/class/leds/rgb:kbd_backlight*";
-const LIGHTBAR_PATH: &str = "/sys/class/leds/rgb:lightbar";
-
-fn should_write_all_keyboard_paths() -> bool {
-    match std::env::var("KBD_WRITE_ALL") {
-        Ok(v) => !v.is_empty() && v != "0",
-        Err(_) => false,
-    }
-}
-
-fn should_write_primary_only_keyboard_path() -> bool {
-    match std::env::var("KBD_WRITE_PRIMARY_ONLY") {
-        Ok(v) => !v.is_empty() && v != "0",
-        Err(_) => false,
-    }
-}
-
-fn keyboard_write_paths(all_paths: &[PathBuf], primary_path: &PathBuf) -> Vec<PathBuf> {
-    // Many devices expose one LED per-key (or per-zone) as separate sysfs entries
-    // (e.g. `rgb:kbd_backlight_1`, `rgb:kbd_backlight_2`, ...). In that case,
-    // writing only the "primary" path often doesn't affect the visible backlight.
-    // Default to writing all paths to match user expectations.
-    if all_paths.len() <= 1
-        || should_write_all_keyboard_paths()
-        || !should_write_primary_only_keyboard_path()
-    {
-        return all_paths.to_vec();
-    }
-    vec![primary_path.clone()]
-}
-
-fn pack_rgb(r: u8, g: u8, b: u8) -> u32 {
-    ((r as u32) << 16) | ((g as u32) << 8) | (b as u32)
-}
-
-fn unpack_rgb(v: u32) -> (u8, u8, u8) {
-    (((v >> 16) & 0xFF) as u8, ((v >> 8) & 0xFF) as u8, (v & 0xFF) as u8)
-}
-
-fn notify_coalescer(tx: &mpsc::SyncSender<()>) {
-    let _ = tx.try_send(());
-}
+mod ambient;
+mod backend;
+mod channels;
+mod cli;
+mod color_picker;
+mod config;
+mod dbus;
+mod doctor;
+mod effect_view;
+mod effects;
+mod error_view;
+mod hotplug;
+mod hotplug_view;
+mod hw_brightness;
+mod idle;
+mod input;
+mod keyboard_view;
+mod layout;
+mod leds;
+mod palette;
+mod palette_view;
+mod persist;
+mod power;
+mod profile_view;
+mod schedule;
+#[cfg(test)]
+mod temp_dir;
+mod workers;
+mod write_errors;
+
+use color_picker::ColorPicker;
+use config::load_config_or_default;
+use leds::{
+    find_kb_paths, find_lightbar, keyboard_write_paths, pack_rgb, pick_primary,
+    read_brightness, read_brightness_parallel, read_color, read_color_parallel,
+    read_max_brightness,
+};
+use persist::{
+    persist_color_state, spawn_persistence_worker, Brightness, DEFAULT_KB_MAX_BRIGHTNESS,
+    DEFAULT_LB_MAX_BRIGHTNESS,
+};
+use workers::{
+    notify_coalescer, shared_targets, spawn_kb_brightness_worker, spawn_kb_color_worker,
+    spawn_lb_brightness_worker, spawn_lb_color_worker,
+};
 
 fn rgba_to_rgb8(rgba: &RGBA) -> (u8, u8, u8) {
     let r = (rgba.red().clamp(0.0, 1.0) * 255.0).round() as u8;
@@ -64,276 +63,29 @@ fn rgba_to_rgb8(rgba: &RGBA) -> (u8, u8, u8) {
     (r, g, b)
 }
 
-struct PersistState {
-    kb_color: u32,
-    kb_brightness: i32,
-    lb_color: Option<u32>,
-    lb_brightness: Option<i32>,
-}
-
-fn spawn_persistence_worker() -> (Arc<Mutex<Option<PersistState>>>, mpsc::SyncSender<()>) {
-    let state = Arc::new(Mutex::new(None::<PersistState>));
-    let state_for_thread = Arc::clone(&state);
-    let (tx, rx) = mpsc::sync_channel::<()>(1);
-    thread::spawn(move || {
-        while rx.recv().is_ok() {
-            let pending = {
-                let mut s = state_for_thread.lock().unwrap();
-                s.take()
-            };
-            if let Some(ps) = pending {
-                let Some(home) = env::var("HOME").ok() else { continue; };
-                let path = PathBuf::from(home).join(".rusty-kb").join("colors.txt");
-                if let Some(parent) = path.parent() {
-                    let _ = fs::create_dir_all(parent);
-                }
-                let (kr, kg, kbv) = unpack_rgb(ps.kb_color);
-                let lb_line = if let Some(lb) = ps.lb_color {
-                    let (r, g, b) = unpack_rgb(lb);
-                    format!("{} {} {} {}", r, g, b, ps.lb_brightness.unwrap_or(0))
-                } else {
-                    format!("0 0 0 {}", ps.lb_brightness.unwrap_or(0))
-                };
-                let content = format!("{} {} {} {}\n{}\n", kr, kg, kbv, ps.kb_brightness, lb_line);
-                let _ = fs::write(&path, content);
-            }
-        }
-    });
-    (state, tx)
-}
-
-fn persist_color_state(
-    state: &Arc<Mutex<Option<PersistState>>>,
-    tx: &mpsc::SyncSender<()>,
-    kb_color: u32,
-    kb_brightness: i32,
-    lb_color: Option<u32>,
-    lb_brightness: Option<i32>,
-) {
-    let mut s = state.lock().unwrap();
-    *s = Some(PersistState {
-        kb_color,
-        kb_brightness,
-        lb_color,
-        lb_brightness,
-    });
-    let _ = tx.try_send(());
-}
-
-fn dropdown_for_colors(
-    presets: &[(&'static str, RGBA)],
-    initial_rgb: (u8, u8, u8),
-    on_select: impl Fn(RGBA) + 'static,
-) -> DropDown {
-    let names: Vec<&str> = presets.iter().map(|(name, _)| *name).collect();
-    let list = StringList::new(&names);
-    let dropdown = DropDown::builder().model(&list).build();
-
-    let initial_index = presets
-        .iter()
-        .position(|(_, rgba)| rgba_to_rgb8(rgba) == initial_rgb)
-        .unwrap_or(0);
-    dropdown.set_selected(initial_index as u32);
-
-    let presets_for_cb: Vec<RGBA> = presets.iter().map(|(_, rgba)| *rgba).collect();
-    dropdown.connect_selected_notify(move |dd| {
-        let idx = dd.selected() as usize;
-        if let Some(rgba) = presets_for_cb.get(idx) {
-            on_select(*rgba);
-        }
-    });
-
-    dropdown
-}
-
-fn find_kb_paths() -> Vec<PathBuf> {
-    let mut out = Vec::new();
-    if let Ok(entries) = glob(KB_BACKLIGHT_PATTERN) {
-        for e in entries.flatten() {
-            println!("Found keyboard backlight path: {:?}", e);
-            out.push(e);
-        }
-    }
-    println!("Total keyboard backlight paths found: {}", out.len());
-    out
-}
-
-fn pick_primary(paths: &[PathBuf]) -> Option<PathBuf> {
-    if paths.is_empty() {
-        return None;
-    }
-    for p in paths {
-        if let Some(name) = p.file_name().and_then(|s| s.to_str()) {
-            if name == "rgb:kbd_backlight" {
-                return Some(p.clone());
-            }
-        }
-    }
-    Some(paths[0].clone())
-}
-
-fn write_color(path: &PathBuf, r: u8, g: u8, b: u8) {
-    let color_path = path.join("multi_intensity");
-    let content = format!("{} {} {}\n", r, g, b);
-    if let Err(e) = fs::write(&color_path, content) {
-        eprintln!("Error: {}", e);
-    }
-}
-
-fn write_brightness(path: &PathBuf, val: i32) {
-    let brightness_path = path.join("brightness");
-    if let Err(e) = fs::write(&brightness_path, format!("{}\n", val)) {
-        eprintln!("Error: {}", e);
-    }
-}
-
-fn write_color_all(paths: &[PathBuf], r: u8, g: u8, b: u8) {
-    scope(|s| {
-        paths.iter().for_each(|p| {
-            let p = p.clone();
-            s.spawn(move |_| {
-                write_color(&p, r, g, b);
-            });
-        });
-    }).unwrap();
-}
-
-fn write_brightness_all(paths: &[PathBuf], val: i32) {
-    scope(|s| {
-        paths.iter().for_each(|p| {
-            let p = p.clone();
-            s.spawn(move |_| {
-                write_brightness(&p, val);
-            });
-        });
-    }).unwrap();
+/// A 0-100% slider; callers convert to the LED's own `max_brightness`.
+fn brightness_scale() -> Scale {
+    let scale = Scale::with_range(Orientation::Horizontal, 0.0, 100.0, 1.0);
+    scale.set_draw_value(true);
+    scale.set_digits(0);
+    // Make scroll/keyboard adjustments feel snappier while keeping drag smooth.
+    scale.set_increments(5.0, 10.0);
+    scale
 }
 
-fn read_color(path: &PathBuf) -> Option<(u8, u8, u8)> {
-    let color_path = path.join("multi_intensity");
-    if let Ok(content) = fs::read_to_string(color_path) {
-        let parts: Vec<u8> = content
-            .split_whitespace()
-            .filter_map(|s| s.parse().ok())
-            .collect();
-        if parts.len() == 3 {
-            return Some((parts[0], parts[1], parts[2]));
-        }
-    }
-    None
-}
+fn main() {
+    let backend = backend::from_env();
 
-fn read_brightness(path: &PathBuf) -> Option<i32> {
-    let brightness_path = path.join("brightness");
-    if let Ok(content) = fs::read_to_string(brightness_path) {
-        return content.trim().parse().ok();
+    let args: Vec<String> = env::args().skip(1).collect();
+    if let Some(code) = cli::run(&backend, &args) {
+        std::process::exit(code);
     }
-    None
-}
 
-fn read_color_parallel(paths: &[PathBuf]) -> Option<(u8, u8, u8)> {
-    use std::sync::{Arc, Mutex};
-    let result = Arc::new(Mutex::new(None));
-    scope(|s| {
-        paths.iter().for_each(|p| {
-            let p = p.clone();
-            let result = Arc::clone(&result);
-            s.spawn(move |_| {
-                if let Some(color) = read_color(&p) {
-                    let mut r = result.lock().unwrap();
-                    if r.is_none() {
-                        *r = Some(color);
-                    }
-                }
-            });
-        });
-    }).unwrap();
-    let inner = Arc::try_unwrap(result).ok().unwrap();
-    inner.into_inner().unwrap()
-}
-
-fn read_brightness_parallel(paths: &[PathBuf]) -> Option<i32> {
-    use std::sync::{Arc, Mutex};
-    let result = Arc::new(Mutex::new(None));
-    scope(|s| {
-        paths.iter().for_each(|p| {
-            let p = p.clone();
-            let result = Arc::clone(&result);
-            s.spawn(move |_| {
-                if let Some(brightness) = read_brightness(&p) {
-                    let mut r = result.lock().unwrap();
-                    if r.is_none() {
-                        *r = Some(brightness);
-                    }
-                }
-            });
-        });
-    }).unwrap();
-    let inner = Arc::try_unwrap(result).ok().unwrap();
-    inner.into_inner().unwrap()
-}
-
-// Coalescing worker: only apply the most recent value received (no sender-side backlog)
-fn spawn_kb_color_worker(paths: Vec<PathBuf>) -> (Arc<AtomicU32>, mpsc::SyncSender<()>) {
-    let latest = Arc::new(AtomicU32::new(0));
-    let latest_for_thread = Arc::clone(&latest);
-    let (tx, rx) = mpsc::sync_channel::<()>(1);
-    thread::spawn(move || {
-        while rx.recv().is_ok() {
-            let v = latest_for_thread.load(Ordering::Relaxed);
-            let (r, g, b) = unpack_rgb(v);
-            write_color_all(&paths, r, g, b);
-        }
-    });
-    (latest, tx)
-}
-
-fn spawn_kb_brightness_worker(paths: Vec<PathBuf>) -> (Arc<AtomicI32>, mpsc::SyncSender<()>) {
-    let latest = Arc::new(AtomicI32::new(0));
-    let latest_for_thread = Arc::clone(&latest);
-    let (tx, rx) = mpsc::sync_channel::<()>(1);
-    thread::spawn(move || {
-        while rx.recv().is_ok() {
-            let v = latest_for_thread.load(Ordering::Relaxed);
-            write_brightness_all(&paths, v);
-        }
-    });
-    (latest, tx)
-}
-
-fn spawn_lb_color_worker(path: PathBuf) -> (Arc<AtomicU32>, mpsc::SyncSender<()>) {
-    let latest = Arc::new(AtomicU32::new(0));
-    let latest_for_thread = Arc::clone(&latest);
-    let (tx, rx) = mpsc::sync_channel::<()>(1);
-    thread::spawn(move || {
-        while rx.recv().is_ok() {
-            let v = latest_for_thread.load(Ordering::Relaxed);
-            let (r, g, b) = unpack_rgb(v);
-            write_color(&path, r, g, b);
-        }
-    });
-    (latest, tx)
-}
-
-fn spawn_lb_brightness_worker(path: PathBuf) -> (Arc<AtomicI32>, mpsc::SyncSender<()>) {
-    let latest = Arc::new(AtomicI32::new(0));
-    let latest_for_thread = Arc::clone(&latest);
-    let (tx, rx) = mpsc::sync_channel::<()>(1);
-    thread::spawn(move || {
-        while rx.recv().is_ok() {
-            let v = latest_for_thread.load(Ordering::Relaxed);
-            write_brightness(&path, v);
-        }
-    });
-    (latest, tx)
-}
-
-fn main() {
     let app = Application::builder()
         .application_id("com.example.keyboard_controller")
         .build();
 
-    app.connect_activate(|app| {
+    app.connect_activate(move |app| {
         let window = ApplicationWindow::builder()
             .application(app)
             .title("Keyboard & Lightbar Controller")
@@ -346,6 +98,7 @@ fn main() {
         container.set_margin_bottom(24);
         container.set_margin_start(24);
         container.set_margin_end(24);
+        container.append(&error_view::write_error_banner());
 
         let shared_lb_color = Arc::new(AtomicU32::new(0));
         let shared_lb_brightness = Arc::new(AtomicI32::new(0));
@@ -353,13 +106,110 @@ fn main() {
         let kb_color_shared: Rc<RefCell<Option<Arc<AtomicU32>>>> = Rc::new(RefCell::new(None));
         let kb_brightness_shared: Rc<RefCell<Option<Arc<AtomicI32>>>> = Rc::new(RefCell::new(None));
 
-        // Background persistence worker to avoid blocking UI
-        let (persist_state, tx_persist) = spawn_persistence_worker();
+        let (config, config_error) = load_config_or_default();
+        if let Some(e) = &config_error {
+            eprintln!("Error: {}", e);
+            let warning = Label::new(Some(&format!(
+                "Your config could not be read, using defaults.\n{}",
+                e
+            )));
+            warning.set_wrap(true);
+            warning.add_css_class("error");
+            container.append(&warning);
+        }
+        // The sliders start from what the LEDs show, so bring them out of idle dimming.
+        idle::wake(backend.as_ref(), &config);
+        let presets = config.presets.clone();
+        let options = config.options.clone();
+
+        // Background persistence worker to avoid blocking UI. Saves of the daemon
+        // and the command line come back through `rx_outside` to update the controls.
+        let (tx_outside, rx_outside) = std::sync::mpsc::channel();
+        let (persist_state, tx_persist) = spawn_persistence_worker(config, move |config| {
+            let _ = tx_outside.send(config.clone());
+        });
+
+        // Controls a profile switch has to update. While it does, their callbacks
+        // stay quiet: the profile is written to the hardware in one go.
+        let switching_profile = Rc::new(Cell::new(false));
+        let kb_picker_shared: Rc<RefCell<Option<ColorPicker>>> = Rc::new(RefCell::new(None));
+        let kb_scale_shared: Rc<RefCell<Option<Scale>>> = Rc::new(RefCell::new(None));
+        let lb_picker_shared: Rc<RefCell<Option<ColorPicker>>> = Rc::new(RefCell::new(None));
+        let lb_scale_shared: Rc<RefCell<Option<Scale>>> = Rc::new(RefCell::new(None));
 
         // Keyboard Section
-        let kb_paths = find_kb_paths();
+        let kb_paths = find_kb_paths(backend.as_ref());
+        // Filled below if there is a keyboard; follows hotplug either way.
+        let kb_targets = shared_targets(Vec::new());
+        // The lightbar can also show up after the window opened.
+        let lb_path = find_lightbar(backend.as_ref());
+        let lb_targets = shared_targets(lb_path.iter().cloned().collect());
+        // Persisted brightness is stored together with the scale it was set on.
+        let kb_max = pick_primary(&kb_paths)
+            .and_then(|p| read_max_brightness(backend.as_ref(), &p))
+            .unwrap_or(DEFAULT_KB_MAX_BRIGHTNESS);
+        let lb_max = Rc::new(Cell::new(DEFAULT_LB_MAX_BRIGHTNESS));
+        let on_switch = {
+            let switching = Rc::clone(&switching_profile);
+            let kb_picker = Rc::clone(&kb_picker_shared);
+            let kb_scale = Rc::clone(&kb_scale_shared);
+            let lb_picker = Rc::clone(&lb_picker_shared);
+            let lb_scale = Rc::clone(&lb_scale_shared);
+            let kb_color = kb_color_shared.clone();
+            let kb_brightness = kb_brightness_shared.clone();
+            let lb_available = lb_available.clone();
+            let lb_color = Arc::clone(&shared_lb_color);
+            let lb_brightness = Arc::clone(&shared_lb_brightness);
+            let lb_max = Rc::clone(&lb_max);
+            move |config: &config::Config| {
+                let lb_max = lb_max.get();
+                switching.set(true);
+                let kb_value = config.keyboard.brightness().scaled_to(kb_max);
+                if let Some(color) = kb_color.borrow().as_ref() {
+                    color.store(config.keyboard.color.packed(), Ordering::Relaxed);
+                }
+                if let Some(brightness) = kb_brightness.borrow().as_ref() {
+                    brightness.store(kb_value, Ordering::Relaxed);
+                }
+                if let Some(picker) = kb_picker.borrow().as_ref() {
+                    picker.set_color(config.keyboard.color.tuple());
+                }
+                if let Some(scale) = kb_scale.borrow().as_ref() {
+                    scale.set_value(Brightness::new(kb_value, kb_max).percent());
+                }
+                if let (true, Some(lb)) = (lb_available.get(), &config.lightbar) {
+                    let lb_value = lb.brightness().scaled_to(lb_max);
+                    lb_color.store(lb.color.packed(), Ordering::Relaxed);
+                    lb_brightness.store(lb_value, Ordering::Relaxed);
+                    if let Some(picker) = lb_picker.borrow().as_ref() {
+                        picker.set_color(lb.color.tuple());
+                    }
+                    if let Some(scale) = lb_scale.borrow().as_ref() {
+                        scale.set_value(Brightness::new(lb_value, lb_max).percent());
+                    }
+                }
+                switching.set(false);
+            }
+        };
+        let on_switch = Rc::new(on_switch);
+        let on_switch_for_bar = Rc::clone(&on_switch);
+        container.append(&profile_view::profile_bar(
+            Arc::clone(&backend),
+            Arc::clone(&persist_state),
+            tx_persist.clone(),
+            move |config| on_switch_for_bar(config),
+        ));
+        glib::timeout_add_local(persist::CONFIG_POLL, move || {
+            if let Some(config) = rx_outside.try_iter().last() {
+                on_switch(&config);
+            }
+            glib::ControlFlow::Continue
+        });
+
         if let Some(primary_path) = pick_primary(&kb_paths) {
-            let kb_write_paths = keyboard_write_paths(&kb_paths, &primary_path);
+            let key_map = Arc::new(layout::KeyMap::resolve(&kb_paths));
+            println!("Matched {} keyboard LED(s) to keys of the X6 layout.", key_map.len());
+            let kb_write_paths = keyboard_write_paths(&kb_paths, &primary_path, &options);
             println!(
                 "Keyboard writes will target {} path(s) (set KBD_WRITE_PRIMARY_ONLY=1 to write only {:?}).",
                 kb_write_paths.len(),
@@ -378,10 +228,11 @@ fn main() {
             color_box.append(&Label::new(Some("Color:")));
 
             // Initialize color from current hardware state (parallel read for speed)
-            let initial_kb_color = read_color_parallel(&kb_paths).unwrap_or((255, 255, 255));
+            let initial_kb_color = read_color_parallel(backend.as_ref(), &kb_paths).unwrap_or((255, 255, 255));
 
             // Updates via coalescing worker (applied on SetColor)
-            let (latest_kb_color, tx_kb_color) = spawn_kb_color_worker(kb_write_paths.clone());
+            *kb_targets.lock().unwrap() = kb_write_paths.clone();
+            let (latest_kb_color, tx_kb_color) = spawn_kb_color_worker(Arc::clone(&backend), Arc::clone(&kb_targets));
             // Sync initial hardware color across all per-key LEDs so the UI state matches
             // what will happen when you start changing colors.
             latest_kb_color.store(
@@ -391,26 +242,24 @@ fn main() {
             notify_coalescer(&tx_kb_color);
             *kb_color_shared.borrow_mut() = Some(Arc::clone(&latest_kb_color));
 
-            let preset_colors = [
-                ("Red", RGBA::new(1.0, 0.0, 0.0, 1.0)),
-                ("Blue", RGBA::new(0.0, 0.0, 1.0, 1.0)),
-                ("Green", RGBA::new(0.0, 1.0, 0.0, 1.0)),
-                ("Pink", RGBA::new(1.0, 0.41, 0.71, 1.0)),
-                ("Orange", RGBA::new(1.0, 0.5, 0.0, 1.0)),
-                ("Light Blue", RGBA::new(0.0, 0.6, 1.0, 1.0)),
-            ];
             let latest_kb_color_for_dropdown = Arc::clone(&latest_kb_color);
             let tx_kb_color_for_dropdown = tx_kb_color.clone();
             let lb_color_for_dropdown = Arc::clone(&shared_lb_color);
             let lb_available_for_dropdown = lb_available.clone();
             let kb_brightness_for_dropdown = kb_brightness_shared.clone();
             let lb_brightness_for_dropdown = Arc::clone(&shared_lb_brightness);
+            let lb_max_for_dropdown = Rc::clone(&lb_max);
             let persist_state_for_dropdown = Arc::clone(&persist_state);
             let tx_persist_for_dropdown = tx_persist.clone();
-            let dropdown = dropdown_for_colors(&preset_colors, initial_kb_color, move |rgba| {
-                let (r, g, b) = rgba_to_rgb8(&rgba);
+            let switching_for_dropdown = Rc::clone(&switching_profile);
+            let picker = ColorPicker::new(&presets, initial_kb_color, move |(r, g, b)| {
+                if switching_for_dropdown.get() {
+                    return;
+                }
                 latest_kb_color_for_dropdown.store(pack_rgb(r, g, b), Ordering::Relaxed);
                 notify_coalescer(&tx_kb_color_for_dropdown);
+                // The whole keyboard was repainted, so the saved per-key colors are gone.
+                persist_state_for_dropdown.lock().unwrap().keyboard.keys = BTreeMap::new();
                 let lb_state = if lb_available_for_dropdown.get() {
                     Some(lb_color_for_dropdown.load(Ordering::Relaxed))
                 } else {
@@ -422,7 +271,7 @@ fn main() {
                     .map(|b: &Arc<AtomicI32>| b.load(Ordering::Relaxed))
                     .unwrap_or(0);
                 let lb_brightness_val = if lb_available_for_dropdown.get() {
-                    Some(lb_brightness_for_dropdown.load(Ordering::Relaxed))
+                    Some(Brightness::new(lb_brightness_for_dropdown.load(Ordering::Relaxed), lb_max_for_dropdown.get()))
                 } else {
                     None
                 };
@@ -430,28 +279,25 @@ fn main() {
                     &persist_state_for_dropdown,
                     &tx_persist_for_dropdown,
                     latest_kb_color_for_dropdown.load(Ordering::Relaxed),
-                    kb_brightness_val,
+                    Brightness::new(kb_brightness_val, kb_max),
                     lb_state,
                     lb_brightness_val,
                 );
             });
 
-            color_box.append(&dropdown);
+            color_box.append(&picker.widget);
             section.append(&color_box);
+            *kb_picker_shared.borrow_mut() = Some(picker);
 
             let bright_box = Box::new(Orientation::Vertical, 4);
-            bright_box.append(&Label::builder().label("Brightness (0-50)").halign(gtk4::Align::Start).build());
-            let kb_bright_scale = Scale::with_range(Orientation::Horizontal, 0.0, 50.0, 1.0);
+            bright_box.append(&Label::builder().label("Brightness (%)").halign(gtk4::Align::Start).build());
+            let kb_bright_scale = brightness_scale();
             // Initialize brightness from current hardware state (parallel read for speed)
-            let initial_kb_brightness = read_brightness_parallel(&kb_paths);
+            let initial_kb_brightness = read_brightness_parallel(backend.as_ref(), &kb_paths);
             if let Some(val) = initial_kb_brightness {
-                kb_bright_scale.set_value(val as f64);
+                kb_bright_scale.set_value(Brightness::new(val, kb_max).percent());
             }
-            kb_bright_scale.set_draw_value(true);
-            kb_bright_scale.set_digits(0);
-            // Make scroll/keyboard adjustments feel snappier while keeping drag smooth.
-            kb_bright_scale.set_increments(5.0, 10.0);
-            let (latest_kb_bright, tx_kb_bright) = spawn_kb_brightness_worker(kb_write_paths.clone());
+            let (latest_kb_bright, tx_kb_bright) = spawn_kb_brightness_worker(Arc::clone(&backend), Arc::clone(&kb_targets));
             *kb_brightness_shared.borrow_mut() = Some(Arc::clone(&latest_kb_bright));
             let latest_kb_bright_for_cb = Arc::clone(&latest_kb_bright);
             let tx_kb_bright_for_cb = tx_kb_bright.clone();
@@ -459,10 +305,15 @@ fn main() {
             let lb_available_for_brightness = lb_available.clone();
             let lb_color_for_brightness = Arc::clone(&shared_lb_color);
             let lb_brightness_for_brightness = Arc::clone(&shared_lb_brightness);
+            let lb_max_for_brightness = Rc::clone(&lb_max);
             let persist_state_for_brightness = Arc::clone(&persist_state);
             let tx_persist_for_brightness = tx_persist.clone();
+            let switching_for_brightness = Rc::clone(&switching_profile);
             kb_bright_scale.connect_value_changed(move |scale| {
-                let val = scale.value() as i32;
+                if switching_for_brightness.get() {
+                    return;
+                }
+                let val = Brightness::from_percent(scale.value(), kb_max).value;
                 latest_kb_bright_for_cb.store(val, Ordering::Relaxed);
                 notify_coalescer(&tx_kb_bright_for_cb);
                 let kb_color_val = kb_color_for_brightness
@@ -476,11 +327,11 @@ fn main() {
                     None
                 };
                 let lb_brightness_val = if lb_available_for_brightness.get() {
-                    Some(lb_brightness_for_brightness.load(Ordering::Relaxed))
+                    Some(Brightness::new(lb_brightness_for_brightness.load(Ordering::Relaxed), lb_max_for_brightness.get()))
                 } else {
                     None
                 };
-                persist_color_state(&persist_state_for_brightness, &tx_persist_for_brightness, kb_color_val, val, lb_color_val, lb_brightness_val);
+                persist_color_state(&persist_state_for_brightness, &tx_persist_for_brightness, kb_color_val, Brightness::new(val, kb_max), lb_color_val, lb_brightness_val);
             });
             // Sync initial brightness across all per-key LEDs. Many devices expose
             // per-key LEDs with independent brightness values; if most are 0,
@@ -491,6 +342,46 @@ fn main() {
             }
             bright_box.append(&kb_bright_scale);
             section.append(&bright_box);
+            *kb_scale_shared.borrow_mut() = Some(kb_bright_scale);
+
+            let engine = effects::spawn_effect_engine(
+                Arc::clone(&backend),
+                Arc::clone(&kb_targets),
+                Arc::clone(&key_map),
+                Arc::clone(&lb_targets),
+            );
+            let backend_for_effects = Arc::clone(&backend);
+            let persist_state_for_effects = Arc::clone(&persist_state);
+            section.append(&effect_view::effect_controls(
+                engine,
+                Arc::clone(&latest_kb_color),
+                move || {
+                    // Put the saved look back once the effect is stopped.
+                    let snapshot = persist_state_for_effects.lock().unwrap().clone();
+                    let backend = Arc::clone(&backend_for_effects);
+                    std::thread::spawn(move || {
+                        let lightbar = snapshot.lightbar.as_ref();
+                        if let Err(e) = leds::apply_lighting(
+                            backend.as_ref(),
+                            &snapshot.keyboard,
+                            lightbar,
+                            &snapshot.options,
+                        ) {
+                            eprintln!("Error: {}", e);
+                        }
+                    });
+                },
+            ));
+
+            // Per-key painting only makes sense when the LEDs map onto the X6 layout.
+            if !key_map.is_empty() {
+                section.append(&keyboard_view::keyboard_painter(
+                    Arc::clone(&backend),
+                    key_map,
+                    Arc::clone(&persist_state),
+                    tx_persist.clone(),
+                ));
+            }
             
             container.append(&section);
         } else {
@@ -500,132 +391,216 @@ fn main() {
         // Separator
         container.append(&gtk4::Separator::new(Orientation::Horizontal));
 
-        // Lightbar Section
-        let lb_path = PathBuf::from(LIGHTBAR_PATH);
-        if lb_path.exists() {
-            lb_available.set(true);
-            let section = Box::new(Orientation::Vertical, 8);
+        // Lightbar Section. Built either way and shown while there is a lightbar.
+        let section = Box::new(Orientation::Vertical, 8);
 
-            let lb_label = Label::builder()
-                .label("Lightbar")
-                .halign(gtk4::Align::Start)
-                .build();
-            lb_label.add_css_class("title-4");
-            section.append(&lb_label);
-
-            let color_box = Box::new(Orientation::Horizontal, 10);
-            color_box.append(&Label::new(Some("Color:")));
-            // Initialize color from current hardware state
-            let initial_lb_color = read_color(&lb_path).unwrap_or((255, 255, 255));
-            let (latest_lb_color, tx_lb_color) = spawn_lb_color_worker(lb_path.clone());
-
-            // Sync initial color so state is consistent when applying further updates.
-            latest_lb_color.store(
-                pack_rgb(initial_lb_color.0, initial_lb_color.1, initial_lb_color.2),
-                Ordering::Relaxed,
-            );
-            notify_coalescer(&tx_lb_color);
-            shared_lb_color.store(
-                pack_rgb(initial_lb_color.0, initial_lb_color.1, initial_lb_color.2),
-                Ordering::Relaxed,
+        let lb_label = Label::builder()
+            .label("Lightbar")
+            .halign(gtk4::Align::Start)
+            .build();
+        lb_label.add_css_class("title-4");
+        section.append(&lb_label);
+
+        let color_box = Box::new(Orientation::Horizontal, 10);
+        color_box.append(&Label::new(Some("Color:")));
+        let (latest_lb_color, tx_lb_color) = spawn_lb_color_worker(Arc::clone(&backend), Arc::clone(&lb_targets));
+
+        let latest_lb_color_for_dropdown = Arc::clone(&latest_lb_color);
+        let tx_lb_color_for_dropdown = tx_lb_color.clone();
+        let shared_lb_color_for_dropdown = Arc::clone(&shared_lb_color);
+        let kb_color_for_dropdown = kb_color_shared.clone();
+        let kb_brightness_for_dropdown = kb_brightness_shared.clone();
+        let lb_brightness_for_dropdown = Arc::clone(&shared_lb_brightness);
+        let lb_max_for_dropdown = Rc::clone(&lb_max);
+        let persist_state_for_lb_dropdown = Arc::clone(&persist_state);
+        let tx_persist_for_lb_dropdown = tx_persist.clone();
+        let switching_for_lb_dropdown = Rc::clone(&switching_profile);
+        let picker = ColorPicker::new(&presets, (255, 255, 255), move |(r, g, b)| {
+            if switching_for_lb_dropdown.get() {
+                return;
+            }
+            latest_lb_color_for_dropdown.store(pack_rgb(r, g, b), Ordering::Relaxed);
+            notify_coalescer(&tx_lb_color_for_dropdown);
+            shared_lb_color_for_dropdown.store(pack_rgb(r, g, b), Ordering::Relaxed);
+            let kb_state = kb_color_for_dropdown
+                .borrow()
+                .as_ref()
+                .map(|c: &Arc<AtomicU32>| c.load(Ordering::Relaxed))
+                .unwrap_or(0);
+            let kb_brightness_val = kb_brightness_for_dropdown
+                .borrow()
+                .as_ref()
+                .map(|b: &Arc<AtomicI32>| b.load(Ordering::Relaxed))
+                .unwrap_or(0);
+            let lb_brightness_val = lb_brightness_for_dropdown.load(Ordering::Relaxed);
+            persist_color_state(
+                &persist_state_for_lb_dropdown,
+                &tx_persist_for_lb_dropdown,
+                kb_state,
+                Brightness::new(kb_brightness_val, kb_max),
+                Some(latest_lb_color_for_dropdown.load(Ordering::Relaxed)),
+                Some(Brightness::new(lb_brightness_val, lb_max_for_dropdown.get())),
             );
+        });
 
-            let preset_colors = [
-                ("Red", RGBA::new(1.0, 0.0, 0.0, 1.0)),
-                ("Blue", RGBA::new(0.0, 0.0, 1.0, 1.0)),
-                ("Green", RGBA::new(0.0, 1.0, 0.0, 1.0)),
-                ("Pink", RGBA::new(1.0, 0.41, 0.71, 1.0)),
-                ("Orange", RGBA::new(1.0, 0.5, 0.0, 1.0)),
-                ("Light Blue", RGBA::new(0.0, 0.6, 1.0, 1.0)),
-            ];
-            let latest_lb_color_for_dropdown = Arc::clone(&latest_lb_color);
-            let tx_lb_color_for_dropdown = tx_lb_color.clone();
-            let shared_lb_color_for_dropdown = Arc::clone(&shared_lb_color);
-            let kb_color_for_dropdown = kb_color_shared.clone();
-            let kb_brightness_for_dropdown = kb_brightness_shared.clone();
-            let lb_brightness_for_dropdown = Arc::clone(&shared_lb_brightness);
-            let persist_state_for_lb_dropdown = Arc::clone(&persist_state);
-            let tx_persist_for_lb_dropdown = tx_persist.clone();
-            let dropdown = dropdown_for_colors(&preset_colors, initial_lb_color, move |rgba| {
-                let (r, g, b) = rgba_to_rgb8(&rgba);
-                latest_lb_color_for_dropdown.store(pack_rgb(r, g, b), Ordering::Relaxed);
-                notify_coalescer(&tx_lb_color_for_dropdown);
-                shared_lb_color_for_dropdown.store(pack_rgb(r, g, b), Ordering::Relaxed);
-                let kb_state = kb_color_for_dropdown
-                    .borrow()
-                    .as_ref()
-                    .map(|c: &Arc<AtomicU32>| c.load(Ordering::Relaxed))
-                    .unwrap_or(0);
-                let kb_brightness_val = kb_brightness_for_dropdown
-                    .borrow()
-                    .as_ref()
-                    .map(|b: &Arc<AtomicI32>| b.load(Ordering::Relaxed))
-                    .unwrap_or(0);
-                let lb_brightness_val = lb_brightness_for_dropdown.load(Ordering::Relaxed);
-                persist_color_state(
-                    &persist_state_for_lb_dropdown,
-                    &tx_persist_for_lb_dropdown,
-                    kb_state,
-                    kb_brightness_val,
-                    Some(latest_lb_color_for_dropdown.load(Ordering::Relaxed)),
-                    Some(lb_brightness_val),
-                );
-            });
-
-            color_box.append(&dropdown);
-            section.append(&color_box);
-
-            let bright_box = Box::new(Orientation::Vertical, 4);
-            bright_box.append(&Label::builder().label("Brightness (0-100)").halign(gtk4::Align::Start).build());
-            let lb_bright_scale = Scale::with_range(Orientation::Horizontal, 0.0, 100.0, 5.0);
-            // Initialize brightness from current hardware state
-            if let Some(val) = read_brightness(&lb_path) {
-                lb_bright_scale.set_value(val as f64);
+        color_box.append(&picker.widget);
+        section.append(&color_box);
+        *lb_picker_shared.borrow_mut() = Some(picker);
+
+        let bright_box = Box::new(Orientation::Vertical, 4);
+        bright_box.append(&Label::builder().label("Brightness (%)").halign(gtk4::Align::Start).build());
+        let lb_bright_scale = brightness_scale();
+        let (latest_lb_bright, tx_lb_bright) = spawn_lb_brightness_worker(Arc::clone(&backend), Arc::clone(&lb_targets));
+        let latest_lb_bright_for_cb = Arc::clone(&latest_lb_bright);
+        let shared_lb_brightness_for_cb = Arc::clone(&shared_lb_brightness);
+        let lb_max_for_cb = Rc::clone(&lb_max);
+        let kb_color_for_lb_brightness = kb_color_shared.clone();
+        let kb_brightness_for_lb_brightness = kb_brightness_shared.clone();
+        let persist_state_for_lb_brightness = Arc::clone(&persist_state);
+        let tx_persist_for_lb_brightness = tx_persist.clone();
+        let switching_for_lb_brightness = Rc::clone(&switching_profile);
+        let shared_lb_color_for_cb = Arc::clone(&shared_lb_color);
+        lb_bright_scale.connect_value_changed(move |scale| {
+            if switching_for_lb_brightness.get() {
+                return;
             }
-            lb_bright_scale.set_draw_value(true);
-            lb_bright_scale.set_digits(0);
-            lb_bright_scale.set_increments(10.0, 25.0);
-            let (latest_lb_bright, tx_lb_bright) = spawn_lb_brightness_worker(lb_path.clone());
-            shared_lb_brightness.store(
-                read_brightness(&lb_path).unwrap_or(0),
-                Ordering::Relaxed,
+            let lb_max = lb_max_for_cb.get();
+            let val = Brightness::from_percent(scale.value(), lb_max).value;
+            latest_lb_bright_for_cb.store(val, Ordering::Relaxed);
+            notify_coalescer(&tx_lb_bright);
+            shared_lb_brightness_for_cb.store(val, Ordering::Relaxed);
+            let kb_color_val = kb_color_for_lb_brightness
+                .borrow()
+                .as_ref()
+                .map(|c: &Arc<AtomicU32>| c.load(Ordering::Relaxed))
+                .unwrap_or(0);
+            let kb_brightness_val = kb_brightness_for_lb_brightness
+                .borrow()
+                .as_ref()
+                .map(|b: &Arc<AtomicI32>| b.load(Ordering::Relaxed))
+                .unwrap_or(0);
+            persist_color_state(
+                &persist_state_for_lb_brightness,
+                &tx_persist_for_lb_brightness,
+                kb_color_val,
+                Brightness::new(kb_brightness_val, kb_max),
+                Some(shared_lb_color_for_cb.load(Ordering::Relaxed)),
+                Some(Brightness::new(val, lb_max)),
             );
-            let shared_lb_brightness_for_cb = Arc::clone(&shared_lb_brightness);
-            let kb_color_for_lb_brightness = kb_color_shared.clone();
-            let kb_brightness_for_lb_brightness = kb_brightness_shared.clone();
-            let persist_state_for_lb_brightness = Arc::clone(&persist_state);
-            let tx_persist_for_lb_brightness = tx_persist.clone();
-            lb_bright_scale.connect_value_changed(move |scale| {
-                let val = scale.value() as i32;
-                latest_lb_bright.store(val, Ordering::Relaxed);
-                notify_coalescer(&tx_lb_bright);
-                shared_lb_brightness_for_cb.store(val, Ordering::Relaxed);
-                let kb_color_val = kb_color_for_lb_brightness
-                    .borrow()
-                    .as_ref()
-                    .map(|c: &Arc<AtomicU32>| c.load(Ordering::Relaxed))
-                    .unwrap_or(0);
-                let kb_brightness_val = kb_brightness_for_lb_brightness
-                    .borrow()
-                    .as_ref()
-                    .map(|b: &Arc<AtomicI32>| b.load(Ordering::Relaxed))
-                    .unwrap_or(0);
-                persist_color_state(
-                    &persist_state_for_lb_brightness,
-                    &tx_persist_for_lb_brightness,
-                    kb_color_val,
-                    kb_brightness_val,
-                    Some(shared_lb_color.load(Ordering::Relaxed)),
-                    Some(val),
-                );
-            });
-            bright_box.append(&lb_bright_scale);
-            section.append(&bright_box);
+        });
+        bright_box.append(&lb_bright_scale);
+        section.append(&bright_box);
+        *lb_scale_shared.borrow_mut() = Some(lb_bright_scale);
+
+        container.append(&section);
+        let lb_missing = Label::new(Some("Lightbar not found"));
+        container.append(&lb_missing);
+
+        // Takes up the lightbar at `path`: from what it shows at startup, and
+        // from the saved state once hotplug gave it that later on.
+        let show_lightbar = {
+            let backend = Arc::clone(&backend);
+            let persist_state = Arc::clone(&persist_state);
+            let switching = Rc::clone(&switching_profile);
+            let lb_picker = Rc::clone(&lb_picker_shared);
+            let lb_scale = Rc::clone(&lb_scale_shared);
+            let lb_available = lb_available.clone();
+            let lb_max = Rc::clone(&lb_max);
+            let shared_lb_color = Arc::clone(&shared_lb_color);
+            let shared_lb_brightness = Arc::clone(&shared_lb_brightness);
+            move |path: Option<&std::path::Path>, from_config: bool| {
+                section.set_visible(path.is_some());
+                lb_missing.set_visible(path.is_none());
+                let Some(path) = path else { return };
+                // Stays set while the lightbar is briefly gone, so its saved state is kept.
+                lb_available.set(true);
+                let max = read_max_brightness(backend.as_ref(), path).unwrap_or(DEFAULT_LB_MAX_BRIGHTNESS);
+                lb_max.set(max);
+                let saved = persist_state.lock().unwrap().lightbar.clone().filter(|_| from_config);
+                let (color, value) = match saved {
+                    Some(lb) => (lb.color.tuple(), lb.brightness().scaled_to(max)),
+                    None => (
+                        read_color(backend.as_ref(), path).unwrap_or((255, 255, 255)),
+                        read_brightness(backend.as_ref(), path).unwrap_or(0),
+                    ),
+                };
+                // Sync the workers so state is consistent when applying further updates.
+                latest_lb_color.store(pack_rgb(color.0, color.1, color.2), Ordering::Relaxed);
+                shared_lb_color.store(pack_rgb(color.0, color.1, color.2), Ordering::Relaxed);
+                latest_lb_bright.store(value, Ordering::Relaxed);
+                shared_lb_brightness.store(value, Ordering::Relaxed);
+                switching.set(true);
+                if let Some(picker) = lb_picker.borrow().as_ref() {
+                    picker.set_color(color);
+                }
+                if let Some(scale) = lb_scale.borrow().as_ref() {
+                    scale.set_value(Brightness::new(value, max).percent());
+                }
+                switching.set(false);
+            }
+        };
+        show_lightbar(lb_path.as_deref(), false);
+        hotplug_view::follow_hotplug(
+            Arc::clone(&backend),
+            Arc::clone(&kb_targets),
+            Arc::clone(&lb_targets),
+            Arc::clone(&persist_state),
+            lb_path,
+            move |path| show_lightbar(path, true),
+        );
+
+        // Brightness set with the Fn keys moves the sliders. Setting them while
+        // `switching_profile` is set keeps their callbacks from writing it back.
+        let (tx_hw, rx_hw) = std::sync::mpsc::channel();
+        hw_brightness::spawn_brightness_watcher(
+            Arc::clone(&backend),
+            Arc::clone(&persist_state),
+            tx_persist.clone(),
+            move |change| {
+                let _ = tx_hw.send(change);
+            },
+        );
+        let switching_for_hw = Rc::clone(&switching_profile);
+        let kb_scale_for_hw = Rc::clone(&kb_scale_shared);
+        let lb_scale_for_hw = Rc::clone(&lb_scale_shared);
+        let kb_brightness_for_hw = kb_brightness_shared.clone();
+        let lb_brightness_for_hw = Arc::clone(&shared_lb_brightness);
+        glib::timeout_add_local(hw_brightness::SLIDER_POLL, move || {
+            while let Ok(change) = rx_hw.try_recv() {
+                let scale = match change.target {
+                    hw_brightness::Target::Keyboard => {
+                        if let Some(latest) = kb_brightness_for_hw.borrow().as_ref() {
+                            latest.store(change.brightness.value, Ordering::Relaxed);
+                        }
+                        &kb_scale_for_hw
+                    }
+                    hw_brightness::Target::Lightbar => {
+                        lb_brightness_for_hw.store(change.brightness.value, Ordering::Relaxed);
+                        &lb_scale_for_hw
+                    }
+                };
+                switching_for_hw.set(true);
+                if let Some(scale) = scale.borrow().as_ref() {
+                    scale.set_value(change.brightness.percent());
+                }
+                switching_for_hw.set(false);
+            }
+            glib::ControlFlow::Continue
+        });
 
-            container.append(&section);
-        } else {
-            container.append(&Label::new(Some("Lightbar not found")));
-        }
+        let kb_picker_for_palette = Rc::clone(&kb_picker_shared);
+        let lb_picker_for_palette = Rc::clone(&lb_picker_shared);
+        container.append(&palette_view::palette_editor(
+            Arc::clone(&persist_state),
+            tx_persist.clone(),
+            move |presets| {
+                for picker in [&kb_picker_for_palette, &lb_picker_for_palette] {
+                    if let Some(picker) = picker.borrow().as_ref() {
+                        picker.set_presets(presets);
+                    }
+                }
+            },
+        ));
 
         // Exit Button
         let exit_box = Box::new(Orientation::Horizontal, 0);
@@ -644,5 +619,8 @@ fn main() {
         window.present();
     });
 
+    // Closing the window quits the app; the last slider and color changes may
+    // still be on their way to the LEDs and the config.
+    app.connect_shutdown(|_| workers::flush_workers());
     app.run();
 }
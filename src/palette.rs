@@ -0,0 +1,134 @@
+// Palette files that can be imported as presets: GIMP `.gpl` palettes and plain
+// lists with one color per line (`#ff0000 Red`, `00ff00`, ... as in Lospec `.hex`).
+
+use crate::config::{Preset, Rgb};
+use crate::leds::{format_hex, parse_color};
+use std::fs;
+use std::path::Path;
+
+const GPL_HEADER: &str = "GIMP Palette";
+
+// Unnamed swatches are called by their hex value; repeated names get a number.
+fn push_swatch(presets: &mut Vec<Preset>, name: &str, color: Rgb) {
+    let base = match name.trim() {
+        "" | "Untitled" => format_hex(color.tuple()),
+        name => name.to_string(),
+    };
+    let mut name = base.clone();
+    let mut n = 2;
+    while presets.iter().any(|p| p.name == name) {
+        name = format!("{} ({})", base, n);
+        n += 1;
+    }
+    presets.push(Preset { name, color });
+}
+
+/// A GIMP palette: the header, optional `Name:`/`Columns:` lines and `#`
+/// comments, then `R G B name` lines.
+pub fn parse_gpl(content: &str) -> Result<Vec<Preset>, String> {
+    let mut lines = content.lines().enumerate();
+    if lines.next().is_none_or(|(_, l)| l.trim() != GPL_HEADER) {
+        return Err(format!("missing '{}' header", GPL_HEADER));
+    }
+    let mut presets = Vec::new();
+    for (i, line) in lines {
+        let line = line.trim();
+        if line.is_empty()
+            || line.starts_with('#')
+            || line.starts_with("Name:")
+            || line.starts_with("Columns:")
+        {
+            continue;
+        }
+        let mut words = line.split_whitespace();
+        let mut channel = || words.next().and_then(|w| w.parse::<u8>().ok());
+        let (Some(r), Some(g), Some(b)) = (channel(), channel(), channel()) else {
+            return Err(format!("line {}: expected 'R G B name', got '{}'", i + 1, line));
+        };
+        let name = words.collect::<Vec<_>>().join(" ");
+        push_swatch(&mut presets, &name, Rgb(r, g, b));
+    }
+    Ok(presets)
+}
+
+/// One color per line, optionally followed by a name. Lines that don't start
+/// with a color but with `#`, `;` or `//` are comments.
+pub fn parse_color_list(content: &str) -> Result<Vec<Preset>, String> {
+    let mut presets = Vec::new();
+    for (i, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let (color, name) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        match parse_color(color) {
+            Ok((r, g, b)) => push_swatch(&mut presets, name, Rgb(r, g, b)),
+            Err(_) if line.starts_with(['#', ';']) || line.starts_with("//") => {}
+            Err(e) => return Err(format!("line {}: {}", i + 1, e)),
+        }
+    }
+    Ok(presets)
+}
+
+pub fn import_palette(path: &Path) -> Result<Vec<Preset>, String> {
+    let content =
+        fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let presets = if content.trim_start().starts_with(GPL_HEADER) {
+        parse_gpl(content.trim_start())
+    } else {
+        parse_color_list(&content)
+    }
+    .map_err(|e| format!("{}: {}", path.display(), e))?;
+    if presets.is_empty() {
+        return Err(format!("{}: no colors found", path.display()));
+    }
+    Ok(presets)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp_dir::TempDir;
+
+    fn swatches(presets: &[Preset]) -> Vec<(&str, (u8, u8, u8))> {
+        presets.iter().map(|p| (p.name.as_str(), p.color.tuple())).collect()
+    }
+
+    #[test]
+    fn parses_gimp_palettes() {
+        let gpl = "GIMP Palette\nName: Test\nColumns: 4\n# comment\n255 0 0 Red\n  0 128 255\t Sky Blue\n0 0 0 Untitled\n1 2 3 Red\n";
+        let presets = parse_gpl(gpl).unwrap();
+        assert_eq!(
+            swatches(&presets),
+            [("Red", (255, 0, 0)), ("Sky Blue", (0, 128, 255)), ("#000000", (0, 0, 0)), ("Red (2)", (1, 2, 3))]
+        );
+        assert!(parse_gpl("255 0 0 Red\n").is_err());
+        assert!(parse_gpl("GIMP Palette\n255 0 Red\n").unwrap_err().starts_with("line 2:"));
+    }
+
+    #[test]
+    fn parses_color_lists() {
+        let list = "; Lospec export\nff0000\n#00ff00 Green\n// more\n0,0,255 Deep Blue\n";
+        let presets = parse_color_list(list).unwrap();
+        assert_eq!(
+            swatches(&presets),
+            [("#ff0000", (255, 0, 0)), ("Green", (0, 255, 0)), ("Deep Blue", (0, 0, 255))]
+        );
+        assert!(parse_color_list("ff0000\nnot a color\n").unwrap_err().starts_with("line 2:"));
+    }
+
+    #[test]
+    fn imports_palette_files() {
+        let dir = TempDir::new("palette");
+        let gpl = dir.join("test.gpl");
+        fs::write(&gpl, "\nGIMP Palette\n10 20 30 Slate\n").unwrap();
+        assert_eq!(swatches(&import_palette(&gpl).unwrap()), [("Slate", (10, 20, 30))]);
+        let hex = dir.join("test.hex");
+        fs::write(&hex, "a0b0c0\n").unwrap();
+        assert_eq!(swatches(&import_palette(&hex).unwrap()), [("#a0b0c0", (160, 176, 192))]);
+        let empty = dir.join("empty.hex");
+        fs::write(&empty, "# nothing\n").unwrap();
+        assert!(import_palette(&empty).unwrap_err().contains("no colors found"));
+        assert!(import_palette(&dir.join("missing.gpl")).is_err());
+    }
+}
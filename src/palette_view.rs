@@ -0,0 +1,212 @@
+use crate::config::{Config, Preset, Rgb};
+use crate::palette::import_palette;
+use crate::persist::{update_config, SharedConfig};
+use crate::rgba_to_rgb8;
+use gdk4::RGBA;
+use gtk4::prelude::*;
+use gtk4::{
+    Box, Button, ColorButton, Entry, Expander, FileChooserAction, FileChooserNative, FileFilter,
+    Label, ListBox, Orientation, ResponseType,
+};
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::sync::mpsc;
+
+type OnChange = std::boxed::Box<dyn Fn(&[Preset])>;
+
+struct PaletteEditor {
+    rows: ListBox,
+    status: Label,
+    config: SharedConfig,
+    tx_persist: mpsc::SyncSender<()>,
+    on_change: OnChange,
+    // A native file chooser is dropped (and closed) with its last reference.
+    import_dialog: RefCell<Option<FileChooserNative>>,
+}
+
+fn rgba((r, g, b): (u8, u8, u8)) -> RGBA {
+    RGBA::new(r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0, 1.0)
+}
+
+impl PaletteEditor {
+    fn edit(self: &Rc<Self>, change: impl FnOnce(&mut Config) -> Result<(), String>) -> bool {
+        let edited = update_config(&self.config, &self.tx_persist, |c| {
+            change(c)?;
+            Ok(c.presets.clone())
+        });
+        match edited {
+            Ok(presets) => {
+                self.status.set_label("");
+                self.fill(&presets);
+                (self.on_change)(&presets);
+                true
+            }
+            Err(e) => {
+                self.report(&e);
+                false
+            }
+        }
+    }
+
+    fn report(&self, e: &str) {
+        eprintln!("Error: {}", e);
+        self.status.set_label(e);
+    }
+
+    fn fill(self: &Rc<Self>, presets: &[Preset]) {
+        while let Some(row) = self.rows.first_child() {
+            self.rows.remove(&row);
+        }
+        for (i, preset) in presets.iter().enumerate() {
+            self.rows.append(&self.row(i, preset, presets.len()));
+        }
+    }
+
+    // Swatch (click to recolor), name (Enter renames), move up/down and delete.
+    fn row(self: &Rc<Self>, index: usize, preset: &Preset, count: usize) -> Box {
+        let row = Box::new(Orientation::Horizontal, 6);
+        let swatch = ColorButton::with_rgba(&rgba(preset.color.tuple()));
+        let name_entry = Entry::builder().text(preset.name.as_str()).hexpand(true).build();
+        name_entry.set_tooltip_text(Some("Press Enter to rename"));
+        let up = Button::from_icon_name("go-up-symbolic");
+        up.set_sensitive(index > 0);
+        let down = Button::from_icon_name("go-down-symbolic");
+        down.set_sensitive(index + 1 < count);
+        let delete = Button::from_icon_name("edit-delete-symbolic");
+        row.append(&swatch);
+        row.append(&name_entry);
+        row.append(&up);
+        row.append(&down);
+        row.append(&delete);
+
+        // Rows are rebuilt after every edit, so each handler knows its preset by name.
+        let handler = |edit: fn(&mut Config, &str, usize) -> Result<(), String>| {
+            let editor = Rc::downgrade(self);
+            let name = preset.name.clone();
+            move || {
+                if let Some(editor) = editor.upgrade() {
+                    editor.edit(|c| edit(c, &name, index));
+                }
+            }
+        };
+        let move_up = handler(|c, name, index| c.move_preset(name, index - 1));
+        up.connect_clicked(move |_| move_up());
+        let move_down = handler(|c, name, index| c.move_preset(name, index + 1));
+        down.connect_clicked(move |_| move_down());
+        let remove = handler(|c, name, _| c.delete_preset(name));
+        delete.connect_clicked(move |_| remove());
+
+        let editor = Rc::downgrade(self);
+        let name = preset.name.clone();
+        swatch.connect_color_set(move |b| {
+            let (r, g, bl) = rgba_to_rgb8(&b.rgba());
+            if let Some(editor) = editor.upgrade() {
+                editor.edit(|c| c.add_preset(&name, Rgb(r, g, bl)));
+            }
+        });
+        let editor = Rc::downgrade(self);
+        let name = preset.name.clone();
+        name_entry.connect_activate(move |entry| {
+            if let Some(editor) = editor.upgrade() {
+                editor.edit(|c| c.rename_preset(&name, &entry.text()));
+            }
+        });
+        row
+    }
+
+    fn open_import_dialog(self: &Rc<Self>) {
+        let parent = self.rows.root().and_downcast::<gtk4::Window>();
+        let dialog = FileChooserNative::new(
+            Some("Import palette"),
+            parent.as_ref(),
+            FileChooserAction::Open,
+            Some("Import"),
+            Some("Cancel"),
+        );
+        let filter = FileFilter::new();
+        filter.set_name(Some("Palettes (.gpl, .hex, .txt)"));
+        for pattern in ["*.gpl", "*.hex", "*.txt"] {
+            filter.add_pattern(pattern);
+        }
+        dialog.add_filter(&filter);
+        let editor = Rc::downgrade(self);
+        dialog.connect_response(move |dialog, response| {
+            let Some(editor) = editor.upgrade() else { return };
+            let path = dialog.file().and_then(|f| f.path());
+            if let (ResponseType::Accept, Some(path)) = (response, path) {
+                match import_palette(&path) {
+                    Ok(presets) => {
+                        editor.edit(|c| {
+                            c.import_presets(presets);
+                            Ok(())
+                        });
+                    }
+                    Err(e) => editor.report(&e),
+                }
+            }
+            editor.import_dialog.borrow_mut().take();
+        });
+        dialog.show();
+        *self.import_dialog.borrow_mut() = Some(dialog);
+    }
+}
+
+/// Editor for the palette shared by the color dropdowns. `on_change` gets the
+/// presets after every edit.
+pub fn palette_editor(
+    config: SharedConfig,
+    tx_persist: mpsc::SyncSender<()>,
+    on_change: impl Fn(&[Preset]) + 'static,
+) -> Expander {
+    let content = Box::new(Orientation::Vertical, 8);
+    let rows = ListBox::new();
+    rows.set_selection_mode(gtk4::SelectionMode::None);
+    content.append(&rows);
+
+    let add_row = Box::new(Orientation::Horizontal, 6);
+    let new_color = ColorButton::with_rgba(&rgba((255, 255, 255)));
+    let new_name = Entry::builder().placeholder_text("Preset name").hexpand(true).build();
+    let add_btn = Button::with_label("Add");
+    let import_btn = Button::with_label("Import…");
+    add_row.append(&new_color);
+    add_row.append(&new_name);
+    add_row.append(&add_btn);
+    add_row.append(&import_btn);
+    content.append(&add_row);
+
+    let status = Label::new(None);
+    status.set_wrap(true);
+    status.add_css_class("error");
+    content.append(&status);
+
+    let presets = config.lock().unwrap().presets.clone();
+    let editor = Rc::new(PaletteEditor {
+        rows,
+        status,
+        config,
+        tx_persist,
+        on_change: std::boxed::Box::new(on_change),
+        import_dialog: RefCell::new(None),
+    });
+    editor.fill(&presets);
+
+    let editor_for_add = Rc::clone(&editor);
+    add_btn.connect_clicked(move |_| {
+        let (r, g, b) = rgba_to_rgb8(&new_color.rgba());
+        let name = new_name.text().to_string();
+        let added = editor_for_add.edit(|c| {
+            if c.presets.iter().any(|p| p.name == name.trim()) {
+                return Err(format!("a preset named '{}' already exists", name.trim()));
+            }
+            c.add_preset(&name, Rgb(r, g, b))
+        });
+        if added {
+            new_name.set_text("");
+        }
+    });
+    import_btn.connect_clicked(move |_| editor.open_import_dialog());
+
+    let expander = Expander::new(Some("Palette"));
+    expander.set_child(Some(&content));
+    expander
+}
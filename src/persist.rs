@@ -0,0 +1,436 @@
+use crate::config::{config_path, merge, read_config, save_merged, Config, Rgb};
+use crate::leds::pack_rgb;
+use crate::workers::on_shutdown;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+// The brightness scale differs between LEDs and driver versions, so every value
+// is stored with the `max_brightness` it was set against.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Brightness {
+    pub value: i32,
+    pub max: i32,
+}
+
+impl Brightness {
+    pub fn new(value: i32, max: i32) -> Self {
+        Brightness { value: value.clamp(0, max.max(0)), max }
+    }
+
+    /// `percent` (0-100) of `max`, rounded to the nearest step the LED has.
+    pub fn from_percent(percent: f64, max: i32) -> Self {
+        Brightness::new((percent.clamp(0.0, 100.0) * max as f64 / 100.0).round() as i32, max)
+    }
+
+    pub fn percent(&self) -> f64 {
+        if self.max <= 0 {
+            return 0.0;
+        }
+        self.value as f64 * 100.0 / self.max as f64
+    }
+
+    /// The same relative brightness on an LED whose maximum is `max`.
+    pub fn scaled_to(&self, max: i32) -> i32 {
+        if self.max <= 0 || self.max == max {
+            return self.value.clamp(0, max);
+        }
+        let scaled = (self.value as i64 * max as i64 + self.max as i64 / 2) / self.max as i64;
+        (scaled as i32).clamp(0, max)
+    }
+}
+
+// Fallbacks for LEDs that don't report `max_brightness`, and the scale version 1
+// files were written in (the slider ranges of the window).
+pub const DEFAULT_KB_MAX_BRIGHTNESS: i32 = 50;
+pub const DEFAULT_LB_MAX_BRIGHTNESS: i32 = 100;
+
+const STATE_VERSION: u32 = 2;
+
+pub struct PersistState {
+    pub kb_color: u32,
+    pub kb_brightness: Brightness,
+    pub lb_color: Option<u32>,
+    pub lb_brightness: Option<Brightness>,
+}
+
+pub fn rusty_kb_dir() -> Option<PathBuf> {
+    let home = env::var("HOME").ok()?;
+    Some(PathBuf::from(home).join(".rusty-kb"))
+}
+
+/// A file for this session only, shared by the window, the daemon and the
+/// command line: `$XDG_RUNTIME_DIR/rusty-kb-<name>`, or `~/.rusty-kb/<name>`.
+pub fn runtime_path(name: &str) -> Option<PathBuf> {
+    match env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) => Some(PathBuf::from(dir).join(format!("rusty-kb-{}", name))),
+        None => Some(rusty_kb_dir()?.join(name)),
+    }
+}
+
+pub fn state_path() -> Option<PathBuf> {
+    Some(rusty_kb_dir()?.join("colors.txt"))
+}
+
+pub fn key_colors_path() -> Option<PathBuf> {
+    Some(rusty_kb_dir()?.join("keys.txt"))
+}
+
+// Legacy `colors.txt` state files, superseded by config.toml but still imported.
+//
+// Version 2:
+//   version 2
+//   keyboard R G B BRIGHTNESS MAX_BRIGHTNESS
+//   lightbar R G B BRIGHTNESS MAX_BRIGHTNESS   (or `lightbar none`)
+fn parse_numbers(fields: &[&str]) -> Option<Vec<i32>> {
+    fields.iter().map(|s| s.parse().ok()).collect()
+}
+
+fn pack_channels(r: i32, g: i32, b: i32) -> Option<u32> {
+    let channel = |v: i32| u8::try_from(v).ok();
+    Some(pack_rgb(channel(r)?, channel(g)?, channel(b)?))
+}
+
+// Version 1 has no header: `r g b brightness` for the keyboard, then the same for
+// the lightbar with `0 0 0` meaning "no lightbar". Brightness was the raw value of
+// the window's sliders, i.e. out of 50 (keyboard) and 100 (lightbar).
+fn parse_v1(lines: &[&str]) -> Result<PersistState, String> {
+    let parse_line = |line: &str| -> Option<(u32, i32)> {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let [r, g, b, brightness] = parse_numbers(&fields)?[..] else { return None };
+        Some((pack_channels(r, g, b)?, brightness))
+    };
+    let kb_line = lines.first().ok_or("empty state file")?;
+    let (kb_color, kb_value) =
+        parse_line(kb_line).ok_or_else(|| format!("invalid keyboard line '{}'", kb_line))?;
+    let (lb_color, lb_brightness) = match lines.get(1) {
+        Some(line) => match parse_line(line) {
+            Some((0, _)) => (None, None),
+            Some((color, value)) => (
+                Some(color),
+                Some(Brightness::new(value, DEFAULT_LB_MAX_BRIGHTNESS)),
+            ),
+            None => return Err(format!("invalid lightbar line '{}'", line)),
+        },
+        None => (None, None),
+    };
+    Ok(PersistState {
+        kb_color,
+        kb_brightness: Brightness::new(kb_value, DEFAULT_KB_MAX_BRIGHTNESS),
+        lb_color,
+        lb_brightness,
+    })
+}
+
+fn parse_v2(lines: &[&str]) -> Result<PersistState, String> {
+    let mut keyboard = None;
+    let mut lightbar = None;
+    for line in lines {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let (name, rest) = fields.split_first().ok_or("empty line")?;
+        let entry = if rest == ["none"] {
+            None
+        } else {
+            let parsed = parse_numbers(rest).and_then(|n| match n[..] {
+                [r, g, b, value, max] if max > 0 => {
+                    Some((pack_channels(r, g, b)?, Brightness::new(value, max)))
+                }
+                _ => None,
+            });
+            Some(parsed.ok_or_else(|| format!("invalid {} line '{}'", name, line))?)
+        };
+        match *name {
+            "keyboard" => keyboard = Some(entry.ok_or("keyboard can't be 'none'")?),
+            "lightbar" => lightbar = entry,
+            other => return Err(format!("unknown entry '{}'", other)),
+        }
+    }
+    let (kb_color, kb_brightness) = keyboard.ok_or("missing keyboard line")?;
+    Ok(PersistState {
+        kb_color,
+        kb_brightness,
+        lb_color: lightbar.map(|(color, _)| color),
+        lb_brightness: lightbar.map(|(_, brightness)| brightness),
+    })
+}
+
+/// Parses a state file of either version.
+pub fn parse_state(content: &str) -> Result<PersistState, String> {
+    let lines: Vec<&str> = content
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty())
+        .collect();
+    match lines.first().and_then(|l| l.strip_prefix("version ")) {
+        Some(v) if v.trim() == STATE_VERSION.to_string() => parse_v2(&lines[1..]),
+        Some(v) => Err(format!("unsupported state version '{}'", v.trim())),
+        None => parse_v1(&lines),
+    }
+}
+
+pub fn load_state(path: &Path) -> Result<PersistState, String> {
+    let content =
+        fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    parse_state(&content).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+/// Per-key colors saved by earlier versions of the keyboard painter, one
+/// `name r g b` line per key.
+pub fn load_key_colors() -> Vec<(String, (u8, u8, u8))> {
+    let Some(content) = key_colors_path().and_then(|p| fs::read_to_string(p).ok()) else {
+        return Vec::new();
+    };
+    content
+        .lines()
+        .filter_map(|line| {
+            let mut parts = line.split_whitespace();
+            let name = parts.next()?.to_string();
+            let mut channel = || parts.next()?.parse::<u8>().ok();
+            Some((name, (channel()?, channel()?, channel()?)))
+        })
+        .collect()
+}
+
+// Numbers the temporary files, so that two saves in one process (e.g. the
+// persistence worker and the shutdown flush) never share one.
+static TMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Replaces `path` with `contents` so that a crash leaves either the old or the
+/// new file, never a truncated one: the data goes to a temporary file next to
+/// it, is synced, and then renamed over it.
+pub fn write_atomically(path: &Path, contents: &str) -> io::Result<()> {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let n = TMP_COUNTER.fetch_add(1, Ordering::Relaxed);
+    let tmp = path.with_file_name(format!(".{}.{}.{}.tmp", name, std::process::id(), n));
+    let result = (|| {
+        let mut file = fs::File::create(&tmp)?;
+        file.write_all(contents.as_bytes())?;
+        file.sync_all()?;
+        fs::rename(&tmp, path)
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp);
+        return result;
+    }
+    // Make the rename itself survive a power loss.
+    if let Some(dir) = path.parent() {
+        fs::File::open(dir)?.sync_all()?;
+    }
+    Ok(())
+}
+
+pub type SharedConfig = Arc<Mutex<Config>>;
+
+// How often `config.toml` is checked for saves of other processes.
+pub const CONFIG_POLL: Duration = Duration::from_secs(1);
+
+type OnOutsideChange = Arc<dyn Fn(&Config) + Send + Sync>;
+
+// Saves the latest config snapshot whenever notified; bursts of changes coalesce.
+// On shutdown the config is saved once more if it changed since the last save.
+// The window, the daemon and the command line save the same file, so each save
+// is merged with what the others saved meanwhile, and their saves are picked
+// up while idle too. `on_outside_change` gets the config after either.
+pub fn spawn_persistence_worker(
+    config: Config,
+    on_outside_change: impl Fn(&Config) + Send + Sync + 'static,
+) -> (SharedConfig, mpsc::SyncSender<()>) {
+    let on_outside_change: OnOutsideChange = Arc::new(on_outside_change);
+    // The config as last read or saved, the base of the merges.
+    let saved = Arc::new(Mutex::new(config.clone()));
+    let state = Arc::new(Mutex::new(config));
+    let save = {
+        let state = Arc::clone(&state);
+        let saved = Arc::clone(&saved);
+        let on_outside_change = Arc::clone(&on_outside_change);
+        move |only_if_changed: bool| {
+            let mut saved = saved.lock().unwrap();
+            let snapshot = state.lock().unwrap().clone();
+            if only_if_changed && snapshot == *saved {
+                return;
+            }
+            match save_merged(&saved, &snapshot) {
+                Ok(merged) => {
+                    if merged != snapshot {
+                        let current = {
+                            let mut state = state.lock().unwrap();
+                            *state = merge(&snapshot, &state, &merged);
+                            state.clone()
+                        };
+                        on_outside_change(&current);
+                    }
+                    *saved = merged;
+                }
+                Err(e) => eprintln!("Error: could not save config: {}", e),
+            }
+        }
+    };
+    let save = Arc::new(save);
+    let save_for_thread = Arc::clone(&save);
+    let (tx, rx) = mpsc::sync_channel::<()>(1);
+    thread::spawn(move || {
+        while rx.recv().is_ok() {
+            save_for_thread(false);
+        }
+    });
+    on_shutdown(move || save(true));
+    spawn_config_watcher(Arc::clone(&state), saved, on_outside_change);
+    (state, tx)
+}
+
+// Polls `config.toml` and merges saves of other processes (or an editor) into
+// `state`. Our own saves leave the file equal to `saved` and are skipped.
+fn spawn_config_watcher(state: SharedConfig, saved: SharedConfig, on_change: OnOutsideChange) {
+    let Some(path) = config_path() else { return };
+    let stamp = |path: &Path| fs::metadata(path).ok().map(|m| (m.modified().ok(), m.len()));
+    thread::spawn(move || {
+        let mut seen = stamp(&path);
+        loop {
+            thread::sleep(CONFIG_POLL);
+            let current = stamp(&path);
+            if current == seen {
+                continue;
+            }
+            seen = current;
+            let mut saved = saved.lock().unwrap();
+            // A file that doesn't parse may be half-edited; the next save of it is picked up.
+            let Ok(theirs) = read_config(&path) else { continue };
+            if theirs == *saved {
+                continue;
+            }
+            let current = {
+                let mut state = state.lock().unwrap();
+                *state = merge(&saved, &state, &theirs);
+                state.clone()
+            };
+            *saved = theirs;
+            drop(saved);
+            on_change(&current);
+        }
+    });
+}
+
+pub fn persist_color_state(
+    state: &SharedConfig,
+    tx: &mpsc::SyncSender<()>,
+    kb_color: u32,
+    kb_brightness: Brightness,
+    lb_color: Option<u32>,
+    lb_brightness: Option<Brightness>,
+) {
+    state
+        .lock()
+        .unwrap()
+        .set_state(kb_color, kb_brightness, lb_color, lb_brightness);
+    let _ = tx.try_send(());
+}
+
+/// Replaces the per-key overrides of the keyboard color.
+pub fn persist_key_colors(
+    state: &SharedConfig,
+    tx: &mpsc::SyncSender<()>,
+    keys: BTreeMap<String, Rgb>,
+) {
+    state.lock().unwrap().keyboard.keys = keys;
+    let _ = tx.try_send(());
+}
+
+/// Runs `update` on the shared config and saves the result if it succeeds.
+pub fn update_config<T>(
+    state: &SharedConfig,
+    tx: &mpsc::SyncSender<()>,
+    update: impl FnOnce(&mut Config) -> Result<T, String>,
+) -> Result<T, String> {
+    let result = update(&mut state.lock().unwrap())?;
+    let _ = tx.try_send(());
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp_dir::TempDir;
+
+    #[test]
+    fn brightness_scales_to_other_maximums() {
+        let b = Brightness::new(25, 50);
+        assert_eq!(b.scaled_to(50), 25);
+        assert_eq!(b.scaled_to(100), 50);
+        assert_eq!(b.scaled_to(3), 2);
+        assert_eq!(Brightness::new(1, 3).scaled_to(255), 85);
+        // No known scale: the value is taken as it is, within the new maximum.
+        assert_eq!(Brightness { value: 80, max: 0 }.scaled_to(50), 50);
+        assert_eq!(Brightness::new(60, 50).value, 50);
+    }
+
+    #[test]
+    fn brightness_from_percent_rounds_to_a_step() {
+        assert_eq!(Brightness::from_percent(50.0, 3), Brightness::new(2, 3));
+        assert_eq!(Brightness::from_percent(33.0, 100).value, 33);
+        assert_eq!(Brightness::from_percent(150.0, 50).value, 50);
+        assert_eq!(Brightness::from_percent(-5.0, 50).value, 0);
+        assert_eq!(Brightness::new(1, 4).percent(), 25.0);
+        assert_eq!(Brightness::new(1, 0).percent(), 0.0);
+    }
+
+    #[test]
+    fn parses_version_1_state() {
+        let state = parse_state("255 0 0 30\n0 0 255 80\n").unwrap();
+        assert_eq!(state.kb_color, pack_rgb(255, 0, 0));
+        assert_eq!(state.kb_brightness, Brightness::new(30, DEFAULT_KB_MAX_BRIGHTNESS));
+        assert_eq!(state.lb_color, Some(pack_rgb(0, 0, 255)));
+        assert_eq!(state.lb_brightness, Some(Brightness::new(80, DEFAULT_LB_MAX_BRIGHTNESS)));
+
+        let without_lightbar = parse_state("1 2 3 4\n0 0 0 0\n").unwrap();
+        assert_eq!(without_lightbar.lb_color, None);
+        assert!(parse_state("1 2 3 4\n").unwrap().lb_brightness.is_none());
+    }
+
+    #[test]
+    fn parses_version_2_state() {
+        let state = parse_state("version 2\nkeyboard 1 2 3 2 3\nlightbar none\n").unwrap();
+        assert_eq!(state.kb_color, pack_rgb(1, 2, 3));
+        assert_eq!(state.kb_brightness, Brightness::new(2, 3));
+        assert_eq!((state.lb_color, state.lb_brightness), (None, None));
+
+        let state =
+            parse_state("version 2\n\nlightbar 9 9 9 10 255\nkeyboard 0 0 0 0 50\n").unwrap();
+        assert_eq!(state.lb_brightness, Some(Brightness::new(10, 255)));
+    }
+
+    #[test]
+    fn rejects_broken_state() {
+        for content in [
+            "",
+            "256 0 0 30",
+            "1 2 3",
+            "1 2 3 4\nnone",
+            "version 3\nkeyboard 1 2 3 4 5",
+            "version 2\nlightbar none",
+            "version 2\nkeyboard none",
+            "version 2\nkeyboard 1 2 3 4 0",
+            "version 2\nkeyboard 1 2 3 4 5\nmouse 1 2 3 4 5",
+        ] {
+            assert!(parse_state(content).is_err(), "{:?} parsed", content);
+        }
+    }
+
+    #[test]
+    fn writes_atomically_without_leftovers() {
+        let dir = TempDir::new("persist");
+        let path = dir.join("config.toml");
+        write_atomically(&path, "one").unwrap();
+        write_atomically(&path, "two").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "two");
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+        // A failed write leaves no temporary file behind either.
+        assert!(write_atomically(&dir.join("missing/config.toml"), "three").is_err());
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+}
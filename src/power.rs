@@ -0,0 +1,160 @@
+// Suspend and the lid, as reported by logind. The ITE controller often comes
+// back from suspend with its firmware defaults, so the lighting is switched off
+// before sleeping and the saved state is applied again after resume; a closed
+// lid switches it off as well. Both go through the idle marker, so the Fn-key
+// watcher and the idle timer leave the switched-off LEDs alone.
+
+use crate::backend::LedBackend;
+use crate::idle::{set_dimmed, switch_off};
+use crate::leds::apply_lighting;
+use crate::persist::SharedConfig;
+use std::env;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use zbus::blocking::{proxy, Connection, Proxy};
+use zbus::proxy::CacheProperties;
+use zbus::zvariant::OwnedFd;
+
+// `RUSTY_KB_LOGIND_BUS=unix:path=/tmp/bus` talks to a stand-in logind on a
+// private bus instead of the system bus.
+const LOGIND_BUS_ENV: &str = "RUSTY_KB_LOGIND_BUS";
+const LOGIND: &str = "org.freedesktop.login1";
+const MANAGER_PATH: &str = "/org/freedesktop/login1";
+const MANAGER_INTERFACE: &str = "org.freedesktop.login1.Manager";
+pub const LID_POLL: Duration = Duration::from_secs(1);
+// The controller sometimes resets again shortly after resume.
+const RESUME_SETTLE: Duration = Duration::from_secs(2);
+
+pub fn logind_connection() -> zbus::Result<Connection> {
+    match env::var(LOGIND_BUS_ENV) {
+        Ok(address) => zbus::blocking::connection::Builder::address(address.as_str())?.build(),
+        Err(_) => Connection::system(),
+    }
+}
+
+/// A logind object; properties are read fresh, logind doesn't announce all changes.
+pub fn logind_proxy<'a>(connection: &Connection, path: &'a str, interface: &'a str) -> zbus::Result<Proxy<'a>> {
+    proxy::Builder::new(connection)
+        .destination(LOGIND)?
+        .path(path)?
+        .interface(interface)?
+        .cache_properties(CacheProperties::No)
+        .build()
+}
+
+// Asleep or lid closed: the lighting is off while either holds.
+#[derive(Default)]
+struct PowerState {
+    sleeping: bool,
+    lid_closed: bool,
+}
+
+impl PowerState {
+    fn lit(&self) -> bool {
+        !self.sleeping && !self.lid_closed
+    }
+}
+
+fn switch(backend: &dyn LedBackend, config: &SharedConfig, on: bool) {
+    let snapshot = config.lock().unwrap().clone();
+    if !on {
+        switch_off(backend, &snapshot);
+        return;
+    }
+    if let Err(e) = apply_lighting(backend, &snapshot.keyboard, snapshot.lightbar.as_ref(), &snapshot.options) {
+        eprintln!("Error: {}", e);
+    }
+    set_dimmed(false);
+}
+
+// Runs `change` on the state and switches the lighting if that turns it on or off.
+fn update(backend: &dyn LedBackend, config: &SharedConfig, state: &Mutex<PowerState>, change: impl FnOnce(&mut PowerState)) {
+    let mut state = state.lock().unwrap();
+    let was_lit = state.lit();
+    change(&mut state);
+    if state.lit() != was_lit {
+        switch(backend, config, state.lit());
+    }
+}
+
+// A delay lock makes logind wait (up to its InhibitDelayMaxSec) with the
+// suspend until it is closed, i.e. until the lighting is off.
+fn take_sleep_lock(manager: &Proxy) -> Option<OwnedFd> {
+    let what = ("sleep", "lwl-x6-keyboard", "Switching the keyboard lighting off", "delay");
+    match manager.call("Inhibit", &what) {
+        Ok(fd) => Some(fd),
+        Err(e) => {
+            eprintln!("Error: could not take a sleep delay lock: {}", e);
+            None
+        }
+    }
+}
+
+/// Follows logind's `PrepareForSleep` signal and the `LidClosed` property from
+/// background threads.
+pub fn spawn_power_watcher(backend: Arc<dyn LedBackend>, config: SharedConfig) {
+    let connection = match logind_connection() {
+        Ok(connection) => connection,
+        Err(e) => {
+            eprintln!("Error: could not reach logind, lighting stays on during suspend: {}", e);
+            return;
+        }
+    };
+    let state = Arc::new(Mutex::new(PowerState::default()));
+
+    let backend_for_sleep = Arc::clone(&backend);
+    let config_for_sleep = Arc::clone(&config);
+    let state_for_sleep = Arc::clone(&state);
+    let connection_for_sleep = connection.clone();
+    thread::spawn(move || {
+        let result = (|| -> zbus::Result<()> {
+            let manager = logind_proxy(&connection_for_sleep, MANAGER_PATH, MANAGER_INTERFACE)?;
+            let signals = manager.receive_signal("PrepareForSleep")?;
+            let mut lock = take_sleep_lock(&manager);
+            for signal in signals {
+                let going_to_sleep: bool = signal.body().deserialize()?;
+                let backend = backend_for_sleep.as_ref();
+                update(backend, &config_for_sleep, &state_for_sleep, |s| s.sleeping = going_to_sleep);
+                if going_to_sleep {
+                    // Lighting is off, let the suspend go ahead.
+                    drop(lock.take());
+                } else {
+                    lock = take_sleep_lock(&manager);
+                    // Until then a reset by the controller is not a brightness
+                    // change made with the Fn keys.
+                    set_dimmed(true);
+                    thread::sleep(RESUME_SETTLE);
+                    let state = state_for_sleep.lock().unwrap();
+                    if state.lit() {
+                        switch(backend, &config_for_sleep, true);
+                    }
+                }
+            }
+            Ok(())
+        })();
+        if let Err(e) = result {
+            eprintln!("Error: listening for suspend: {}", e);
+        }
+    });
+
+    thread::spawn(move || {
+        let manager = match logind_proxy(&connection, MANAGER_PATH, MANAGER_INTERFACE) {
+            Ok(manager) => manager,
+            Err(e) => {
+                eprintln!("Error: watching the lid: {}", e);
+                return;
+            }
+        };
+        loop {
+            match manager.get_property::<bool>("LidClosed") {
+                Ok(closed) => update(backend.as_ref(), &config, &state, |s| s.lid_closed = closed),
+                Err(e) => {
+                    eprintln!("Error: watching the lid: {}", e);
+                    return;
+                }
+            }
+            thread::sleep(LID_POLL);
+        }
+    });
+}
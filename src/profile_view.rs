@@ -0,0 +1,128 @@
+use crate::backend::LedBackend;
+use crate::config::Config;
+use crate::leds::apply_lighting;
+use crate::persist::{update_config, SharedConfig};
+use gtk4::prelude::*;
+use gtk4::{Box, Button, DropDown, Entry, Label, Orientation, StringList};
+use std::cell::Cell;
+use std::rc::Rc;
+use std::sync::{mpsc, Arc};
+use std::thread;
+
+fn reload_names(list: &StringList, dropdown: &DropDown, names: &[String], selected: Option<&str>) {
+    let names: Vec<&str> = names.iter().map(String::as_str).collect();
+    list.splice(0, list.n_items(), &names);
+    let position = selected
+        .and_then(|s| names.iter().position(|n| *n == s))
+        .map_or(gtk4::INVALID_LIST_POSITION, |i| i as u32);
+    dropdown.set_selected(position);
+}
+
+/// Profile dropdown with save-as and delete. Picking a profile applies it to the
+/// hardware and hands the new state to `on_switch` so the other controls follow.
+pub fn profile_bar(
+    backend: Arc<dyn LedBackend>,
+    config: SharedConfig,
+    tx_persist: mpsc::SyncSender<()>,
+    on_switch: impl Fn(&Config) + 'static,
+) -> Box {
+    let bar = Box::new(Orientation::Horizontal, 10);
+    bar.append(&Label::new(Some("Profile:")));
+
+    let list = StringList::new(&[]);
+    let dropdown = DropDown::builder().model(&list).build();
+    // Refilling the list moves the selection, which must not switch profiles.
+    let reloading = Rc::new(Cell::new(true));
+    reload_names(&list, &dropdown, &config.lock().unwrap().profile_names(), None);
+    reloading.set(false);
+    bar.append(&dropdown);
+
+    let name_entry = Entry::builder().placeholder_text("Profile name").build();
+    bar.append(&name_entry);
+    let save_btn = Button::with_label("Save as");
+    bar.append(&save_btn);
+    let delete_btn = Button::with_label("Delete");
+    bar.append(&delete_btn);
+
+    let config_for_switch = Arc::clone(&config);
+    let tx_for_switch = tx_persist.clone();
+    let reloading_for_switch = Rc::clone(&reloading);
+    dropdown.connect_selected_notify(move |dd| {
+        if reloading_for_switch.get() {
+            return;
+        }
+        let Some(name) = dd
+            .selected_item()
+            .and_downcast::<gtk4::StringObject>()
+            .map(|s| s.string().to_string())
+        else {
+            return;
+        };
+        let switched = update_config(&config_for_switch, &tx_for_switch, |c| {
+            c.activate_profile(&name)?;
+            Ok(c.clone())
+        });
+        match switched {
+            Ok(snapshot) => {
+                on_switch(&snapshot);
+                let backend = Arc::clone(&backend);
+                thread::spawn(move || {
+                    let lightbar = snapshot.lightbar.as_ref();
+                    if let Err(e) =
+                        apply_lighting(backend.as_ref(), &snapshot.keyboard, lightbar, &snapshot.options)
+                    {
+                        eprintln!("Error: {}", e);
+                    }
+                });
+            }
+            Err(e) => eprintln!("Error: {}", e),
+        }
+    });
+
+    let config_for_save = Arc::clone(&config);
+    let tx_for_save = tx_persist.clone();
+    let reloading_for_save = Rc::clone(&reloading);
+    let list_for_save = list.clone();
+    let dropdown_for_save = dropdown.clone();
+    save_btn.connect_clicked(move |_| {
+        let name = name_entry.text().trim().to_string();
+        let saved = update_config(&config_for_save, &tx_for_save, |c| {
+            c.save_profile(&name)?;
+            Ok(c.profile_names())
+        });
+        match saved {
+            Ok(names) => {
+                reloading_for_save.set(true);
+                reload_names(&list_for_save, &dropdown_for_save, &names, Some(&name));
+                reloading_for_save.set(false);
+                name_entry.set_text("");
+            }
+            Err(e) => eprintln!("Error: {}", e),
+        }
+    });
+
+    let dropdown_for_delete = dropdown.clone();
+    delete_btn.connect_clicked(move |_| {
+        let Some(name) = dropdown_for_delete
+            .selected_item()
+            .and_downcast::<gtk4::StringObject>()
+            .map(|s| s.string().to_string())
+        else {
+            return;
+        };
+        let deleted = update_config(&config, &tx_persist, |c| {
+            c.delete_profile(&name)?;
+            Ok(c.profile_names())
+        });
+        match deleted {
+            Ok(names) => {
+                reloading.set(true);
+                reload_names(&list, &dropdown_for_delete, &names, None);
+                reloading.set(false);
+            }
+            Err(e) => eprintln!("Error: {}", e),
+        }
+    });
+
+    bar
+}
@@ -0,0 +1,419 @@
+// Profiles switched at set times of day, by clock or by the sun. Sunrise and
+// sunset are computed offline from `[location]`. Times are local and go through
+// the C library, so daylight saving time and time zone changes are followed.
+// The schedule is worked out again from the wall clock on every poll, which
+// also catches up on what came due during suspend: the entry whose time came
+// last is the one in effect.
+
+use crate::backend::LedBackend;
+use crate::config::{Config, Location, ScheduleEntry};
+use crate::idle::unless_dimmed;
+use crate::leds::apply_lighting;
+use crate::persist::{update_config, SharedConfig};
+use std::f64::consts::PI;
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub const SCHEDULE_POLL: Duration = Duration::from_secs(15);
+// Offsets from sunrise or sunset beyond this are more likely a typo.
+const MAX_OFFSET_MINUTES: i32 = 12 * 60;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Anchor {
+    /// Minutes after midnight.
+    Clock(u32),
+    Sunrise,
+    Sunset,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ScheduleTime {
+    pub anchor: Anchor,
+    pub offset_minutes: i32,
+}
+
+// "+30m", "-1h", "+1h30m".
+fn parse_offset(s: &str) -> Option<i32> {
+    let (sign, body) = match s.chars().next()? {
+        '+' => (1, &s[1..]),
+        '-' => (-1, &s[1..]),
+        _ => return None,
+    };
+    let mut minutes = 0;
+    let mut digits = String::new();
+    for c in body.trim().chars() {
+        match c {
+            '0'..='9' => digits.push(c),
+            'h' | 'm' => {
+                let n: i32 = digits.parse().ok()?;
+                minutes += if c == 'h' { n * 60 } else { n };
+                digits.clear();
+            }
+            _ => return None,
+        }
+    }
+    (digits.is_empty() && minutes <= MAX_OFFSET_MINUTES).then_some(sign * minutes)
+}
+
+fn parse_clock(s: &str) -> Option<u32> {
+    let (h, m) = s.split_once(':')?;
+    if !(1..=2).contains(&h.len()) || m.len() != 2 {
+        return None;
+    }
+    let (h, m): (u32, u32) = (h.parse().ok()?, m.parse().ok()?);
+    (h < 24 && m < 60).then_some(h * 60 + m)
+}
+
+impl ScheduleTime {
+    /// `HH:MM`, or `sunrise`/`sunset` optionally followed by an offset such as
+    /// `+30m` or `-1h`.
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let lower = s.trim().to_ascii_lowercase();
+        let sun = [("sunrise", Anchor::Sunrise), ("sunset", Anchor::Sunset)]
+            .into_iter()
+            .find_map(|(name, anchor)| Some((anchor, lower.strip_prefix(name)?.trim())));
+        let time = match sun {
+            Some((anchor, "")) => Some(ScheduleTime { anchor, offset_minutes: 0 }),
+            Some((anchor, offset)) => {
+                parse_offset(offset).map(|offset_minutes| ScheduleTime { anchor, offset_minutes })
+            }
+            None => parse_clock(&lower).map(|m| ScheduleTime { anchor: Anchor::Clock(m), offset_minutes: 0 }),
+        };
+        time.ok_or_else(|| {
+            format!("invalid time '{}', expected HH:MM, sunrise or sunset (e.g. sunset-30m)", s)
+        })
+    }
+
+    pub fn needs_location(&self) -> bool {
+        !matches!(self.anchor, Anchor::Clock(_))
+    }
+
+    /// When it comes on local date `date`, as a Unix time. `None` when the sun
+    /// doesn't rise or set that day.
+    pub fn on(&self, date: Date, location: Option<Location>) -> Option<i64> {
+        let base = match self.anchor {
+            Anchor::Clock(minutes) => local_time(date, minutes),
+            Anchor::Sunrise => sun_times(date, location?)?.0,
+            Anchor::Sunset => sun_times(date, location?)?.1,
+        };
+        Some(base + i64::from(self.offset_minutes) * 60)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Date {
+    pub year: i32,
+    pub month: u32,
+    pub day: u32,
+}
+
+impl Date {
+    // Days since 1970-01-01 (Howard Hinnant's days_from_civil).
+    fn days(self) -> i64 {
+        let y = i64::from(self.year) - i64::from(self.month <= 2);
+        let era = y.div_euclid(400);
+        let yoe = y - era * 400;
+        let m = i64::from(self.month);
+        let doy = (153 * (m + if m > 2 { -3 } else { 9 }) + 2) / 5 + i64::from(self.day) - 1;
+        let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+        era * 146097 + doe - 719468
+    }
+
+    fn from_days(days: i64) -> Self {
+        let z = days + 719468;
+        let era = z.div_euclid(146097);
+        let doe = z - era * 146097;
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+        Date { year: (yoe + era * 400 + i64::from(month <= 2)) as i32, month, day }
+    }
+
+    pub fn add_days(self, n: i64) -> Self {
+        Date::from_days(self.days() + n)
+    }
+}
+
+pub fn unix_now() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs() as i64)
+}
+
+// Not in the libc crate.
+unsafe extern "C" {
+    fn tzset();
+}
+
+fn local_tm(t: i64) -> libc::tm {
+    let t = t as libc::time_t;
+    // SAFETY: both pointers are valid for the call; tzset picks up a changed
+    // time zone, which localtime_r isn't required to do.
+    unsafe {
+        let mut tm: libc::tm = std::mem::zeroed();
+        tzset();
+        libc::localtime_r(&t, &mut tm);
+        tm
+    }
+}
+
+pub fn local_date(t: i64) -> Date {
+    let tm = local_tm(t);
+    Date { year: tm.tm_year + 1900, month: tm.tm_mon as u32 + 1, day: tm.tm_mday as u32 }
+}
+
+/// `minutes` after midnight on local date `date`, as a Unix time. A time that
+/// the switch to daylight saving time skips comes an hour late.
+fn local_time(date: Date, minutes: u32) -> i64 {
+    // SAFETY: `tm` is a valid, fully initialized struct for mktime to normalize.
+    unsafe {
+        let mut tm: libc::tm = std::mem::zeroed();
+        tm.tm_year = date.year - 1900;
+        tm.tm_mon = date.month as i32 - 1;
+        tm.tm_mday = date.day as i32;
+        tm.tm_hour = (minutes / 60) as i32;
+        tm.tm_min = (minutes % 60) as i32;
+        // Let mktime work out whether daylight saving time is in effect.
+        tm.tm_isdst = -1;
+        libc::mktime(&mut tm) as i64
+    }
+}
+
+/// `YYYY-MM-DD HH:MM` in local time.
+pub fn format_local(t: i64) -> String {
+    let tm = local_tm(t);
+    format!(
+        "{}-{:02}-{:02} {:02}:{:02}",
+        tm.tm_year + 1900,
+        tm.tm_mon + 1,
+        tm.tm_mday,
+        tm.tm_hour,
+        tm.tm_min
+    )
+}
+
+/// `HH:MM` in local time.
+pub fn format_local_time(t: i64) -> String {
+    let tm = local_tm(t);
+    format!("{:02}:{:02}", tm.tm_hour, tm.tm_min)
+}
+
+/// Sunrise and sunset on `date` at `location` as Unix times, or `None` on a
+/// polar day or night. This is the sunrise equation, good to a minute or two.
+pub fn sun_times(date: Date, location: Location) -> Option<(i64, i64)> {
+    const J2000: f64 = 2451545.0;
+    const UNIX_EPOCH_JD: f64 = 2440587.5;
+    let rad = PI / 180.0;
+    // Days from J2000 to noon of `date`, then to local solar noon.
+    let n = date.days() as f64 + UNIX_EPOCH_JD + 0.5 - J2000;
+    let mean_noon = n + 0.0008 - location.longitude / 360.0;
+    let anomaly = (357.5291 + 0.98560028 * mean_noon).rem_euclid(360.0);
+    let m = anomaly * rad;
+    let center = 1.9148 * m.sin() + 0.0200 * (2.0 * m).sin() + 0.0003 * (3.0 * m).sin();
+    let ecliptic = (anomaly + center + 180.0 + 102.9372).rem_euclid(360.0) * rad;
+    let transit = J2000 + mean_noon + 0.0053 * m.sin() - 0.0069 * (2.0 * ecliptic).sin();
+    let declination = (ecliptic.sin() * (23.4397 * rad).sin()).asin();
+    let latitude = location.latitude * rad;
+    // -0.833° accounts for refraction and the size of the sun's disk.
+    let cos_hour = ((-0.833 * rad).sin() - latitude.sin() * declination.sin())
+        / (latitude.cos() * declination.cos());
+    if !(-1.0..=1.0).contains(&cos_hour) {
+        return None;
+    }
+    let half_day = cos_hour.acos() / rad / 360.0;
+    let unix = |jd: f64| ((jd - UNIX_EPOCH_JD) * 86400.0).round() as i64;
+    Some((unix(transit - half_day), unix(transit + half_day)))
+}
+
+/// A schedule entry coming due at a particular time.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Occurrence {
+    pub time: i64,
+    pub entry: usize,
+}
+
+/// The occurrence in effect at `now` (the last one at or before it) and the
+/// next one after it.
+pub fn due_and_next(
+    entries: &[ScheduleEntry],
+    location: Option<Location>,
+    now: i64,
+) -> (Option<Occurrence>, Option<Occurrence>) {
+    let today = local_date(now);
+    let mut occurrences: Vec<Occurrence> = (-2..=1)
+        .flat_map(|day| {
+            let date = today.add_days(day);
+            entries.iter().enumerate().filter_map(move |(entry, e)| {
+                let time = ScheduleTime::parse(&e.at).ok()?.on(date, location)?;
+                Some(Occurrence { time, entry })
+            })
+        })
+        .collect();
+    occurrences.sort_by_key(|o| (o.time, o.entry));
+    let due = occurrences.iter().rev().find(|o| o.time <= now).copied();
+    let next = occurrences.iter().find(|o| o.time > now).copied();
+    (due, next)
+}
+
+fn activate(backend: &dyn LedBackend, config: &SharedConfig, tx_persist: &mpsc::SyncSender<()>, name: &str) -> bool {
+    let snapshot = match update_config(config, tx_persist, |c| {
+        c.activate_profile(name)?;
+        Ok(c.clone())
+    }) {
+        Ok(snapshot) => snapshot,
+        Err(e) => {
+            eprintln!("Error: schedule: {}", e);
+            return false;
+        }
+    };
+    println!("Schedule: switched to profile '{}'", name);
+    let apply = || apply_lighting(backend, &snapshot.keyboard, snapshot.lightbar.as_ref(), &snapshot.options);
+    if let Some(Err(e)) = unless_dimmed(apply) {
+        eprintln!("Error: {}", e);
+    }
+    true
+}
+
+/// Activates the profiles in the `[[schedule]]` of `config` as they come due,
+/// starting with the one in effect now, and calls `on_change` after each.
+pub fn spawn_scheduler(
+    backend: Arc<dyn LedBackend>,
+    config: SharedConfig,
+    tx_persist: mpsc::SyncSender<()>,
+    on_change: impl Fn() + Send + 'static,
+) {
+    thread::spawn(move || {
+        let mut applied: Option<Occurrence> = None;
+        loop {
+            let (entries, location) = {
+                let c: &Config = &config.lock().unwrap();
+                (c.schedule.clone(), c.location)
+            };
+            let now = unix_now();
+            let (due, next) = due_and_next(&entries, location, now);
+            if due != applied {
+                if let Some(due) = due
+                    && activate(backend.as_ref(), &config, &tx_persist, &entries[due.entry].profile)
+                {
+                    on_change();
+                }
+                applied = due;
+            }
+            // Sleeping doesn't count time spent suspended, so never sleep long.
+            let until_next = next.map_or(SCHEDULE_POLL, |n| Duration::from_secs((n.time - now).max(1) as u64));
+            thread::sleep(until_next.min(SCHEDULE_POLL));
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AMSTERDAM: Location = Location { latitude: 52.37, longitude: 4.90 };
+
+    fn entry(at: &str) -> ScheduleEntry {
+        ScheduleEntry { at: at.to_string(), profile: at.to_string() }
+    }
+
+    fn date(year: i32, month: u32, day: u32) -> Date {
+        Date { year, month, day }
+    }
+
+    #[test]
+    fn parses_offsets() {
+        assert_eq!(parse_offset("+30m"), Some(30));
+        assert_eq!(parse_offset("-1h"), Some(-60));
+        assert_eq!(parse_offset("+1h30m"), Some(90));
+        assert_eq!(parse_offset("+ 45m"), Some(45));
+        assert_eq!(parse_offset("+12h"), Some(720));
+        for bad in ["", "30m", "+30", "+1x", "+13h", "+h", "+1.5h"] {
+            assert_eq!(parse_offset(bad), None, "{:?} parsed", bad);
+        }
+    }
+
+    #[test]
+    fn parses_schedule_times() {
+        let at = |anchor, offset_minutes| Ok(ScheduleTime { anchor, offset_minutes });
+        assert_eq!(ScheduleTime::parse("07:05"), at(Anchor::Clock(425), 0));
+        assert_eq!(ScheduleTime::parse("7:05"), at(Anchor::Clock(425), 0));
+        assert_eq!(ScheduleTime::parse(" Sunset+30m "), at(Anchor::Sunset, 30));
+        assert_eq!(ScheduleTime::parse("sunrise -1h"), at(Anchor::Sunrise, -60));
+        for bad in ["24:00", "12:60", "12:5", "noon", "sunrisefoo", "sunset+30"] {
+            assert!(ScheduleTime::parse(bad).is_err(), "{:?} parsed", bad);
+        }
+        assert!(ScheduleTime::parse("sunset").unwrap().needs_location());
+        assert!(!ScheduleTime::parse("22:00").unwrap().needs_location());
+    }
+
+    #[test]
+    fn counts_days_from_the_epoch() {
+        assert_eq!(date(1970, 1, 1).days(), 0);
+        assert_eq!(date(1969, 12, 31).days(), -1);
+        assert_eq!(date(2000, 3, 1).days(), 11017);
+        assert_eq!(date(2024, 2, 29).days(), 19782);
+        for days in (-800_000..800_000).step_by(997) {
+            assert_eq!(Date::from_days(days).days(), days);
+        }
+        assert_eq!(date(2024, 2, 28).add_days(1), date(2024, 2, 29));
+        assert_eq!(date(2023, 2, 28).add_days(1), date(2023, 3, 1));
+        assert_eq!(date(2024, 1, 1).add_days(-1), date(2023, 12, 31));
+    }
+
+    #[test]
+    fn formats_local_times() {
+        let today = local_date(unix_now());
+        let t = local_time(today, 7 * 60 + 5);
+        assert_eq!(format_local_time(t), "07:05");
+        assert_eq!(
+            format_local(t),
+            format!("{}-{:02}-{:02} 07:05", today.year, today.month, today.day)
+        );
+    }
+
+    #[test]
+    fn computes_sunrise_and_sunset() {
+        // Amsterdam on the summer solstice of 2024: 03:18 and 20:06 UTC.
+        let midnight = date(2024, 6, 21).days() * 86400;
+        let (rise, set) = sun_times(date(2024, 6, 21), AMSTERDAM).unwrap();
+        assert!((rise - (midnight + 3 * 3600 + 18 * 60)).abs() <= 180, "sunrise at {}", rise - midnight);
+        assert!((set - (midnight + 20 * 3600 + 6 * 60)).abs() <= 180, "sunset at {}", set - midnight);
+        // Midnight sun and polar night in Tromsø.
+        let tromso = Location { latitude: 69.65, longitude: 18.96 };
+        assert_eq!(sun_times(date(2024, 6, 21), tromso), None);
+        assert_eq!(sun_times(date(2024, 12, 21), tromso), None);
+        assert!(sun_times(date(2024, 3, 20), tromso).is_some());
+    }
+
+    #[test]
+    fn finds_the_entry_in_effect_and_the_next() {
+        let entries = [entry("08:00"), entry("20:00")];
+        let today = local_date(unix_now());
+        let at = |date: Date, minutes| local_time(date, minutes);
+        let occurrence = |time, entry| Some(Occurrence { time, entry });
+
+        let (due, next) = due_and_next(&entries, None, at(today, 12 * 60));
+        assert_eq!(due, occurrence(at(today, 8 * 60), 0));
+        assert_eq!(next, occurrence(at(today, 20 * 60), 1));
+
+        // Before the first entry of the day, yesterday's last one holds.
+        let (due, next) = due_and_next(&entries, None, at(today, 7 * 60));
+        assert_eq!(due, occurrence(at(today.add_days(-1), 20 * 60), 1));
+        assert_eq!(next, occurrence(at(today, 8 * 60), 0));
+
+        // An entry comes due at its exact time.
+        let (due, _) = due_and_next(&entries, None, at(today, 20 * 60));
+        assert_eq!(due.map(|o| o.entry), Some(1));
+        assert_eq!(due_and_next(&[], None, at(today, 0)), (None, None));
+    }
+
+    #[test]
+    fn sun_entries_need_a_location() {
+        let entries = [entry("sunset"), entry("06:00")];
+        let (due, next) = due_and_next(&entries, None, unix_now());
+        assert_eq!((due.unwrap().entry, next.unwrap().entry), (1, 1));
+        let (_, next) = due_and_next(&entries[..1], Some(AMSTERDAM), unix_now());
+        assert!(next.is_some());
+    }
+}
@@ -0,0 +1,58 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Mutex, MutexGuard, OnceLock};
+
+static NEXT: AtomicU32 = AtomicU32::new(0);
+
+/// A fresh directory below the system temp dir, removed again on drop.
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    pub fn new(name: &str) -> Self {
+        let n = NEXT.fetch_add(1, Ordering::Relaxed);
+        let path = env::temp_dir().join(format!("rusty-kb-{}-{}-{}", name, process::id(), n));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        TempDir { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn join(&self, name: &str) -> PathBuf {
+        self.path.join(name)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+/// Points `XDG_RUNTIME_DIR` at an empty directory of its own and holds it until
+/// the guard is dropped. Tests that use the runtime markers (or read them
+/// through code under test) take this, so they don't see each other's markers
+/// or the real ones.
+pub fn runtime_dir() -> MutexGuard<'static, TempDir> {
+    static RUNTIME_DIR: OnceLock<Mutex<TempDir>> = OnceLock::new();
+    let dir = RUNTIME_DIR.get_or_init(|| {
+        let dir = TempDir::new("runtime");
+        // SAFETY: set once, before any test reads it through `runtime_path`;
+        // the other tests don't read XDG_RUNTIME_DIR.
+        unsafe { env::set_var("XDG_RUNTIME_DIR", dir.path()) };
+        Mutex::new(dir)
+    });
+    // A test that failed while holding it leaves nothing the next one can't clear.
+    let dir = dir.lock().unwrap_or_else(|e| e.into_inner());
+    for entry in fs::read_dir(dir.path()).unwrap().flatten() {
+        let _ = fs::remove_file(entry.path());
+    }
+    dir
+}
@@ -0,0 +1,201 @@
+use crate::backend::LedBackend;
+use crate::leds::{unpack_rgb, write_brightness_all, write_color, write_color_all};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicI32, AtomicU32, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+
+pub fn notify_coalescer(tx: &mpsc::SyncSender<()>) {
+    let _ = tx.try_send(());
+}
+
+// The worker threads are detached, so a value handed to them just before the
+// application quits may never be written. Each worker registers what it still
+// owes here, and `flush_workers` does that on the calling thread.
+type Flush = Box<dyn Fn() + Send>;
+
+static FLUSHES: Mutex<Vec<Flush>> = Mutex::new(Vec::new());
+
+pub fn on_shutdown(flush: impl Fn() + Send + 'static) {
+    FLUSHES.lock().unwrap().push(Box::new(flush));
+}
+
+/// Writes and saves whatever the workers haven't yet. Call it once, right
+/// before the process exits.
+pub fn flush_workers() {
+    for flush in FLUSHES.lock().unwrap().drain(..) {
+        flush();
+    }
+}
+
+// A value applied by a worker thread, remembered so that shutdown only writes
+// it again when it changed since. The lock keeps the thread and a shutdown
+// flush from overtaking each other with different values.
+struct Coalesced<T, R, A> {
+    read: R,
+    apply: A,
+    applied: Mutex<T>,
+}
+
+impl<T: Copy + PartialEq, R: Fn() -> T, A: Fn(T)> Coalesced<T, R, A> {
+    fn run(&self, only_if_changed: bool) {
+        let mut applied = self.applied.lock().unwrap();
+        let value = (self.read)();
+        if only_if_changed && value == *applied {
+            return;
+        }
+        (self.apply)(value);
+        *applied = value;
+    }
+}
+
+// Coalescing worker: only apply the most recent value received (no sender-side backlog)
+fn spawn_coalescing<T, R, A>(read: R, apply: A) -> mpsc::SyncSender<()>
+where
+    T: Copy + PartialEq + Send + 'static,
+    R: Fn() -> T + Send + Sync + 'static,
+    A: Fn(T) + Send + Sync + 'static,
+{
+    let applied = Mutex::new(read());
+    let worker = Arc::new(Coalesced { read, apply, applied });
+    let worker_for_thread = Arc::clone(&worker);
+    let (tx, rx) = mpsc::sync_channel::<()>(1);
+    thread::spawn(move || {
+        while rx.recv().is_ok() {
+            worker_for_thread.run(false);
+        }
+    });
+    on_shutdown(move || worker.run(true));
+    tx
+}
+
+/// The LEDs a worker writes to, swapped out when LEDs come and go.
+pub type Targets = Arc<Mutex<Vec<PathBuf>>>;
+
+pub fn shared_targets(paths: Vec<PathBuf>) -> Targets {
+    Arc::new(Mutex::new(paths))
+}
+
+pub fn spawn_kb_color_worker(
+    backend: Arc<dyn LedBackend>,
+    targets: Targets,
+) -> (Arc<AtomicU32>, mpsc::SyncSender<()>) {
+    let latest = Arc::new(AtomicU32::new(0));
+    let latest_for_worker = Arc::clone(&latest);
+    let tx = spawn_coalescing(
+        move || latest_for_worker.load(Ordering::Relaxed),
+        move |v| {
+            let (r, g, b) = unpack_rgb(v);
+            let paths = targets.lock().unwrap().clone();
+            write_color_all(backend.as_ref(), &paths, r, g, b);
+        },
+    );
+    (latest, tx)
+}
+
+pub fn spawn_kb_brightness_worker(
+    backend: Arc<dyn LedBackend>,
+    targets: Targets,
+) -> (Arc<AtomicI32>, mpsc::SyncSender<()>) {
+    let latest = Arc::new(AtomicI32::new(0));
+    let latest_for_worker = Arc::clone(&latest);
+    let tx = spawn_coalescing(
+        move || latest_for_worker.load(Ordering::Relaxed),
+        move |v| {
+            let paths = targets.lock().unwrap().clone();
+            write_brightness_all(backend.as_ref(), &paths, v);
+        },
+    );
+    (latest, tx)
+}
+
+// The lightbar workers take `Targets` too (empty while there is no lightbar),
+// so a lightbar that shows up later is written without new workers.
+pub fn spawn_lb_color_worker(
+    backend: Arc<dyn LedBackend>,
+    targets: Targets,
+) -> (Arc<AtomicU32>, mpsc::SyncSender<()>) {
+    let latest = Arc::new(AtomicU32::new(0));
+    let latest_for_worker = Arc::clone(&latest);
+    let tx = spawn_coalescing(
+        move || latest_for_worker.load(Ordering::Relaxed),
+        move |v| {
+            let (r, g, b) = unpack_rgb(v);
+            let paths = targets.lock().unwrap().clone();
+            write_color_all(backend.as_ref(), &paths, r, g, b);
+        },
+    );
+    (latest, tx)
+}
+
+pub fn spawn_lb_brightness_worker(
+    backend: Arc<dyn LedBackend>,
+    targets: Targets,
+) -> (Arc<AtomicI32>, mpsc::SyncSender<()>) {
+    let latest = Arc::new(AtomicI32::new(0));
+    let latest_for_worker = Arc::clone(&latest);
+    let tx = spawn_coalescing(
+        move || latest_for_worker.load(Ordering::Relaxed),
+        move |v| {
+            let paths = targets.lock().unwrap().clone();
+            write_brightness_all(backend.as_ref(), &paths, v);
+        },
+    );
+    (latest, tx)
+}
+
+// Per-key variant: callers insert into the pending map, the worker drains it.
+pub fn spawn_key_color_worker(
+    backend: Arc<dyn LedBackend>,
+) -> (Arc<Mutex<BTreeMap<PathBuf, u32>>>, mpsc::SyncSender<()>) {
+    let pending = Arc::new(Mutex::new(BTreeMap::<PathBuf, u32>::new()));
+    // Held while writing a batch, so a shutdown flush can't be overtaken by an
+    // older batch still being written.
+    let writing = Arc::new(Mutex::new(()));
+    let drain = {
+        let pending = Arc::clone(&pending);
+        move || {
+            let _writing = writing.lock().unwrap();
+            let batch = std::mem::take(&mut *pending.lock().unwrap());
+            for (path, v) in batch {
+                let (r, g, b) = unpack_rgb(v);
+                write_color(backend.as_ref(), &path, r, g, b);
+            }
+        }
+    };
+    let drain = Arc::new(drain);
+    let drain_for_thread = Arc::clone(&drain);
+    let (tx, rx) = mpsc::sync_channel::<()>(1);
+    thread::spawn(move || {
+        while rx.recv().is_ok() {
+            drain_for_thread();
+        }
+    });
+    on_shutdown(move || drain());
+    (pending, tx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shutdown_only_applies_values_that_changed() {
+        let latest = AtomicI32::new(1);
+        let applied = Mutex::new(Vec::new());
+        let worker = Coalesced {
+            read: || latest.load(Ordering::Relaxed),
+            apply: |v| applied.lock().unwrap().push(v),
+            applied: Mutex::new(1),
+        };
+        worker.run(true);
+        assert!(applied.lock().unwrap().is_empty());
+        latest.store(2, Ordering::Relaxed);
+        worker.run(true);
+        worker.run(true);
+        // The worker thread itself writes whatever it is woken for.
+        worker.run(false);
+        assert_eq!(*applied.lock().unwrap(), [2, 2]);
+    }
+}
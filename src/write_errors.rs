@@ -0,0 +1,217 @@
+// Failed LED writes, sorted by what the user can do about them. The workers
+// write from background threads, so the latest failure is kept here for the
+// window to pick up; the command line gets the remedy printed once per kind.
+
+use std::collections::BTreeSet;
+use std::fs;
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+// Owner group of the LED attributes, set up by the packages and the udev rule.
+pub const LED_GROUP: &str = "rustykb";
+pub const UDEV_RELOAD: &str = "sudo udevadm control --reload && sudo udevadm trigger --subsystem-match=leds";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum WriteErrorKind {
+    PermissionDenied,
+    Missing,
+    Io,
+    Other,
+}
+
+impl WriteErrorKind {
+    pub fn classify(e: &io::Error) -> Self {
+        match e.raw_os_error() {
+            Some(libc::EACCES | libc::EPERM) => WriteErrorKind::PermissionDenied,
+            Some(libc::ENOENT | libc::ENODEV | libc::ENXIO) => WriteErrorKind::Missing,
+            Some(libc::EIO) => WriteErrorKind::Io,
+            Some(_) => WriteErrorKind::Other,
+            None => match e.kind() {
+                io::ErrorKind::PermissionDenied => WriteErrorKind::PermissionDenied,
+                io::ErrorKind::NotFound => WriteErrorKind::Missing,
+                _ => WriteErrorKind::Other,
+            },
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct WriteError {
+    pub kind: WriteErrorKind,
+    pub file: PathBuf,
+    pub message: String,
+}
+
+// The numeric id of `name` and its listed members, from /etc/group.
+pub fn group_entry(name: &str) -> Option<(u32, Vec<String>)> {
+    let groups = fs::read_to_string("/etc/group").ok()?;
+    groups.lines().find_map(|line| {
+        let fields: Vec<&str> = line.split(':').collect();
+        if fields.len() < 4 || fields[0] != name {
+            return None;
+        }
+        let members = fields[3].split(',').filter(|m| !m.is_empty()).map(String::from).collect();
+        Some((fields[2].parse().ok()?, members))
+    })
+}
+
+// Groups this process runs with, from /proc/self/status.
+pub fn process_groups() -> Vec<u32> {
+    fs::read_to_string("/proc/self/status")
+        .ok()
+        .and_then(|status| {
+            let line = status.lines().find_map(|l| l.strip_prefix("Groups:"))?;
+            Some(line.split_whitespace().filter_map(|g| g.parse().ok()).collect())
+        })
+        .unwrap_or_default()
+}
+
+fn permission_remedy(file: &Path) -> String {
+    let user = std::env::var("USER").unwrap_or_default();
+    let file_gid = fs::metadata(file).ok().map(|m| m.gid());
+    permission_advice(group_entry(LED_GROUP), &process_groups(), &user, file_gid, file)
+}
+
+// Walks the setup the packages do: the group exists, the user is in it (and
+// this session knows), and the udev rule handed the file to it.
+fn permission_advice(
+    group: Option<(u32, Vec<String>)>,
+    process_groups: &[u32],
+    user: &str,
+    file_gid: Option<u32>,
+    file: &Path,
+) -> String {
+    let Some((gid, members)) = group else {
+        return format!(
+            "The '{}' group doesn't exist. Reinstall the package, or run: sudo groupadd -r {}",
+            LED_GROUP, LED_GROUP
+        );
+    };
+    if !process_groups.contains(&gid) {
+        if !user.is_empty() && members.iter().any(|m| m == user) {
+            return format!(
+                "You are in the '{}' group, but this session started before you were added. Log out and back in.",
+                LED_GROUP
+            );
+        }
+        return format!(
+            "Add yourself to the '{}' group, then log out and back in: sudo usermod -aG {} $USER",
+            LED_GROUP, LED_GROUP
+        );
+    }
+    if file_gid.is_some_and(|g| g != gid) {
+        return format!(
+            "The udev rule hasn't given {} to the '{}' group yet. Run: {}",
+            file.display(),
+            LED_GROUP,
+            UDEV_RELOAD
+        );
+    }
+    format!(
+        "{} isn't group-writable. Check /etc/udev/rules.d/99-rusty-kb.rules, then run: {}",
+        file.display(),
+        UDEV_RELOAD
+    )
+}
+
+impl WriteError {
+    /// What to do about it, in a sentence or two.
+    pub fn remedy(&self) -> String {
+        match self.kind {
+            WriteErrorKind::PermissionDenied => permission_remedy(&self.file),
+            WriteErrorKind::Missing => "The LED is gone, usually because the keyboard controller is \
+                re-enumerating after resume or a driver reload. It is picked up again when it comes back."
+                .to_string(),
+            WriteErrorKind::Io => "The keyboard controller didn't take the write. This can happen \
+                while it wakes up; if it keeps happening, reload the driver or reboot."
+                .to_string(),
+            WriteErrorKind::Other => "Check the kernel log (journalctl -k) for driver errors.".to_string(),
+        }
+    }
+}
+
+struct Errors {
+    latest: Option<WriteError>,
+    explained: BTreeSet<WriteErrorKind>,
+}
+
+static ERRORS: Mutex<Errors> = Mutex::new(Errors { latest: None, explained: BTreeSet::new() });
+
+/// Reports a failed write of `attr` on the LED at `led`.
+pub fn record_write_error(led: &Path, attr: &str, e: &io::Error) {
+    let error = WriteError {
+        kind: WriteErrorKind::classify(e),
+        file: led.join(attr),
+        message: e.to_string(),
+    };
+    let mut errors = ERRORS.lock().unwrap();
+    eprintln!("Error: writing {}: {}", error.file.display(), error.message);
+    if errors.explained.insert(error.kind) {
+        eprintln!("{}", error.remedy());
+    }
+    errors.latest = Some(error);
+}
+
+/// The most recent failure since the last call.
+pub fn take_write_error() -> Option<WriteError> {
+    ERRORS.lock().unwrap().latest.take()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classifies_errors_by_errno() {
+        let cases = [
+            (libc::EACCES, WriteErrorKind::PermissionDenied),
+            (libc::EPERM, WriteErrorKind::PermissionDenied),
+            (libc::ENOENT, WriteErrorKind::Missing),
+            (libc::ENODEV, WriteErrorKind::Missing),
+            (libc::ENXIO, WriteErrorKind::Missing),
+            (libc::EIO, WriteErrorKind::Io),
+            (libc::EBUSY, WriteErrorKind::Other),
+            (libc::EINVAL, WriteErrorKind::Other),
+        ];
+        for (errno, kind) in cases {
+            assert_eq!(WriteErrorKind::classify(&io::Error::from_raw_os_error(errno)), kind, "errno {}", errno);
+        }
+        // Errors of the in-memory backend carry no errno.
+        let cases = [
+            (io::ErrorKind::PermissionDenied, WriteErrorKind::PermissionDenied),
+            (io::ErrorKind::NotFound, WriteErrorKind::Missing),
+            (io::ErrorKind::InvalidData, WriteErrorKind::Other),
+        ];
+        for (error_kind, kind) in cases {
+            assert_eq!(WriteErrorKind::classify(&io::Error::new(error_kind, "test")), kind);
+        }
+    }
+
+    #[test]
+    fn advises_on_denied_writes() {
+        let file = Path::new("/sys/class/leds/rgb:kbd_backlight/brightness");
+        let group = |members: &[&str]| Some((990, members.iter().map(|m| m.to_string()).collect()));
+        let cases = [
+            (None, vec![], "", None, "group doesn't exist"),
+            (group(&[]), vec![100], "alice", None, "sudo usermod -aG rustykb"),
+            (group(&["bob"]), vec![100], "alice", None, "sudo usermod -aG rustykb"),
+            (group(&["alice"]), vec![100], "alice", None, "Log out and back in"),
+            (group(&["alice"]), vec![100, 990], "alice", Some(0), "udev rule hasn't given"),
+            (group(&["alice"]), vec![990], "alice", Some(990), "isn't group-writable"),
+            (group(&[]), vec![990], "", None, "isn't group-writable"),
+        ];
+        for (group, process_groups, user, file_gid, expected) in cases {
+            let advice = permission_advice(group, &process_groups, user, file_gid, file);
+            assert!(advice.contains(expected), "{:?} for {}", advice, expected);
+        }
+        let remedy = |errno| {
+            let e = io::Error::from_raw_os_error(errno);
+            WriteError { kind: WriteErrorKind::classify(&e), file: file.to_path_buf(), message: e.to_string() }.remedy()
+        };
+        assert!(remedy(libc::ENODEV).contains("re-enumerating"));
+        assert!(remedy(libc::EIO).contains("didn't take the write"));
+        assert!(remedy(libc::EINVAL).contains("journalctl -k"));
+    }
+}
@@ -0,0 +1,87 @@
+// Auto brightness against a fake IIO tree: the `ambient` command reports the
+// reading, and the daemon follows the sensor (also when it is reloaded) and
+// keeps the level for the next login when it is stopped.
+
+mod common;
+
+use common::{read_attr, wait_for, PrivateBus, Running, Sandbox};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+
+const CONFIG: &str = "version = 1\n\n[keyboard]\ncolor = \"#ffffff\"\nbrightness = 50\nmax_brightness = 50\n\n\
+                      [auto_brightness]\ncurve = [[0, 100], [100, 0]]\nsmoothing_seconds = 0\n";
+
+// A sensor reporting raw counts at half a lux each, like the X6's.
+fn add_sensor(sandbox: &Sandbox, name: &str, raw: u32) -> PathBuf {
+    let device = sandbox.root.join("iio").join(name);
+    fs::create_dir_all(&device).unwrap();
+    fs::write(device.join("name"), "als\n").unwrap();
+    fs::write(device.join("in_illuminance_scale"), "0.5\n").unwrap();
+    set_raw(&device, raw);
+    device
+}
+
+fn set_raw(device: &Path, raw: u32) {
+    fs::write(device.join("in_illuminance_raw"), format!("{}\n", raw)).unwrap();
+}
+
+#[test]
+fn ambient_prints_the_reading_and_level() {
+    let sandbox = Sandbox::new("ambient");
+    let output = sandbox.run(&["ambient"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("no ambient light sensor"));
+
+    add_sensor(&sandbox, "iio:device0", 50);
+    let output = sandbox.run(&["ambient"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success());
+    assert!(stdout.contains("(als): 25.0 lux"), "{}", stdout);
+    assert!(stdout.contains("Auto brightness is off"), "{}", stdout);
+
+    sandbox.write_config(CONFIG);
+    let stdout = String::from_utf8_lossy(&sandbox.run(&["ambient"]).stdout).into_owned();
+    assert!(stdout.contains("Auto brightness: 75%"), "{}", stdout);
+}
+
+#[test]
+fn daemon_follows_the_sensor() {
+    let sandbox = Sandbox::new("auto");
+    let led = sandbox.add_rgb_led("rgb:kbd_backlight", 50, 50);
+    let sensor = add_sensor(&sandbox, "iio:device0", 20);
+    sandbox.write_config(CONFIG);
+    let Some(bus) = PrivateBus::start(&sandbox, "session") else { return };
+    let daemon = Running(
+        sandbox
+            .command(&["daemon"])
+            .env("DBUS_SESSION_BUS_ADDRESS", &bus.address)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap(),
+    );
+
+    // 10 lux is 90% of the keyboard's maximum.
+    assert!(wait_for(Duration::from_secs(10), || read_attr(&led, "brightness") == "45"));
+    set_raw(&sensor, 120);
+    assert!(wait_for(Duration::from_secs(10), || read_attr(&led, "brightness") == "20"));
+    // Below the hysteresis nothing moves.
+    set_raw(&sensor, 124);
+    std::thread::sleep(Duration::from_secs(2));
+    assert_eq!(read_attr(&led, "brightness"), "20");
+    // The level isn't saved on every change, but when the daemon stops.
+    assert_eq!(sandbox.saved("keyboard", "brightness"), Some(toml::Value::from(50)));
+
+    // A sensor that comes back under another number (after a driver reload)
+    // is found again.
+    fs::remove_dir_all(&sensor).unwrap();
+    std::thread::sleep(Duration::from_secs(2));
+    add_sensor(&sandbox, "iio:device1", 160);
+    assert!(wait_for(Duration::from_secs(10), || read_attr(&led, "brightness") == "10"));
+
+    unsafe { libc::kill(daemon.0.id() as libc::pid_t, libc::SIGTERM) };
+    assert!(wait_for(Duration::from_secs(5), || sandbox.saved("keyboard", "brightness")
+        == Some(toml::Value::from(10))));
+}
@@ -0,0 +1,205 @@
+// The command line against a fake LED class: what it writes to the LEDs, what
+// it prints and what it saves for the next login.
+
+mod common;
+
+use common::{read_attr, Sandbox};
+use std::fs;
+
+fn stdout(output: &std::process::Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+fn assert_ok(output: &std::process::Output) {
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+}
+
+#[test]
+fn set_writes_the_keyboard_and_saves_it() {
+    let sandbox = Sandbox::new("set");
+    let led = sandbox.add_rgb_led("rgb:kbd_backlight", 0, 50);
+
+    assert_ok(&sandbox.run(&["set", "--color", "#0000ff", "--brightness", "40%"]));
+    assert_eq!(read_attr(&led, "multi_intensity"), "0 0 255");
+    assert_eq!(read_attr(&led, "brightness"), "20");
+    assert_eq!(sandbox.saved("keyboard", "color"), Some(toml::Value::from("#0000ff")));
+    assert_eq!(sandbox.saved("keyboard", "brightness"), Some(toml::Value::from(20)));
+    assert_eq!(sandbox.saved("keyboard", "max_brightness"), Some(toml::Value::from(50)));
+
+    let output = sandbox.run(&["get"]);
+    assert_ok(&output);
+    assert!(stdout(&output).contains("keyboard: color #0000ff brightness 20/50 (40%) (1 LEDs)"));
+    assert!(stdout(&output).contains("lightbar: not found"));
+}
+
+#[test]
+fn set_with_keys_writes_those_keys_only() {
+    let sandbox = Sandbox::new("keys");
+    let w = sandbox.add_rgb_led("rgb:kbd_backlight_66", 50, 50);
+    let q = sandbox.add_rgb_led("rgb:kbd_backlight_65", 50, 50);
+
+    assert_ok(&sandbox.run(&["set", "--keys", "w", "--color", "#ff0000"]));
+    assert_eq!(read_attr(&w, "multi_intensity"), "255 0 0");
+    assert_eq!(read_attr(&q, "multi_intensity"), "255 255 255");
+    // Key selections are one-offs, not remembered.
+    assert!(!sandbox.config_path().exists());
+}
+
+#[test]
+fn lightbar_off_and_on() {
+    let sandbox = Sandbox::new("power");
+    let kb = sandbox.add_rgb_led("rgb:kbd_backlight", 30, 50);
+    let lb = sandbox.add_rgb_led("rgb:lightbar", 60, 100);
+
+    assert_ok(&sandbox.run(&["lightbar", "set", "--color", "0,255,0", "--brightness", "25"]));
+    assert_eq!(read_attr(&lb, "multi_intensity"), "0 255 0");
+    assert_eq!(read_attr(&lb, "brightness"), "25");
+    assert_eq!(sandbox.saved("lightbar", "brightness"), Some(toml::Value::from(25)));
+    assert!(stdout(&sandbox.run(&["lightbar", "get"])).contains("color #00ff00 brightness 25/100"));
+
+    assert_ok(&sandbox.run(&["off"]));
+    assert_eq!((read_attr(&kb, "brightness"), read_attr(&lb, "brightness")), ("0".into(), "0".into()));
+    assert_ok(&sandbox.run(&["on", "--brightness", "50%"]));
+    assert_eq!((read_attr(&kb, "brightness"), read_attr(&lb, "brightness")), ("25".into(), "50".into()));
+}
+
+#[test]
+fn failures_exit_with_an_error() {
+    let sandbox = Sandbox::new("failures");
+    assert!(!sandbox.run(&["set", "--color", "#ffffff"]).status.success());
+
+    let led = sandbox.add_rgb_led("rgb:kbd_backlight", 0, 50);
+    assert!(!sandbox.run(&["set"]).status.success());
+    assert!(!sandbox.run(&["set", "--color", "purple"]).status.success());
+    assert!(!sandbox.run(&["set", "--brightness", "-1"]).status.success());
+    assert!(!sandbox.run(&["set", "--brightness", "120%"]).status.success());
+    assert!(!sandbox.run(&["lightbar", "get"]).status.success());
+    assert_eq!(sandbox.run(&["frobnicate"]).status.code(), Some(2));
+
+    // A write the LED refuses fails the command too.
+    fs::remove_file(led.join("brightness")).unwrap();
+    fs::create_dir(led.join("brightness")).unwrap();
+    let output = sandbox.run(&["set", "--brightness", "10"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("LED write failed"));
+}
+
+#[test]
+fn restore_applies_the_saved_state() {
+    let sandbox = Sandbox::new("restore");
+    let kb = sandbox.add_rgb_led("rgb:kbd_backlight", 0, 50);
+    let w = sandbox.add_rgb_led("rgb:kbd_backlight_66", 0, 50);
+    let lb = sandbox.add_rgb_led("rgb:lightbar", 0, 100);
+    fs::create_dir_all(sandbox.home().join(".rusty-kb")).unwrap();
+    fs::write(sandbox.state_path(), "18 52 86 25\n255 0 0 40\n").unwrap();
+    fs::write(sandbox.home().join(".rusty-kb/keys.txt"), "w 0 255 0\nhyper 1 2 3\n").unwrap();
+
+    assert_ok(&sandbox.run(&["restore"]));
+    assert_eq!(read_attr(&kb, "multi_intensity"), "18 52 86");
+    assert_eq!(read_attr(&kb, "brightness"), "25");
+    assert_eq!(read_attr(&w, "multi_intensity"), "0 255 0");
+    assert_eq!(read_attr(&lb, "multi_intensity"), "255 0 0");
+    assert_eq!(read_attr(&lb, "brightness"), "40");
+    // The old files are imported into the config.
+    assert!(sandbox.read_config().contains("w = \"#00ff00\""));
+}
+
+#[test]
+fn restore_reads_the_given_file() {
+    let sandbox = Sandbox::new("restore-file");
+    let kb = sandbox.add_rgb_led("rgb:kbd_backlight", 0, 50);
+    let colors = sandbox.root.join("colors.txt");
+    fs::write(&colors, "255 0 0 30\n0 0 0 0\n").unwrap();
+
+    assert_ok(&sandbox.run(&["restore", colors.to_str().unwrap()]));
+    assert_eq!(read_attr(&kb, "multi_intensity"), "255 0 0");
+    assert_eq!(read_attr(&kb, "brightness"), "30");
+
+    fs::write(&colors, "255 0 0\n").unwrap();
+    assert!(!sandbox.run(&["restore", colors.to_str().unwrap()]).status.success());
+}
+
+#[test]
+fn restore_rescales_to_the_leds_maximum() {
+    let sandbox = Sandbox::new("rescale");
+    let kb = sandbox.add_rgb_led("rgb:kbd_backlight", 0, 100);
+    let lb = sandbox.add_rgb_led("rgb:lightbar", 0, 100);
+    sandbox.write_config(
+        "version = 1\n\n[keyboard]\ncolor = \"#123456\"\nbrightness = 25\nmax_brightness = 50\n\n\
+         [lightbar]\ncolor = \"#ff0000\"\nbrightness = 1\nmax_brightness = 4\n",
+    );
+
+    assert_ok(&sandbox.run(&["restore"]));
+    assert_eq!(read_attr(&kb, "multi_intensity"), "18 52 86");
+    assert_eq!(read_attr(&kb, "brightness"), "50");
+    assert_eq!(read_attr(&lb, "multi_intensity"), "255 0 0");
+    assert_eq!(read_attr(&lb, "brightness"), "25");
+}
+
+#[test]
+fn restore_imports_old_state_files() {
+    let sandbox = Sandbox::new("legacy");
+    let kb = sandbox.add_rgb_led("rgb:kbd_backlight", 0, 100);
+    fs::create_dir_all(sandbox.home().join(".rusty-kb")).unwrap();
+    fs::write(sandbox.state_path(), "255 0 0 30\n0 0 0 0\n").unwrap();
+
+    assert_ok(&sandbox.run(&["restore"]));
+    // Version 1 brightness was out of the keyboard slider's 50.
+    assert_eq!(read_attr(&kb, "brightness"), "60");
+    assert_eq!(sandbox.saved("keyboard", "color"), Some(toml::Value::from("#ff0000")));
+    assert_eq!(sandbox.saved("keyboard", "brightness"), Some(toml::Value::from(30)));
+    assert_eq!(sandbox.saved("keyboard", "max_brightness"), Some(toml::Value::from(50)));
+}
+
+#[test]
+fn a_broken_config_is_not_overwritten() {
+    let sandbox = Sandbox::new("broken");
+    let led = sandbox.add_rgb_led("rgb:kbd_backlight", 0, 50);
+    sandbox.write_config("[keyboard\n");
+
+    let output = sandbox.run(&["set", "--color", "#00ff00"]);
+    assert!(!output.status.success());
+    // The LED still changes, only the save is refused.
+    assert_eq!(read_attr(&led, "multi_intensity"), "0 255 0");
+    assert_eq!(sandbox.read_config(), "[keyboard\n");
+}
+
+#[test]
+fn profiles_round_trip() {
+    let sandbox = Sandbox::new("profiles");
+    let led = sandbox.add_rgb_led("rgb:kbd_backlight", 0, 50);
+
+    assert_ok(&sandbox.run(&["set", "--color", "#ff0000", "--brightness", "5"]));
+    assert_ok(&sandbox.run(&["profile", "save", "night"]));
+    assert_ok(&sandbox.run(&["set", "--color", "#ffffff", "--brightness", "50"]));
+    assert!(stdout(&sandbox.run(&["profile", "list"])).contains("night: keyboard #ff0000 5/50"));
+
+    assert_ok(&sandbox.run(&["profile", "switch", "night"]));
+    assert_eq!(read_attr(&led, "multi_intensity"), "255 0 0");
+    assert_eq!(read_attr(&led, "brightness"), "5");
+    assert!(!sandbox.run(&["profile", "switch", "day"]).status.success());
+
+    assert_ok(&sandbox.run(&["profile", "delete", "night"]));
+    assert!(!sandbox.run(&["profile", "delete", "night"]).status.success());
+    assert!(!stdout(&sandbox.run(&["profile", "list"])).contains("night"));
+}
+
+#[test]
+fn presets_can_be_edited_and_imported() {
+    let sandbox = Sandbox::new("presets");
+    sandbox.add_rgb_led("rgb:kbd_backlight", 0, 50);
+    let palette = sandbox.root.join("palette.gpl");
+    fs::write(&palette, "GIMP Palette\n255 191 0 Amber\n0 255 0 Green\n").unwrap();
+
+    assert_ok(&sandbox.run(&["preset", "import", palette.to_str().unwrap()]));
+    assert_ok(&sandbox.run(&["preset", "move", "Amber", "1"]));
+    assert_ok(&sandbox.run(&["preset", "rename", "Blue", "Navy"]));
+    assert_ok(&sandbox.run(&["preset", "delete", "Red"]));
+    let list = stdout(&sandbox.run(&["preset", "list"]));
+    assert!(list.starts_with(" 1. #ffbf00 Amber\n 2. #0000ff Navy\n 3. #00ff00 Green\n"), "{}", list);
+
+    assert!(!sandbox.run(&["preset", "rename", "Navy", "Green"]).status.success());
+    assert!(!sandbox.run(&["preset", "add", "Teal", "#00zz00"]).status.success());
+    assert!(!sandbox.run(&["preset", "move", "Navy", "0"]).status.success());
+    assert!(sandbox.read_config().contains("name = \"Navy\""));
+}
@@ -0,0 +1,175 @@
+// Helpers for the integration tests: a throwaway directory with a fake LED
+// class, a light sensor tree, a home and a runtime dir, the binary run against
+// it, and a private session bus for the daemon.
+
+#![allow(dead_code)]
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Output, Stdio};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+static COUNTER: AtomicU32 = AtomicU32::new(0);
+
+/// A temporary tree, removed again when dropped.
+pub struct Sandbox {
+    pub root: PathBuf,
+}
+
+impl Sandbox {
+    pub fn new(name: &str) -> Self {
+        let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+        let root = std::env::temp_dir().join(format!("rusty-kb-test-{}-{}-{}", name, std::process::id(), n));
+        let _ = fs::remove_dir_all(&root);
+        for dir in ["leds", "home", "run", "iio"] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        Sandbox { root }
+    }
+
+    pub fn leds(&self) -> PathBuf {
+        self.root.join("leds")
+    }
+
+    pub fn home(&self) -> PathBuf {
+        self.root.join("home")
+    }
+
+    pub fn state_path(&self) -> PathBuf {
+        self.home().join(".rusty-kb/colors.txt")
+    }
+
+    pub fn config_path(&self) -> PathBuf {
+        self.home().join(".rusty-kb/config.toml")
+    }
+
+    /// Adds an RGB LED like the ones the ITE driver creates.
+    pub fn add_rgb_led(&self, name: &str, brightness: i32, max: i32) -> PathBuf {
+        let led = self.leds().join(name);
+        fs::create_dir_all(&led).unwrap();
+        fs::write(led.join("brightness"), format!("{}\n", brightness)).unwrap();
+        fs::write(led.join("max_brightness"), format!("{}\n", max)).unwrap();
+        fs::write(led.join("multi_index"), "red green blue\n").unwrap();
+        fs::write(led.join("multi_intensity"), "255 255 255\n").unwrap();
+        led
+    }
+
+    pub fn write_config(&self, contents: &str) {
+        fs::create_dir_all(self.home().join(".rusty-kb")).unwrap();
+        fs::write(self.config_path(), contents).unwrap();
+    }
+
+    pub fn read_config(&self) -> String {
+        fs::read_to_string(self.config_path()).unwrap_or_default()
+    }
+
+    /// `section.key` of the saved config, if it is there and parses.
+    pub fn saved(&self, section: &str, key: &str) -> Option<toml::Value> {
+        let config: toml::Table = self.read_config().parse().ok()?;
+        config.get(section)?.get(key).cloned()
+    }
+
+    /// The binary, pointed at this tree instead of the real system.
+    pub fn command(&self, args: &[&str]) -> Command {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_lwl-x6-keyboard"));
+        command
+            .args(args)
+            .env_clear()
+            .env("PATH", std::env::var_os("PATH").unwrap_or_default())
+            .env("RUSTY_KB_SYSFS_ROOT", self.leds())
+            .env("RUSTY_KB_IIO_ROOT", self.root.join("iio"))
+            .env("HOME", self.home())
+            .env("XDG_RUNTIME_DIR", self.root.join("run"))
+            .env("DBUS_SESSION_BUS_ADDRESS", "unix:path=/nonexistent")
+            .env("RUSTY_KB_LOGIND_BUS", "unix:path=/nonexistent");
+        command
+    }
+
+    pub fn run(&self, args: &[&str]) -> Output {
+        self.command(args).output().unwrap()
+    }
+}
+
+impl Drop for Sandbox {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.root);
+    }
+}
+
+pub fn read_attr(led: &Path, attr: &str) -> String {
+    fs::read_to_string(led.join(attr)).unwrap_or_default().trim().to_string()
+}
+
+/// Polls `check` until it holds, for at most `timeout`.
+pub fn wait_for(timeout: Duration, mut check: impl FnMut() -> bool) -> bool {
+    let deadline = Instant::now() + timeout;
+    while Instant::now() < deadline {
+        if check() {
+            return true;
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    check()
+}
+
+/// A child process killed when dropped.
+pub struct Running(pub Child);
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+/// A dbus-daemon of our own, listening in the sandbox.
+pub struct PrivateBus {
+    pub address: String,
+    _daemon: Running,
+}
+
+impl PrivateBus {
+    /// None (and the test skips) when there is no dbus-daemon to run.
+    pub fn start(sandbox: &Sandbox, name: &str) -> Option<Self> {
+        let socket = sandbox.root.join(format!("{}.socket", name));
+        let config = sandbox.root.join(format!("{}.conf", name));
+        fs::write(
+            &config,
+            format!(
+                "<!DOCTYPE busconfig PUBLIC \"-//freedesktop//DTD D-Bus Bus Configuration 1.0//EN\" \
+                 \"http://www.freedesktop.org/standards/dbus/1.0/busconfig.dtd\">\n\
+                 <busconfig><type>session</type><listen>unix:path={}</listen>\
+                 <policy context=\"default\"><allow send_destination=\"*\" eavesdrop=\"true\"/>\
+                 <allow eavesdrop=\"true\"/><allow own=\"*\"/></policy></busconfig>\n",
+                socket.display()
+            ),
+        )
+        .unwrap();
+        let daemon = match Command::new("dbus-daemon")
+            .arg(format!("--config-file={}", config.display()))
+            .arg("--nofork")
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+        {
+            Ok(child) => Running(child),
+            Err(e) => {
+                eprintln!("skipping, can't run dbus-daemon: {}", e);
+                return None;
+            }
+        };
+        if !wait_for(Duration::from_secs(5), || socket.exists()) {
+            eprintln!("skipping, dbus-daemon didn't come up");
+            return None;
+        }
+        Some(PrivateBus { address: format!("unix:path={}", socket.display()), _daemon: daemon })
+    }
+
+    pub fn connect(&self) -> zbus::blocking::Connection {
+        zbus::blocking::connection::Builder::address(self.address.as_str())
+            .and_then(|b| b.build())
+            .unwrap()
+    }
+}
@@ -0,0 +1,94 @@
+// The daemon on a private session bus, changed over D-Bus and with the command
+// line at the same time: neither may undo the other's changes, and changes of
+// the command line are announced with StateChanged.
+
+mod common;
+
+use common::{wait_for, PrivateBus, Running, Sandbox};
+use std::collections::HashMap;
+use std::process::Stdio;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+use zbus::zvariant::OwnedValue;
+
+const BUS_NAME: &str = "com.example.RustyKb";
+const OBJECT_PATH: &str = "/com/example/RustyKb";
+const INTERFACE: &str = "com.example.RustyKb.Lighting";
+
+type State = HashMap<String, OwnedValue>;
+
+fn text(state: &State, key: &str) -> Option<String> {
+    state.get(key).and_then(|v| String::try_from(v.try_clone().ok()?).ok())
+}
+
+fn number(state: &State, key: &str) -> Option<i32> {
+    state.get(key).and_then(|v| i32::try_from(v.try_clone().ok()?).ok())
+}
+
+fn saved_brightness(sandbox: &Sandbox) -> Option<i64> {
+    sandbox.saved("keyboard", "brightness")?.as_integer()
+}
+
+#[test]
+fn daemon_and_command_line_keep_each_others_changes() {
+    let sandbox = Sandbox::new("dbus");
+    let led = sandbox.add_rgb_led("rgb:kbd_backlight", 25, 50);
+    sandbox.write_config("version = 1\n\n[keyboard]\ncolor = \"#ffffff\"\nbrightness = 25\nmax_brightness = 50\n");
+    let Some(bus) = PrivateBus::start(&sandbox, "session") else { return };
+    let _daemon = Running(
+        sandbox
+            .command(&["daemon"])
+            .env("DBUS_SESSION_BUS_ADDRESS", &bus.address)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap(),
+    );
+
+    let connection = bus.connect();
+    let dbus = zbus::blocking::fdo::DBusProxy::new(&connection).unwrap();
+    assert!(
+        wait_for(Duration::from_secs(10), || dbus
+            .name_has_owner(BUS_NAME.try_into().unwrap())
+            .unwrap_or(false)),
+        "the daemon didn't take {}",
+        BUS_NAME
+    );
+    let lighting = zbus::blocking::Proxy::new(&connection, BUS_NAME, OBJECT_PATH, INTERFACE).unwrap();
+
+    let (tx, signals) = mpsc::channel::<State>();
+    let mut changes = lighting.receive_signal("StateChanged").unwrap();
+    thread::spawn(move || {
+        for message in &mut changes {
+            if let Ok((state,)) = message.body().deserialize::<(State,)>()
+                && tx.send(state).is_err()
+            {
+                break;
+            }
+        }
+    });
+
+    let _: () = lighting.call("SetBrightness", &("keyboard", 20)).unwrap();
+    assert!(wait_for(Duration::from_secs(5), || saved_brightness(&sandbox) == Some(20)));
+
+    let output = sandbox.run(&["set", "--color", "#123456"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    // The daemon picks up the command line's save and announces it.
+    let announced = (0..50).find_map(|_| {
+        let state = signals.recv_timeout(Duration::from_millis(200)).ok()?;
+        (text(&state, "keyboard_color").as_deref() == Some("#123456")).then_some(state)
+    });
+    let announced = announced.expect("no StateChanged with the command line's color");
+    assert_eq!(number(&announced, "keyboard_brightness"), Some(20));
+
+    // A change over D-Bus afterwards keeps the command line's color.
+    let _: () = lighting.call("SetBrightness", &("keyboard", 30)).unwrap();
+    assert!(wait_for(Duration::from_secs(5), || saved_brightness(&sandbox) == Some(30)));
+    assert_eq!(sandbox.saved("keyboard", "color"), Some(toml::Value::from("#123456")));
+    let state: State = lighting.call("GetState", &()).unwrap();
+    assert_eq!(text(&state, "keyboard_color").as_deref(), Some("#123456"));
+    assert_eq!(number(&state, "keyboard_brightness"), Some(30));
+    assert!(wait_for(Duration::from_secs(5), || common::read_attr(&led, "brightness") == "30"));
+}
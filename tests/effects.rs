@@ -0,0 +1,67 @@
+// The reactive effect fed from a recorded event stream instead of a keyboard:
+// the pressed key lights up, the others stay dark, and once the recording has
+// played and faded out the saved colors come back.
+
+mod common;
+
+use common::{read_attr, wait_for, Running, Sandbox};
+use std::fs;
+use std::process::Stdio;
+use std::time::Duration;
+
+const EV_SYN: u16 = 0x00;
+const EV_KEY: u16 = 0x01;
+const KEY_W: u16 = 17;
+
+// A `struct input_event` as `cat /dev/input/eventN` would have recorded it.
+fn event(millis: u64, kind: u16, code: u16, value: i32) -> Vec<u8> {
+    let time = Duration::from_millis(millis);
+    let mut buf = Vec::with_capacity(24);
+    buf.extend_from_slice(&(time.as_secs() as i64).to_ne_bytes());
+    buf.extend_from_slice(&(time.subsec_micros() as i64).to_ne_bytes());
+    buf.extend_from_slice(&kind.to_ne_bytes());
+    buf.extend_from_slice(&code.to_ne_bytes());
+    buf.extend_from_slice(&value.to_ne_bytes());
+    buf
+}
+
+fn is_red(led: &std::path::Path) -> bool {
+    let values: Vec<u32> = read_attr(led, "multi_intensity")
+        .split_whitespace()
+        .filter_map(|v| v.parse().ok())
+        .collect();
+    matches!(values[..], [r, 0, 0] if r > 0)
+}
+
+#[test]
+fn reactive_effect_follows_a_recording() {
+    let sandbox = Sandbox::new("reactive");
+    let w = sandbox.add_rgb_led("rgb:kbd_backlight_66", 50, 50);
+    let q = sandbox.add_rgb_led("rgb:kbd_backlight_65", 50, 50);
+    sandbox.write_config("version = 1\n\n[keyboard]\ncolor = \"#00ff00\"\nbrightness = 50\nmax_brightness = 50\n");
+    let recording = sandbox.root.join("typing.bin");
+    let mut events = Vec::new();
+    for at in [0, 500, 1000] {
+        events.extend(event(at, EV_KEY, KEY_W, 1));
+        events.extend(event(at, EV_SYN, 0, 0));
+        events.extend(event(at + 100, EV_KEY, KEY_W, 0));
+        events.extend(event(at + 100, EV_SYN, 0, 0));
+    }
+    fs::write(&recording, events).unwrap();
+
+    let mut effect = Running(
+        sandbox
+            .command(&["effect", "reactive", "--color", "#ff0000", "--input", recording.to_str().unwrap()])
+            .stdout(Stdio::null())
+            .spawn()
+            .unwrap(),
+    );
+    assert!(wait_for(Duration::from_secs(5), || is_red(&w)), "the pressed key didn't light up");
+    assert_eq!(read_attr(&q, "multi_intensity"), "0 0 0");
+
+    // The recording ends the effect, the saved color comes back.
+    assert!(wait_for(Duration::from_secs(10), || effect.0.try_wait().unwrap().is_some()));
+    assert!(effect.0.wait().unwrap().success());
+    assert_eq!(read_attr(&w, "multi_intensity"), "0 255 0");
+    assert_eq!(read_attr(&q, "multi_intensity"), "0 255 0");
+}
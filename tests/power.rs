@@ -0,0 +1,107 @@
+// The daemon against a stand-in logind on a private bus: the lighting goes off
+// for suspend before the delay lock is released, comes back after resume even
+// when the controller reset itself, and follows the lid.
+
+mod common;
+
+use common::{read_attr, wait_for, PrivateBus, Running, Sandbox};
+use std::io::Read;
+use std::os::unix::net::UnixStream;
+use std::process::Stdio;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use zbus::zvariant::OwnedFd;
+
+const LOGIND: &str = "org.freedesktop.login1";
+const MANAGER_PATH: &str = "/org/freedesktop/login1";
+const MANAGER_INTERFACE: &str = "org.freedesktop.login1.Manager";
+
+// The part of logind's Manager the daemon uses. Every delay lock handed out is
+// one end of a socket pair; the test keeps the other to see when it is closed.
+struct Manager {
+    lid_closed: Arc<AtomicBool>,
+    locks: Arc<Mutex<Vec<UnixStream>>>,
+}
+
+#[zbus::interface(name = "org.freedesktop.login1.Manager")]
+impl Manager {
+    #[zbus(property)]
+    fn lid_closed(&self) -> bool {
+        self.lid_closed.load(Ordering::SeqCst)
+    }
+
+    fn inhibit(&self, what: &str, _who: &str, _why: &str, mode: &str) -> zbus::fdo::Result<OwnedFd> {
+        assert_eq!((what, mode), ("sleep", "delay"));
+        let (ours, theirs) = UnixStream::pair().map_err(|e| zbus::fdo::Error::Failed(e.to_string()))?;
+        self.locks.lock().unwrap().push(ours);
+        Ok(OwnedFd::from(std::os::fd::OwnedFd::from(theirs)))
+    }
+}
+
+fn prepare_for_sleep(connection: &zbus::blocking::Connection, start: bool) {
+    connection
+        .emit_signal(None::<&str>, MANAGER_PATH, MANAGER_INTERFACE, "PrepareForSleep", &(start,))
+        .unwrap();
+}
+
+// Whether the other end of a delay lock was closed.
+fn released(lock: &mut UnixStream) -> bool {
+    lock.set_read_timeout(Some(Duration::from_millis(100))).unwrap();
+    matches!(lock.read(&mut [0u8; 1]), Ok(0))
+}
+
+#[test]
+fn lighting_follows_suspend_and_the_lid() {
+    let sandbox = Sandbox::new("power");
+    let led = sandbox.add_rgb_led("rgb:kbd_backlight", 20, 50);
+    sandbox.write_config("version = 1\n\n[keyboard]\ncolor = \"#ff0000\"\nbrightness = 20\nmax_brightness = 50\n");
+    let Some(session) = PrivateBus::start(&sandbox, "session") else { return };
+    let Some(system) = PrivateBus::start(&sandbox, "system") else { return };
+
+    let lid_closed = Arc::new(AtomicBool::new(false));
+    let locks = Arc::new(Mutex::new(Vec::new()));
+    let manager = Manager { lid_closed: Arc::clone(&lid_closed), locks: Arc::clone(&locks) };
+    let logind = zbus::blocking::connection::Builder::address(system.address.as_str())
+        .unwrap()
+        .name(LOGIND)
+        .unwrap()
+        .serve_at(MANAGER_PATH, manager)
+        .unwrap()
+        .build()
+        .unwrap();
+
+    let _daemon = Running(
+        sandbox
+            .command(&["daemon"])
+            .env("DBUS_SESSION_BUS_ADDRESS", &session.address)
+            .env("RUSTY_KB_LOGIND_BUS", &system.address)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap(),
+    );
+    let lock_count = || locks.lock().unwrap().len();
+    assert!(wait_for(Duration::from_secs(10), || lock_count() == 1), "the daemon took no delay lock");
+
+    // Suspend: off first, then the lock goes.
+    prepare_for_sleep(&logind, true);
+    assert!(wait_for(Duration::from_secs(5), || released(&mut locks.lock().unwrap()[0])));
+    assert_eq!(read_attr(&led, "brightness"), "0");
+
+    // Resume with the controller back at its firmware defaults.
+    std::fs::write(led.join("brightness"), "50\n").unwrap();
+    std::fs::write(led.join("multi_intensity"), "255 255 255\n").unwrap();
+    prepare_for_sleep(&logind, false);
+    assert!(wait_for(Duration::from_secs(10), || read_attr(&led, "multi_intensity") == "255 0 0"
+        && read_attr(&led, "brightness") == "20"));
+    assert!(wait_for(Duration::from_secs(5), || lock_count() == 2), "no new delay lock after resume");
+
+    lid_closed.store(true, Ordering::SeqCst);
+    assert!(wait_for(Duration::from_secs(5), || read_attr(&led, "brightness") == "0"));
+    lid_closed.store(false, Ordering::SeqCst);
+    assert!(wait_for(Duration::from_secs(5), || read_attr(&led, "brightness") == "20"));
+
+    // Neither the reset nor the switching off was taken for an Fn-key change.
+    assert_eq!(sandbox.saved("keyboard", "brightness"), Some(toml::Value::from(20)));
+}